@@ -1,39 +1,63 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use compile::{process_data, Trees, Scope};
+use std::fs;
+use compile::{mapped_path, process_data, scope_trees, segment_paths, Trees, Scope};
+
+// Prints the on-disk size of every structure of a scope, over all its
+// segments, so what each one costs shows up in the bench output. The benches
+// only ever rebuild structures inside the live segments, so all are there.
+fn report_sizes(scope: Scope, name: &str) {
+    let segments = segment_paths(&scope).unwrap_or_default();
+    for trees in scope_trees(&scope) {
+        let size = segments
+            .iter()
+            .filter_map(|segment| fs::metadata(mapped_path(segment, &trees)).ok())
+            .map(|metadata| metadata.len())
+            .sum::<u64>();
+        println!("mapped size {} - {:?}: {} bytes", name, trees, size);
+    }
+}
 
 fn bench_trie_word(c: &mut Criterion) {
     c.bench_function("process_data - Trie + Word", |b| {
-        b.iter(|| process_data(Trees::Trie, Scope::Word))
+        b.iter(|| process_data(Trees::Trie, Scope::Word).expect("Failed to process dataset"))
     });
+    report_sizes(Scope::Word, "Word");
 }
 
 fn bench_trie_line(c: &mut Criterion) {
     c.bench_function("process_data - Trie + Line", |b| {
-        b.iter(|| process_data(Trees::Trie, Scope::Line))
+        b.iter(|| process_data(Trees::Trie, Scope::Line).expect("Failed to process dataset"))
     });
+    report_sizes(Scope::Line, "Line");
 }
 
 fn bench_suffix_word(c: &mut Criterion) {
     c.bench_function("process_data - Suffix + Word", |b| {
-        b.iter(|| process_data(Trees::Suffix, Scope::Word))
+        b.iter(|| process_data(Trees::Suffix, Scope::Word).expect("Failed to process dataset"))
     });
 }
 
 fn bench_suffix_line(c: &mut Criterion) {
     c.bench_function("process_data - Suffix + Line", |b| {
-        b.iter(|| process_data(Trees::Suffix, Scope::Line))
+        b.iter(|| process_data(Trees::Suffix, Scope::Line).expect("Failed to process dataset"))
+    });
+}
+
+fn bench_suffix_array_word(c: &mut Criterion) {
+    c.bench_function("process_data - SuffixArray + Word", |b| {
+        b.iter(|| process_data(Trees::SuffixArray, Scope::Word).expect("Failed to process dataset"))
     });
 }
 
 fn bench_ngram_word(c: &mut Criterion) {
     c.bench_function("process_data - NGram + Word", |b| {
-        b.iter(|| process_data(Trees::NGramIndex, Scope::Word))
+        b.iter(|| process_data(Trees::NGramIndex, Scope::Word).expect("Failed to process dataset"))
     });
 }
 
 fn bench_ngram_line(c: &mut Criterion) {
     c.bench_function("process_data - NGram + Line", |b| {
-        b.iter(|| process_data(Trees::NGramIndex, Scope::Line))
+        b.iter(|| process_data(Trees::NGramIndex, Scope::Line).expect("Failed to process dataset"))
     });
 }
 
@@ -43,6 +67,7 @@ criterion_group!(
     bench_trie_line,
     bench_suffix_word,
     bench_suffix_line,
+    bench_suffix_array_word,
     bench_ngram_word,
     bench_ngram_line
 );
@@ -0,0 +1,30 @@
+use std::{io, path::PathBuf};
+
+use data_structs::error::FormatError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Errors {
+    #[error("could not locate the project root above {0:?}")]
+    ProjectRoot(PathBuf),
+    #[error("dataset not found at {0:?}")]
+    DatasetMissing(PathBuf),
+    #[error("failed to read dataset {path:?}: {source}")]
+    DatasetRead { path: PathBuf, source: io::Error },
+    #[error("no files to index in {0:?}")]
+    NoInputFiles(PathBuf),
+    #[error("no indexed document at or below {0:?}")]
+    NotIndexed(PathBuf),
+    #[error("invalid glob {pattern:?}: {source}")]
+    Glob { pattern: PathBuf, source: globset::Error },
+    #[error("failed to list dataset files in {path:?}: {source}")]
+    Walk { path: PathBuf, source: walkdir::Error },
+    #[error("failed to read index {path:?}: {source}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("failed to write index {path:?}: {source}")]
+    Write { path: PathBuf, source: io::Error },
+    #[error("thread building the {0} panicked")]
+    ThreadPanicked(String),
+    #[error(transparent)]
+    Format(#[from] FormatError),
+}
@@ -0,0 +1,158 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use globset::GlobBuilder;
+use walkdir::WalkDir;
+
+use crate::Errors;
+
+// Characters that make an input that is not a path a glob
+const GLOB_CHARS: [char; 4] = ['*', '?', '[', '{'];
+
+// Turns the inputs given to `compile` into the files to index, in order and
+// each once: a file is taken as is, a directory is walked for every file
+// below it, and anything else with `*`, `?`, `[...]` or `{...}` in it is a
+// glob over paths, so a file whose name has those characters is still read
+// as it is. Directories and globs skip hidden entries and list files by
+// name, so the same inputs always give the same document ids.
+pub fn expand_inputs(inputs: &[PathBuf]) -> Result<Vec<PathBuf>, Errors> {
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    for input in inputs {
+        let found = if input.is_file() {
+            vec![input.clone()]
+        } else if input.is_dir() {
+            walk(input, |_| true)?
+        } else if is_glob(input) {
+            glob(input)?
+        } else {
+            return Err(Errors::DatasetMissing(input.clone()));
+        };
+        if found.is_empty() {
+            return Err(Errors::NoInputFiles(input.clone()));
+        }
+        // The same file can be spelled many ways, e.g. `./a.txt` and `a.txt`
+        let mut first_time = |file: &PathBuf| seen.insert(fs::canonicalize(file).unwrap_or(file.clone()));
+        files.extend(found.into_iter().filter(|file| first_time(file)));
+    }
+    Ok(files)
+}
+
+fn is_glob(input: &Path) -> bool {
+    input.to_string_lossy().contains(GLOB_CHARS)
+}
+
+// Every visible file below `dir` whose path relative to it passes `keep`
+fn walk(dir: &Path, keep: impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>, Errors> {
+    let mut files = Vec::new();
+    let entries = WalkDir::new(dir)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| !entry.file_name().to_string_lossy().starts_with('.'));
+    for entry in entries {
+        let entry = entry.map_err(|source| Errors::Walk {
+            path: dir.to_path_buf(),
+            source,
+        })?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        if keep(entry.path().strip_prefix(dir).unwrap_or(entry.path())) {
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
+
+// Files matching a path glob. The components up to the first one with a
+// glob character in it are where the walk starts; the rest is matched
+// against paths relative to there, `*` and `?` never crossing a `/`, and a
+// whole `**` component standing for any number of directories, none
+// included.
+fn glob(pattern: &Path) -> Result<Vec<PathBuf>, Errors> {
+    let mut base = PathBuf::new();
+    let mut rest = PathBuf::new();
+    for component in pattern.components() {
+        let literal = matches!(component, Component::Prefix(_) | Component::RootDir)
+            || !component.as_os_str().to_string_lossy().contains(GLOB_CHARS);
+        if rest.as_os_str().is_empty() && literal {
+            base.push(component);
+        } else {
+            rest.push(component);
+        }
+    }
+    if base.as_os_str().is_empty() {
+        base.push(".");
+    }
+    if !base.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let glob_error = |source| Errors::Glob {
+        pattern: pattern.to_path_buf(),
+        source,
+    };
+    let matcher = GlobBuilder::new(&rest.to_string_lossy())
+        .literal_separator(true)
+        .build()
+        .map_err(glob_error)?
+        .compile_matcher();
+    walk(&base, |relative| matcher.is_match(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh directory of empty files at `files`, relative to it
+    fn tree(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("compile-inputs-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        dir
+    }
+
+    fn expand(dir: &Path, inputs: &[&str]) -> Result<Vec<String>, Errors> {
+        let inputs: Vec<PathBuf> = inputs.iter().map(|input| dir.join(input)).collect();
+        Ok(expand_inputs(&inputs)?
+            .iter()
+            .map(|file| file.strip_prefix(dir).unwrap().to_string_lossy().into_owned())
+            .collect())
+    }
+
+    #[test]
+    fn walks_directories_by_name_without_hidden_entries() {
+        let dir = tree("walk", &["b.txt", "a/z.txt", "a/b.md", ".hidden", ".git/config", "c/.d.txt"]);
+        assert_eq!(expand(&dir, &["."]).unwrap(), ["a/b.md", "a/z.txt", "b.txt"]);
+        // Each file once, however often it is named
+        assert_eq!(expand(&dir, &["a", "a/z.txt", "./b.txt", "b.txt"]).unwrap(), ["a/b.md", "a/z.txt", "b.txt"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn expands_globs_below_their_literal_start() {
+        let dir = tree("glob", &["a.txt", "b.txt", "c.md", "sub/d.txt", "sub/deep/e.txt", ".f.txt"]);
+        assert_eq!(expand(&dir, &["*.txt"]).unwrap(), ["a.txt", "b.txt"]);
+        assert_eq!(expand(&dir, &["**/*.txt"]).unwrap(), ["a.txt", "b.txt", "sub/d.txt", "sub/deep/e.txt"]);
+        assert_eq!(expand(&dir, &["sub/**/?.txt"]).unwrap(), ["sub/d.txt", "sub/deep/e.txt"]);
+        assert_eq!(expand(&dir, &["[ac].*"]).unwrap(), ["a.txt", "c.md"]);
+        assert_eq!(expand(&dir, &["*.{md,txt}"]).unwrap(), ["a.txt", "b.txt", "c.md"]);
+        assert!(matches!(expand(&dir, &["*.rs"]), Err(Errors::NoInputFiles(_))));
+        assert!(matches!(expand(&dir, &["[a.txt"]), Err(Errors::Glob { .. })));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn takes_existing_paths_as_they_are() {
+        let dir = tree("literal", &["notes [draft].txt", "what?.txt", "n.txt"]);
+        assert_eq!(expand(&dir, &["notes [draft].txt"]).unwrap(), ["notes [draft].txt"]);
+        assert_eq!(expand(&dir, &["what?.txt"]).unwrap(), ["what?.txt"]);
+        assert!(matches!(expand(&dir, &["missing.txt"]), Err(Errors::DatasetMissing(_))));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
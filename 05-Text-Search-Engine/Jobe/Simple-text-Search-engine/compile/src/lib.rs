@@ -1,100 +1,761 @@
-use bincode::config;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{
     fs::{self, File},
     io::Write,
+    thread,
+    time::UNIX_EPOCH,
 };
 use unicode_segmentation::UnicodeSegmentation;
 
+use data_structs::document::{self, Document, DocumentId, Location};
+use data_structs::error::FormatError;
+use data_structs::format;
+use data_structs::mapped;
+use data_structs::mapped::dictionary::MappedDictionary;
+use data_structs::mapped::locations::MappedLocations;
+use data_structs::mapped::ngram::MappedNGram;
+use data_structs::mapped::pool::MappedPool;
+use data_structs::mapped::store::{MappedStore, StoreWriter};
+use data_structs::mapped::suffix_array::MappedSuffixArray;
+use data_structs::pool::{StringPool, TermId, TermLookup};
+use data_structs::segment::{self, Manifest, SegmentInfo, SegmentLocations, SegmentTokens};
+use data_structs::structure::BuildStructure;
 use data_structs::trees;
 use trees::ngram::{NGramIndex, SearchScopeNgram};
 use trees::suffix::SuffixTree;
+use trees::suffix_array::SuffixArray;
 use trees::trie::Trie;
 
-//mod error;
-//use error::Errors;
+mod error;
+mod inputs;
+pub use error::Errors;
+pub use inputs::expand_inputs;
 
+#[derive(Debug, Clone, Copy)]
 pub enum Trees {
     Trie,
     Suffix,
+    SuffixArray,
     NGramIndex,
 }
 
+#[derive(Debug, Clone, Copy)]
 pub enum Scope {
     Word,
     Line,
 }
 
-pub fn process_data(trees: Trees, search_scope: Scope) {
-    let dataset_path = Path::new(env!("CARGO_MANIFEST_DIR"))
-        .parent()
-        .unwrap() // one level up to project root
-        .join("Dataset/words.txt");
-    let dataset = fs::read_to_string(dataset_path).unwrap();
+// Segments smaller than this are merged by `compact` unless told otherwise,
+// measured in what each scope indexes: bytes of words for the word scope,
+// lines for the line scope
+pub const DEFAULT_WORD_MERGE_BELOW: u64 = 256 * 1024;
+pub const DEFAULT_LINE_MERGE_BELOW: u64 = 2_000;
 
+// Knobs for how the indexes are built. An empty gram size list lets the
+// n-gram builder pick its default for the scope; a `top_k` of 0 skips the
+// precomputed completions.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub word_gram_sizes: Vec<usize>,
+    pub line_gram_sizes: Vec<usize>,
+    pub top_k: usize,
+    pub word_merge_below: u64, // word segments with fewer bytes of words are small enough to merge
+    pub line_merge_below: u64, // and line segments with fewer lines
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            word_gram_sizes: Vec::new(),
+            line_gram_sizes: Vec::new(),
+            top_k: 0,
+            word_merge_below: DEFAULT_WORD_MERGE_BELOW,
+            line_merge_below: DEFAULT_LINE_MERGE_BELOW,
+        }
+    }
+}
+
+impl Options {
+    // Whether `compact` merges the segment
+    fn is_small(&self, search_scope: &Scope, segment: &SegmentInfo) -> bool {
+        match search_scope {
+            Scope::Word => segment.bytes < self.word_merge_below,
+            Scope::Line => segment.lines < self.line_merge_below,
+        }
+    }
+}
+
+fn project_root() -> Result<PathBuf, Errors> {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    manifest_dir
+        .parent() // one level up to project root
+        .map(Path::to_path_buf)
+        .ok_or_else(|| Errors::ProjectRoot(manifest_dir.to_path_buf()))
+}
+
+fn outputs_dir() -> Result<PathBuf, Errors> {
+    Ok(project_root()?.join("serialized_outputs"))
+}
+
+pub fn scope_dir(search_scope: &Scope) -> Result<PathBuf, Errors> {
+    let scope_path = match search_scope {
+        Scope::Word => "word_scope",
+        Scope::Line => "line_scope",
+    };
+    Ok(outputs_dir()?.join(scope_path))
+}
+
+fn file_stem(trees: &Trees) -> &'static str {
+    match trees {
+        Trees::Trie => "trie",
+        Trees::Suffix => "suffix",
+        Trees::SuffixArray => "suffix-array",
+        Trees::NGramIndex => "ngram",
+    }
+}
+
+// Where the memory-mapped layout of a structure goes, the only form the
+// runtime reads it in
+pub fn mapped_path(segment: &Path, trees: &Trees) -> PathBuf {
+    segment.join(format!("{}-mapped.idx", file_stem(trees)))
+}
+
+pub fn pool_mapped_path(segment: &Path) -> PathBuf {
+    segment.join("pool-mapped.idx")
+}
+
+// Where each line of a line scope segment came from, for the runtime
+pub fn locations_mapped_path(segment: &Path) -> PathBuf {
+    segment.join("locations-mapped.idx")
+}
+
+// The document table is shared by both scopes
+pub fn documents_path() -> Result<PathBuf, Errors> {
+    Ok(outputs_dir()?.join(document::DOCUMENTS))
+}
+
+// So is the compressed text of the documents
+pub fn store_path() -> Result<PathBuf, Errors> {
+    Ok(outputs_dir()?.join(document::STORE))
+}
+
+pub fn manifest_path(search_scope: &Scope) -> Result<PathBuf, Errors> {
+    Ok(scope_dir(search_scope)?.join(segment::MANIFEST))
+}
+
+// Held by every run that changes a scope's segments, from reading the
+// manifest to writing it back, so a compaction left running in the
+// background and the next `--add` never both rewrite it. It sits next to
+// the scope's directory, which a rebuild may clear out. Dropping the file
+// releases it.
+fn lock_scope(search_scope: &Scope) -> Result<File, Errors> {
+    let path = scope_dir(search_scope)?.with_extension("lock");
+    let lock_error = |source| Errors::Write {
+        path: path.clone(),
+        source,
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(lock_error)?;
+    }
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(lock_error)?;
+    file.lock().map_err(lock_error)?;
+    Ok(file)
+}
+
+// Directories of the live segments of a scope, oldest first
+pub fn segment_paths(search_scope: &Scope) -> Result<Vec<PathBuf>, Errors> {
+    let scope_dir = scope_dir(search_scope)?;
+    Ok(read_manifest(search_scope)?
+        .segments
+        .into_iter()
+        .map(|segment| scope_dir.join(segment.name))
+        .collect())
+}
+
+pub fn process_data(trees: Trees, search_scope: Scope) -> Result<(), Errors> {
+    process_data_with(trees, search_scope, &Options::default())
+}
+
+// Rebuilds one structure in every live segment of a scope from the tokens
+// and pool the segment keeps, leaving the manifest and every other file of
+// the segment as it is. A scope with no segments yet gets a full build.
+pub fn process_data_with(trees: Trees, search_scope: Scope, options: &Options) -> Result<(), Errors> {
+    let lock = lock_scope(&search_scope)?;
+    let segments = segment_paths(&search_scope)?;
+    if segments.is_empty() {
+        drop(lock);
+        return process_scope(search_scope, &scope_trees(&search_scope), options);
+    }
+    for dir in segments {
+        let pool = read_pool(&dir)?;
+        let segment_tokens = read_output::<SegmentTokens>(&dir.join(segment::TOKENS))?;
+        let tokens: Vec<Token> = segment_tokens
+            .iter()
+            .map(|(id, line)| (pool.get(*id).unwrap_or_default(), *id, *line))
+            .collect();
+        build_structure(&dir, &trees, &search_scope, &tokens, &pool, options)?;
+    }
+    Ok(())
+}
+
+// Every structure a scope is built with
+pub fn scope_trees(search_scope: &Scope) -> Vec<Trees> {
+    match search_scope {
+        Scope::Line => vec![Trees::Trie, Trees::Suffix, Trees::NGramIndex],
+        Scope::Word => vec![Trees::Trie, Trees::Suffix, Trees::SuffixArray, Trees::NGramIndex],
+    }
+}
+
+// What a rebuild indexes when it is given nothing else: every file in the
+// project's `Dataset` directory
+pub fn dataset_dir() -> Result<PathBuf, Errors> {
+    Ok(project_root()?.join("Dataset"))
+}
+
+// Rebuilds a scope from the default dataset
+pub fn process_scope(search_scope: Scope, trees: &[Trees], options: &Options) -> Result<(), Errors> {
+    let documents = register_documents(&[dataset_dir()?], true)?;
+    rebuild_scope(search_scope, trees, options, &documents)
+}
+
+// Rebuilds a scope from `documents` and their text, as `register_documents`
+// read it: they become the one segment of the scope and every earlier
+// segment is dropped.
+pub fn rebuild_scope(
+    search_scope: Scope,
+    trees: &[Trees],
+    options: &Options,
+    documents: &[(Document, String)],
+) -> Result<(), Errors> {
+    let _lock = lock_scope(&search_scope)?;
+    // A rebuild starts the scope over, so one written in an older format is
+    // cleared out rather than read
+    let mut manifest = match read_manifest(&search_scope) {
+        Err(Errors::Format(_)) => {
+            let path = scope_dir(&search_scope)?;
+            fs::remove_dir_all(&path).map_err(|source| Errors::Write { path, source })?;
+            Manifest::default()
+        }
+        manifest => manifest?,
+    };
+    let segment = write_text_segment(&mut manifest, &search_scope, trees, options, documents)?;
+    let dropped = std::mem::replace(&mut manifest.segments, vec![segment]);
+    write_manifest(&search_scope, &manifest)?;
+    remove_segments(&search_scope, &dropped)
+}
+
+// Gives every file the inputs (files, directories or globs, see
+// `expand_inputs`) stand for the next free document id, and records it with
+// its metadata in the document table, which a rebuild starts over. Both
+// scopes share the table, so this runs once before either of them is
+// indexed. Every file is read here, once: one that is not text stops the
+// run before any index is touched, and the text that goes into the document
+// store comes back with each new document for both scopes to index, so
+// offsets and locations always describe the same bytes.
+pub fn register_documents(inputs: &[PathBuf], rebuild: bool) -> Result<Vec<(Document, String)>, Errors> {
+    let mut table = if rebuild { Vec::new() } else { read_documents()? };
+    let mut store = StoreWriter::new();
+    if !table.is_empty() {
+        store.extend_from(&read_store()?);
+        if store.len() != table.len() {
+            return Err(FormatError::Layout("document store does not match the document table").into());
+        }
+    }
+
+    let files = expand_inputs(inputs)?;
+    let mut added = Vec::with_capacity(files.len());
+    for file in files {
+        let text = read_dataset(&file)?;
+        store.push(&text);
+        let document = describe(&file, table.len() as DocumentId, &text)?;
+        table.push(document.clone());
+        added.push((document, text));
+    }
+    // The store first, so the table never lists a document it lacks
+    write_atomic(&store_path()?, &store.finish())?;
+    write_atomic(&documents_path()?, &format::encode(&table)?)?;
+    Ok(added)
+}
+
+// The table entry of `file`, read as `text`
+fn describe(file: &Path, id: DocumentId, text: &str) -> Result<Document, Errors> {
+    let modified = fs::metadata(file)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_secs());
+    // Stored absolute, so a result can be opened from anywhere
+    let path = fs::canonicalize(file).map_err(|_| Errors::DatasetMissing(file.to_path_buf()))?;
+    Ok(Document {
+        id,
+        path: path.display().to_string(),
+        bytes: text.len() as u64,
+        lines: text.lines().count() as u64,
+        modified,
+    })
+}
+
+fn read_store() -> Result<MappedStore, Errors> {
+    let path = store_path()?;
+    mapped::map::<MappedStore>(&path)
+        .map_err(|source| Errors::Read { path, source })?
+        .map_err(Errors::from)
+}
+
+// The document table; empty before anything was indexed
+pub fn read_documents() -> Result<Vec<Document>, Errors> {
+    let path = documents_path()?;
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    read_output(&path)
+}
+
+// Indexes more documents as a new segment of the scope, next to the ones
+// already there, which are left untouched
+pub fn add_segment(
+    search_scope: Scope,
+    trees: &[Trees],
+    options: &Options,
+    documents: &[(Document, String)],
+) -> Result<SegmentInfo, Errors> {
+    let _lock = lock_scope(&search_scope)?;
+    let mut manifest = read_manifest(&search_scope)?;
+    let segment = write_text_segment(&mut manifest, &search_scope, trees, options, documents)?;
+    manifest.segments.push(segment.clone());
+    write_manifest(&search_scope, &manifest)?;
+    Ok(segment)
+}
+
+// Merges every segment `options` counts as small into one, if there are at
+// least two. The merged segment is written next to the others and swapped in
+// with the manifest, so searches carry on against the old segments
+// meanwhile; a runtime picks the new one up when it reloads. Returns the new
+// segment, if there was anything to merge.
+pub fn compact(search_scope: Scope, trees: &[Trees], options: &Options) -> Result<Option<SegmentInfo>, Errors> {
+    let _lock = lock_scope(&search_scope)?;
+    let mut manifest = read_manifest(&search_scope)?;
+    let small: Vec<SegmentInfo> = manifest
+        .segments
+        .iter()
+        .filter(|segment| options.is_small(&search_scope, segment))
+        .cloned()
+        .collect();
+    if small.len() < 2 {
+        return Ok(None);
+    }
+
+    let scope_dir = scope_dir(&search_scope)?;
+    let mut pools = Vec::with_capacity(small.len());
+    let mut segment_tokens = Vec::with_capacity(small.len());
+    let mut locations = SegmentLocations::new();
+    for segment in small.iter() {
+        let dir = scope_dir.join(&segment.name);
+        pools.push(read_pool(&dir)?);
+        segment_tokens.push(read_output::<SegmentTokens>(&dir.join(segment::TOKENS))?);
+        // Locations follow the tokens, so they only need stringing together
+        locations.extend(read_output::<SegmentLocations>(&dir.join(segment::LOCATIONS))?);
+    }
+
+    // Replay the tokens in segment order, lines numbered on from where the
+    // previous segment stopped
+    let mut pool = StringPool::new();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut offset = 0;
+    for ((segment, old_pool), old_tokens) in small.iter().zip(pools.iter()).zip(segment_tokens.iter()) {
+        for (id, line) in old_tokens.iter() {
+            let token = old_pool.get(*id).unwrap_or_default();
+            tokens.push((token, pool.intern(token)?, line + offset));
+        }
+        offset += segment.lines;
+    }
+
+    let name = manifest.next_name();
+    let dir = scope_dir.join(&name);
+    write_segment(&dir, &search_scope, trees, &tokens, &locations, &pool, options)?;
+    let merged = SegmentInfo {
+        name,
+        lines: offset,
+        tokens: tokens.len() as u64,
+        bytes: small.iter().map(|segment| segment.bytes).sum(),
+    };
+
+    // The merged segment takes the place of the oldest one it replaces
+    let first = manifest.segments.iter().position(|segment| small.contains(segment)).unwrap_or_default();
+    manifest.segments.retain(|segment| !small.contains(segment));
+    manifest.segments.insert(first, merged.clone());
+    write_manifest(&search_scope, &manifest)?;
+    remove_segments(&search_scope, &small)?;
+    Ok(Some(merged))
+}
+
+// Ids of the indexed documents at or below each of `inputs`, which need not
+// exist any more: a retracted file is taken out by the path it was indexed
+// under
+pub fn find_documents(inputs: &[PathBuf]) -> Result<Vec<DocumentId>, Errors> {
+    let table = read_documents()?;
+    let mut found = Vec::new();
+    for input in inputs {
+        let path = fs::canonicalize(input)
+            .or_else(|_| std::path::absolute(input))
+            .map_err(|_| Errors::DatasetMissing(input.clone()))?;
+        let before = found.len();
+        found.extend(
+            table
+                .iter()
+                .filter(|document| Path::new(&document.path).starts_with(&path))
+                .map(|document| document.id),
+        );
+        if found.len() == before {
+            return Err(Errors::NotIndexed(input.clone()));
+        }
+    }
+    found.sort_unstable();
+    found.dedup();
+    Ok(found)
+}
+
+// Takes `documents` out of a scope. Every segment that indexes any of them
+// is written again from its other tokens, as `compact` writes a merge, and
+// swapped in with the manifest; one left without tokens is dropped. The
+// documents keep their ids and their place in the document table and store,
+// so ids stay positions there. Returns how many tokens were taken out.
+pub fn remove_documents(
+    search_scope: Scope,
+    trees: &[Trees],
+    options: &Options,
+    documents: &[DocumentId],
+) -> Result<u64, Errors> {
+    let _lock = lock_scope(&search_scope)?;
+    let mut manifest = read_manifest(&search_scope)?;
+    let scope_dir = scope_dir(&search_scope)?;
+    let mut removed = 0;
+    let mut replaced = Vec::new();
+    for index in 0..manifest.segments.len() {
+        let old = manifest.segments[index].clone();
+        let dir = scope_dir.join(&old.name);
+        let locations = read_output::<SegmentLocations>(&dir.join(segment::LOCATIONS))?;
+        if !locations.iter().any(|location| documents.contains(&location.document)) {
+            continue;
+        }
+        let old_pool = read_pool(&dir)?;
+        let old_tokens = read_output::<SegmentTokens>(&dir.join(segment::TOKENS))?;
+
+        // Lines keep their numbers, so line frequencies still tell the lines
+        // that are left apart
+        let mut pool = StringPool::new();
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut kept = SegmentLocations::new();
+        for ((id, line), location) in old_tokens.iter().zip(locations.iter()) {
+            if documents.contains(&location.document) {
+                continue;
+            }
+            let token = old_pool.get(*id).unwrap_or_default();
+            tokens.push((token, pool.intern(token)?, *line));
+            kept.push(*location);
+        }
+        removed += old_tokens.len() as u64 - tokens.len() as u64;
+
+        if !tokens.is_empty() {
+            let name = manifest.next_name();
+            write_segment(&scope_dir.join(&name), &search_scope, trees, &tokens, &kept, &pool, options)?;
+            manifest.segments[index] = SegmentInfo {
+                name,
+                lines: old.lines,
+                tokens: tokens.len() as u64,
+                bytes: token_bytes(&tokens),
+            };
+        }
+        replaced.push(old);
+    }
+    if replaced.is_empty() {
+        return Ok(0);
+    }
+
+    // Segments still listed under their old name were left empty
+    manifest.segments.retain(|segment| !replaced.contains(segment));
+    write_manifest(&search_scope, &manifest)?;
+    remove_segments(&search_scope, &replaced)?;
+    Ok(removed)
+}
+
+fn read_dataset(dataset_path: &Path) -> Result<String, Errors> {
+    if !dataset_path.is_file() {
+        return Err(Errors::DatasetMissing(dataset_path.to_path_buf()));
+    }
+    fs::read_to_string(dataset_path).map_err(|source| Errors::DatasetRead {
+        path: dataset_path.to_path_buf(),
+        source,
+    })
+}
+
+// Splits a document into its lines, as `str::lines` does, along with where
+// each one starts
+fn document_lines(document: DocumentId, text: &str) -> impl Iterator<Item = (&str, Location)> {
+    let mut offset = 0;
+    text.split_inclusive('\n').enumerate().map(move |(line, raw)| {
+        let location = Location {
+            document,
+            line: line as u64 + 1,
+            offset,
+        };
+        offset += raw.len() as u64;
+        let text = raw.strip_suffix('\n').unwrap_or(raw);
+        (text.strip_suffix('\r').unwrap_or(text), location)
+    })
+}
+
+// Tokenizes the documents for a scope, interns every token into a fresh
+// string pool and writes the segment
+fn write_text_segment(
+    manifest: &mut Manifest,
+    search_scope: &Scope,
+    trees: &[Trees],
+    options: &Options,
+    documents: &[(Document, String)],
+) -> Result<SegmentInfo, Errors> {
+    // Every token remembers the line it came from, numbered through the whole
+    // segment for line frequencies, and where that line is in its document
+    let lines: Vec<(&str, u64, Location)> = documents
+        .iter()
+        .flat_map(|(document, text)| document_lines(document.id, text))
+        .enumerate()
+        .map(|(line, (text, location))| (text, line as u64, location))
+        .collect();
+    let line_count = lines.len() as u64;
     let (chosen_scope, limit) = match search_scope {
-        Scope::Word => (dataset.unicode_words().collect::<Vec<&str>>(), 255),
-        Scope::Line => (dataset.lines().collect::<Vec<&str>>(), 32768),
+        Scope::Word => (
+            lines
+                .into_iter()
+                .flat_map(|(text, line, location)| text.unicode_words().map(move |word| (word, line, location)))
+                .collect::<Vec<(&str, u64, Location)>>(),
+            255,
+        ),
+        Scope::Line => (lines, 32768),
     };
 
-    let serialized_output = match trees {
+    let mut pool = StringPool::new();
+    let mut locations = SegmentLocations::new();
+    let tokens: Vec<Token> = chosen_scope
+        .into_iter()
+        .filter(|(token, _, _)| token.len() <= limit)
+        .map(|(token, line, location)| {
+            locations.push(location);
+            Ok((token, pool.intern(token)?, line))
+        })
+        .collect::<Result<_, FormatError>>()?;
+
+    let name = manifest.next_name();
+    let dir = scope_dir(search_scope)?.join(&name);
+    write_segment(&dir, search_scope, trees, &tokens, &locations, &pool, options)?;
+    Ok(SegmentInfo {
+        name,
+        lines: line_count,
+        tokens: tokens.len() as u64,
+        bytes: token_bytes(&tokens),
+    })
+}
+
+fn token_bytes(tokens: &[Token]) -> u64 {
+    tokens.iter().map(|(token, _, _)| token.len() as u64).sum()
+}
+
+// Builds each requested structure of a segment (in parallel) from its
+// tokens, and writes them along with the pool, the tokens themselves and
+// their locations. Both scopes keep the locations so a document can be taken
+// out again; only line results map them for the runtime.
+fn write_segment(
+    dir: &Path,
+    search_scope: &Scope,
+    trees: &[Trees],
+    tokens: &[Token],
+    locations: &[Location],
+    pool: &StringPool,
+    options: &Options,
+) -> Result<(), Errors> {
+    thread::scope(|scope| {
+        let handles: Vec<_> = trees
+            .iter()
+            .map(|trees| (trees, scope.spawn(|| build_structure(dir, trees, search_scope, tokens, pool, options))))
+            .collect();
+        handles.into_iter().try_for_each(|(trees, handle)| {
+            handle
+                .join()
+                .map_err(|_| Errors::ThreadPanicked(format!("{:?} index", trees)))?
+        })
+    })?;
+
+    let segment_tokens: SegmentTokens = tokens.iter().map(|(_, id, line)| (*id, *line)).collect();
+    write_atomic(&dir.join(segment::TOKENS), &format::encode(&segment_tokens)?)?;
+    write_atomic(&dir.join(segment::LOCATIONS), &format::encode(&locations)?)?;
+    if matches!(search_scope, Scope::Line) {
+        let ids: Vec<TermId> = tokens.iter().map(|(_, id, _)| *id).collect();
+        write_atomic(&locations_mapped_path(dir), &MappedLocations::write(&ids, locations, pool.len()))?;
+    }
+    write_atomic(&pool_mapped_path(dir), &MappedPool::write(pool))
+}
+
+// A token of the chosen scope, its pool id and the line it sits on
+type Token<'a> = (&'a str, TermId, u64);
+
+fn build_structure(
+    dir: &Path,
+    trees: &Trees,
+    search_scope: &Scope,
+    tokens: &[Token],
+    pool: &dyn TermLookup,
+    options: &Options,
+) -> Result<(), Errors> {
+    let mapped_output = match trees {
         Trees::Trie => {
-            let mut trie = Trie::new();
-            for token in chosen_scope.iter() {
-                if token.len() > limit {
-                    continue;
-                }
-                trie.store(token.to_string());
+            let mut trie = fill(Trie::new(), tokens, pool);
+            if options.top_k > 0 {
+                trie.build_top_k(options.top_k);
             }
-            bincode::encode_to_vec(trie, config::standard()).unwrap()
+            MappedDictionary::write_trie(&trie)?
         }
         Trees::Suffix => {
-            let mut suffix = SuffixTree::new();
-            for token in chosen_scope.iter() {
-                if token.len() > limit {
-                    continue;
-                }
-                suffix.store(token.to_string());
+            let mut suffix = fill(SuffixTree::new(), tokens, pool);
+            if options.top_k > 0 {
+                suffix.build_top_k(options.top_k);
             }
-            bincode::encode_to_vec(suffix, config::standard()).unwrap()
+            MappedDictionary::write_suffix_tree(&suffix)?
         }
+        Trees::SuffixArray => MappedSuffixArray::write(&fill(SuffixArray::new(), tokens, pool)),
         Trees::NGramIndex => {
-            let mut ngram = NGramIndex::new();
-            if let Scope::Line = search_scope {
-                ngram.search_type = SearchScopeNgram::Lines;
-            }
-            for token in chosen_scope.iter() {
-                if token.len() > limit {
-                    continue;
-                }
-                ngram.store(token.to_string());
+            let ngram = match search_scope {
+                Scope::Word => NGramIndex::builder()
+                    .scope(SearchScopeNgram::Words)
+                    .gram_sizes(&options.word_gram_sizes),
+                Scope::Line => NGramIndex::builder()
+                    .scope(SearchScopeNgram::Lines)
+                    .gram_sizes(&options.line_gram_sizes),
             }
-            bincode::encode_to_vec(ngram, config::standard()).unwrap()
+            .build();
+            MappedNGram::write(&fill(ngram, tokens, pool))?
         }
     };
 
-    let scope_path = match search_scope {
-        Scope::Word => "word_scope",
-        Scope::Line => "line_scope",
-    };
+    // A live segment can be rebuilt in place, so a runtime mapping it has
+    // to see either the old file or the new one
+    write_atomic(&mapped_path(dir, trees), &mapped_output)
+}
 
-    let type_path = match trees {
-        Trees::Trie => "trie-serial.bin",
-        Trees::Suffix => "suffix-serial.bin",
-        Trees::NGramIndex => "ngram-serial.bin",
+// Stores every token into an empty structure and lays it out
+fn fill<T: BuildStructure>(mut structure: T, tokens: &[Token], pool: &dyn TermLookup) -> T {
+    for (token, id, line) in tokens.iter() {
+        structure.store(token, *id, *line);
+    }
+    structure.build(pool);
+    structure
+}
+
+fn write_output(output_path: &Path, serialized_output: &[u8]) -> Result<(), Errors> {
+    let write_error = |source| Errors::Write {
+        path: output_path.to_path_buf(),
+        source,
     };
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent).map_err(write_error)?;
+    }
 
-    let output_path = Path::new(env!("CARGO_MANIFEST_DIR"))
-        .parent()
-        .unwrap()
-        .join("serialized_outputs")
-        .join(scope_path)
-        .join(type_path);
+    let mut serialized_file = File::create(output_path).map_err(write_error)?;
+    serialized_file
+        .write_all(serialized_output)
+        .map_err(write_error)?;
+    Ok(())
+}
 
-    if let Some(parent) = output_path.parent() {
-        fs::create_dir_all(parent).unwrap();
+// The pool of a written segment, as the runtime maps it
+fn read_pool(dir: &Path) -> Result<MappedPool, Errors> {
+    let path = pool_mapped_path(dir);
+    mapped::map::<MappedPool>(&path)
+        .map_err(|source| Errors::Read { path, source })?
+        .map_err(Errors::from)
+}
+
+fn read_output<T: bincode::Decode<()>>(path: &Path) -> Result<T, Errors> {
+    let contents = fs::read(path).map_err(|source| Errors::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(format::decode(&contents)?)
+}
+
+// A scope without a manifest yet has no segments
+fn read_manifest(search_scope: &Scope) -> Result<Manifest, Errors> {
+    let path = manifest_path(search_scope)?;
+    if !path.is_file() {
+        return Ok(Manifest::default());
+    }
+    read_output(&path)
+}
+
+fn write_manifest(search_scope: &Scope, manifest: &Manifest) -> Result<(), Errors> {
+    write_atomic(&manifest_path(search_scope)?, &format::encode(manifest)?)
+}
+
+// Writes next to the live file and renames into place, so a reader never
+// sees half of it. Every index file goes through here, which is what lets
+// `map_file` map them safely.
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), Errors> {
+    let staged = path.with_extension("tmp");
+    write_output(&staged, contents)?;
+    fs::rename(&staged, path).map_err(|source| Errors::Write {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+// Deletes segments the manifest no longer lists. A runtime still mapping
+// them keeps reading the unlinked files until it reloads.
+fn remove_segments(search_scope: &Scope, segments: &[SegmentInfo]) -> Result<(), Errors> {
+    let scope_dir = scope_dir(search_scope)?;
+    for segment in segments {
+        let path = scope_dir.join(&segment.name);
+        if path.is_dir() {
+            fs::remove_dir_all(&path).map_err(|source| Errors::Write { path, source })?;
+        }
     }
+    Ok(())
+}
 
-    let mut serialized_file = File::create(output_path).unwrap();
-    serialized_file.write_all(&serialized_output).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_lines_from_one_at_their_byte_offsets() {
+        let text = "first\r\nsecond é\n\nlast";
+        let lines: Vec<(&str, Location)> = document_lines(4, text).collect();
+        let at = |line, offset| Location { document: 4, line, offset };
+        assert_eq!(
+            lines,
+            [("first", at(1, 0)), ("second é", at(2, 7)), ("", at(3, 17)), ("last", at(4, 18))]
+        );
+        for (line, location) in lines {
+            assert!(text[location.offset as usize..].starts_with(line));
+        }
+    }
+
+    #[test]
+    fn describes_documents_with_their_metadata() {
+        let path = std::env::temp_dir().join(format!("compile-describe-{}.txt", std::process::id()));
+        let text = "one\ntwo é\nthree";
+        fs::write(&path, text).unwrap();
+        let document = describe(&path, 3, text).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(document.id, 3);
+        let canonical = fs::canonicalize(std::env::temp_dir()).unwrap().join(path.file_name().unwrap());
+        assert_eq!(Path::new(&document.path), canonical);
+        assert_eq!((document.bytes, document.lines), (16, 3));
+        assert!(document.modified.is_some());
+        assert!(matches!(describe(&path, 4, text), Err(Errors::DatasetMissing(_))));
+    }
 }
@@ -1,23 +1,236 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::{self, Stdio};
+use std::sync::Arc;
 use std::time::Instant;
 use std::thread;
-use compile::{process_data, Trees, Scope};
+use compile::{
+    add_segment, compact, dataset_dir, find_documents, rebuild_scope, register_documents, remove_documents,
+    scope_trees, Errors, Options, Scope, Trees,
+};
+use data_structs::document::{Document, DocumentId};
+
+// What this run does to the index
+#[derive(Debug, Clone)]
+enum Command {
+    Rebuild(Vec<PathBuf>), // the default dataset when empty
+    Add(Vec<PathBuf>),
+    Remove(Vec<PathBuf>),
+    Compact,
+}
+
+// Reads `--word-grams 2,3` / `--line-grams 2` / `--top-k 10` style flags,
+// plus `--add INPUT` (repeatable) for a new segment, `--remove PATH`
+// (repeatable) to take out the documents indexed at or below a path,
+// `--compact` to merge small segments, and `--word-merge-below BYTES` /
+// `--line-merge-below LINES` for what counts as small in each scope. Any
+// other argument is an input to index: a file, a directory or a glob.
+fn parse_options() -> (Options, Command) {
+    let mut options = Options::default();
+    let mut command = Command::Rebuild(Vec::new());
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let target = match arg.as_str() {
+            "--top-k" => {
+                options.top_k = args.next().and_then(|k| k.trim().parse().ok()).unwrap_or_default();
+                continue;
+            }
+            "--word-merge-below" | "--line-merge-below" => {
+                if let Some(size) = args.next().and_then(|n| n.trim().parse().ok()) {
+                    match arg.as_str() {
+                        "--word-merge-below" => options.word_merge_below = size,
+                        _ => options.line_merge_below = size,
+                    }
+                }
+                continue;
+            }
+            "--add" => {
+                let input = PathBuf::from(args.next().unwrap_or_default());
+                match &mut command {
+                    Command::Add(inputs) => inputs.push(input),
+                    Command::Rebuild(inputs) => {
+                        inputs.push(input);
+                        command = Command::Add(std::mem::take(inputs));
+                    }
+                    Command::Remove(_) | Command::Compact => command = Command::Add(vec![input]),
+                }
+                continue;
+            }
+            "--remove" => {
+                let input = PathBuf::from(args.next().unwrap_or_default());
+                match &mut command {
+                    Command::Remove(inputs) => inputs.push(input),
+                    _ => command = Command::Remove(vec![input]),
+                }
+                continue;
+            }
+            "--compact" => {
+                command = Command::Compact;
+                continue;
+            }
+            "--word-grams" => &mut options.word_gram_sizes,
+            "--line-grams" => &mut options.line_gram_sizes,
+            _ if !arg.starts_with("--") => {
+                match &mut command {
+                    Command::Rebuild(inputs) | Command::Add(inputs) | Command::Remove(inputs) => {
+                        inputs.push(PathBuf::from(&arg))
+                    }
+                    Command::Compact => eprintln!("Ignoring input while compacting - {}", arg),
+                }
+                continue;
+            }
+            _ => {
+                eprintln!("Ignoring unknown argument - {}", arg);
+                continue;
+            }
+        };
+        *target = args
+            .next()
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|size| size.trim().parse().ok())
+            .collect();
+    }
+    (options, command)
+}
+
+// Documents this run indexes, registered once for both scopes
+fn register(command: &Command) -> Result<Vec<(Document, String)>, Errors> {
+    match command {
+        Command::Rebuild(inputs) if inputs.is_empty() => register_documents(&[dataset_dir()?], true),
+        Command::Rebuild(inputs) => register_documents(inputs, true),
+        Command::Add(inputs) => register_documents(inputs, false),
+        Command::Remove(_) | Command::Compact => Ok(Vec::new()),
+    }
+}
+
+// The flags that give `options` back to `parse_options`
+fn option_args(options: &Options) -> Vec<String> {
+    let sizes = |sizes: &[usize]| sizes.iter().map(usize::to_string).collect::<Vec<_>>().join(",");
+    let mut args = vec![
+        "--top-k".to_string(),
+        options.top_k.to_string(),
+        "--word-merge-below".to_string(),
+        options.word_merge_below.to_string(),
+        "--line-merge-below".to_string(),
+        options.line_merge_below.to_string(),
+    ];
+    let gram_flags = [("--word-grams", &options.word_gram_sizes), ("--line-grams", &options.line_gram_sizes)];
+    for (flag, gram_sizes) in gram_flags {
+        if !gram_sizes.is_empty() {
+            args.extend([flag.to_string(), sizes(gram_sizes)]);
+        }
+    }
+    args
+}
+
+// Starts `compile --compact` with the same options as a process of its own
+// and leaves it running: an `--add` returns as soon as its segments are
+// searchable, and a runtime picks the merged segment up once the manifest
+// changes. Runs that change the same scope wait for it to finish.
+fn compact_in_background(options: &Options) -> Result<(), std::io::Error> {
+    process::Command::new(env::current_exe()?)
+        .arg("--compact")
+        .args(option_args(options))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+// Documents this run takes out, looked up once for both scopes
+fn retracted(command: &Command) -> Result<Vec<DocumentId>, Errors> {
+    match command {
+        Command::Remove(inputs) => find_documents(inputs),
+        _ => Ok(Vec::new()),
+    }
+}
+
+fn run(
+    command: &Command,
+    documents: &[(Document, String)],
+    retracted: &[DocumentId],
+    scope: Scope,
+    trees: Vec<Trees>,
+    options: Options,
+) -> Result<(), Errors> {
+    match command {
+        Command::Rebuild(_) => rebuild_scope(scope, &trees, &options, documents),
+        Command::Add(_) => {
+            let segment = add_segment(scope, &trees, &options, documents)?;
+            eprintln!("Added {:?} segment {} with {} tokens", scope, segment.name, segment.tokens);
+            Ok(())
+        }
+        Command::Remove(_) => {
+            let removed = remove_documents(scope, &trees, &options, retracted)?;
+            eprintln!("Removed {} {:?} tokens of {} documents", removed, scope, retracted.len());
+            Ok(())
+        }
+        Command::Compact => {
+            match compact(scope, &trees, &options)? {
+                Some(merged) => eprintln!("Merged small {:?} segments into {}", scope, merged.name),
+                None => eprintln!("Nothing to merge in {:?} scope", scope),
+            }
+            Ok(())
+        }
+    }
+}
 
 fn main() {
     let now = Instant::now();
-    
-    let handles: Vec<_> = vec![
-        thread::spawn(|| process_data(Trees::Trie, Scope::Line)),
-        thread::spawn(|| process_data(Trees::Trie, Scope::Word)),
-        thread::spawn(|| process_data(Trees::Suffix, Scope::Line)),
-        thread::spawn(|| process_data(Trees::Suffix, Scope::Word)),
-        thread::spawn(|| process_data(Trees::NGramIndex, Scope::Line)),
-        thread::spawn(|| process_data(Trees::NGramIndex, Scope::Word)),
-    ];
+    let (options, command) = parse_options();
+    let documents = match register(&command) {
+        Ok(documents) => documents,
+        Err(e) => {
+            eprintln!("Failed to register documents - {}", e);
+            std::process::exit(1);
+        }
+    };
+    let retracted = match retracted(&command) {
+        Ok(retracted) => Arc::new(retracted),
+        Err(e) => {
+            eprintln!("Failed to find documents to remove - {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Both scopes index the same text, read once
+    let documents = Arc::new(documents);
+    let handles: Vec<_> = [Scope::Line, Scope::Word]
+        .into_iter()
+        .map(|scope| {
+            let trees = scope_trees(&scope);
+            let options = options.clone();
+            let command = command.clone();
+            let documents = Arc::clone(&documents);
+            let retracted = Arc::clone(&retracted);
+            (scope, thread::spawn(move || run(&command, &documents, &retracted, scope, trees, options)))
+        })
+        .collect();
 
-    for handle in handles {
-        handle.join().expect("Thread panicked");
+    let mut failed = false;
+    for (scope, handle) in handles {
+        let result = handle
+            .join()
+            .unwrap_or_else(|_| Err(Errors::ThreadPanicked(format!("{:?} scope", scope))));
+        if let Err(e) = result {
+            eprintln!("Failed to process document - {}", e);
+            failed = true;
+        }
     }
     
     let time_taken = now.elapsed().as_secs_f32();
     eprintln!("Time taken to process document - {}", time_taken);
+    if failed {
+        std::process::exit(1);
+    }
+
+    // The added segments are searchable already, so merging small ones
+    // does not hold this run up
+    if matches!(command, Command::Add(_)) {
+        match compact_in_background(&options) {
+            Ok(()) => eprintln!("Merging small segments in the background"),
+            Err(e) => eprintln!("Failed to start compaction, run --compact later - {}", e),
+        }
+    }
 }
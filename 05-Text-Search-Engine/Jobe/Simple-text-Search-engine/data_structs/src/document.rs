@@ -0,0 +1,48 @@
+use bincode::{Decode, Encode};
+
+pub type DocumentId = u32;
+
+// File in `serialized_outputs` listing every document the index was built
+// from, in id order
+pub const DOCUMENTS: &str = "documents.bin";
+
+// An input file of the index, as it was when it was indexed
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    pub id: DocumentId,
+    pub path: String,
+    pub bytes: u64,
+    pub lines: u64,
+    pub modified: Option<u64>, // seconds since the Unix epoch, where the platform keeps it
+}
+
+// Where an indexed line sits: its document, its line number counted from 1
+// as editors do, and the byte offset the line starts at
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    pub document: DocumentId,
+    pub line: u64,
+    pub offset: u64,
+}
+
+// File in `serialized_outputs` holding the text of every document,
+// compressed, so context around a hit is read without the original files
+pub const STORE: &str = "documents-store.idx";
+
+// How much text around a hit to fetch from the document store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    Lines { before: usize, after: usize },
+    // The run of non-blank lines the hit sits in
+    Paragraph,
+}
+
+// Whole lines around a hit, the first starting `offset` bytes into the
+// document, and `hit` the index of the line the hit is on. Given the hit's
+// `Location`, the first line is number `location.line - hit`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Context {
+    pub offset: u64,
+    pub lines: Vec<String>,
+    pub hit: usize,
+}
@@ -0,0 +1,32 @@
+use bincode::error::{DecodeError, EncodeError};
+use thiserror::Error;
+
+// A search that simply finds nothing is not an error: it comes back as an
+// empty result list. These are the queries that cannot be run at all.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SearchError {
+    #[error("unsupported character {0:?} in query")]
+    UnsupportedCharacter(char),
+    #[error("query is empty")]
+    EmptyQuery,
+    #[error("invalid pattern: {0}")]
+    InvalidPattern(&'static str),
+    #[error("invalid regex: {0}")]
+    InvalidRegex(String),
+}
+
+#[derive(Debug, Error)]
+pub enum FormatError {
+    #[error("failed to encode index: {0}")]
+    Encode(#[from] EncodeError),
+    #[error("failed to decode index: {0}")]
+    Decode(#[from] DecodeError),
+    #[error("failed to build fst dictionary: {0}")]
+    Fst(#[from] fst::Error),
+    #[error("index was written with format version {found}, expected {expected}; recompile the dataset")]
+    VersionMismatch { expected: u32, found: u32 },
+    #[error("malformed mapped index: {0}")]
+    Layout(&'static str),
+    #[error("string pool cannot hold more than {0} entries")]
+    PoolFull(usize),
+}
@@ -0,0 +1,23 @@
+use bincode::{config, Decode, Encode};
+
+use crate::error::FormatError;
+
+// Bumped whenever the encoded layout of any index structure changes, so a
+// stale `serialized_outputs` directory is rejected instead of mis-decoded.
+pub const FORMAT_VERSION: u32 = 15;
+
+pub fn encode<T: Encode>(index: &T) -> Result<Vec<u8>, FormatError> {
+    Ok(bincode::encode_to_vec((FORMAT_VERSION, index), config::standard())?)
+}
+
+pub fn decode<T: Decode<()>>(bytes: &[u8]) -> Result<T, FormatError> {
+    let (found, header_len): (u32, usize) = bincode::decode_from_slice(bytes, config::standard())?;
+    if found != FORMAT_VERSION {
+        return Err(FormatError::VersionMismatch {
+            expected: FORMAT_VERSION,
+            found,
+        });
+    }
+    let (index, _) = bincode::decode_from_slice(&bytes[header_len..], config::standard())?;
+    Ok(index)
+}
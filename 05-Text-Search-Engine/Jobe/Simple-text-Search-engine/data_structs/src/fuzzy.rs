@@ -0,0 +1,231 @@
+use fst::Automaton;
+
+// Bounds of the edit distance a fuzzy search accepts. Past two edits nearly
+// every short word matches nearly every other one.
+pub const MIN_DISTANCE: u32 = 1;
+pub const MAX_DISTANCE: u32 = 2;
+
+// Levenshtein automaton over the characters of a normalized query: a state is
+// one row of the edit distance table, i.e. the distance from every prefix of
+// the query to the key read so far. Cells are capped one past the maximum, so
+// rows stay small and a row past the maximum everywhere is a dead end.
+//
+// Trees step it one `char` at a time through the `*_row` methods; as an
+// `fst::Automaton` it reads UTF-8 bytes and steps once a character is whole.
+#[derive(Debug, Clone)]
+pub struct Levenshtein {
+    query: Vec<char>,
+    max_distance: u32,
+}
+
+impl Levenshtein {
+    // `query` has to be normalized already; `max_distance` is clamped to
+    // `MIN_DISTANCE..=MAX_DISTANCE`
+    pub fn new(query: &str, max_distance: u32) -> Self {
+        Self {
+            query: query.chars().collect(),
+            max_distance: max_distance.clamp(MIN_DISTANCE, MAX_DISTANCE),
+        }
+    }
+
+    pub fn max_distance(&self) -> u32 {
+        self.max_distance
+    }
+
+    fn cap(&self) -> u32 {
+        self.max_distance + 1
+    }
+
+    // Row for the empty key: deleting every character of the query prefix
+    pub fn first_row(&self) -> Vec<u32> {
+        (0..=self.query.len() as u32).map(|cell| cell.min(self.cap())).collect()
+    }
+
+    // Row after the key grows by `char`
+    pub fn next_row(&self, row: &[u32], char: char) -> Vec<u32> {
+        let mut next = Vec::with_capacity(row.len());
+        next.push((row[0] + 1).min(self.cap()));
+        for index in 1..row.len() {
+            let substitution = row[index - 1] + u32::from(self.query[index - 1] != char);
+            let cell = substitution
+                .min(row[index] + 1)
+                .min(next[index - 1] + 1)
+                .min(self.cap());
+            next.push(cell);
+        }
+        next
+    }
+
+    // Distance between the whole query and the key, if it is close enough
+    pub fn row_distance(&self, row: &[u32]) -> Option<u32> {
+        row.last().copied().filter(|distance| *distance <= self.max_distance)
+    }
+
+    // Whether any longer key can still come close enough
+    pub fn row_can_match(&self, row: &[u32]) -> bool {
+        row.iter().any(|cell| *cell <= self.max_distance)
+    }
+}
+
+// A row plus the bytes of a character that has not been read completely
+#[derive(Debug, Clone)]
+pub struct LevenshteinState {
+    row: Vec<u32>,
+    pending: Vec<u8>,
+}
+
+impl LevenshteinState {
+    // Distance of the key read so far, if it is complete and close enough
+    pub fn distance(&self, automaton: &Levenshtein) -> Option<u32> {
+        if self.pending.is_empty() {
+            automaton.row_distance(&self.row)
+        } else {
+            None
+        }
+    }
+}
+
+impl Automaton for Levenshtein {
+    // `None` once the bytes stop being valid UTF-8
+    type State = Option<LevenshteinState>;
+
+    fn start(&self) -> Self::State {
+        Some(LevenshteinState {
+            row: self.first_row(),
+            pending: Vec::new(),
+        })
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        state
+            .as_ref()
+            .is_some_and(|state| state.distance(self).is_some())
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        state
+            .as_ref()
+            .is_some_and(|state| self.row_can_match(&state.row))
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        let state = state.as_ref()?;
+        let mut pending = state.pending.clone();
+        pending.push(byte);
+        match std::str::from_utf8(&pending) {
+            Ok(text) => Some(LevenshteinState {
+                row: self.next_row(&state.row, text.chars().next()?),
+                pending: Vec::new(),
+            }),
+            // The character goes on in the next byte
+            Err(error) if error.error_len().is_none() => Some(LevenshteinState {
+                row: state.row.clone(),
+                pending,
+            }),
+            Err(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Plain edit distance table, to check the automaton against
+    fn distance(left: &str, right: &str) -> u32 {
+        let right: Vec<char> = right.chars().collect();
+        let mut row: Vec<u32> = (0..=right.len() as u32).collect();
+        for (index, char) in left.chars().enumerate() {
+            let mut next = vec![index as u32 + 1];
+            for column in 1..row.len() {
+                let substitution = row[column - 1] + u32::from(right[column - 1] != char);
+                next.push(substitution.min(row[column] + 1).min(next[column - 1] + 1));
+            }
+            row = next;
+        }
+        row[right.len()]
+    }
+
+    fn by_chars(automaton: &Levenshtein, key: &str) -> Option<u32> {
+        let row = key
+            .chars()
+            .fold(automaton.first_row(), |row, char| automaton.next_row(&row, char));
+        automaton.row_distance(&row)
+    }
+
+    fn by_bytes(automaton: &Levenshtein, key: &[u8]) -> Option<u32> {
+        let state = key
+            .iter()
+            .fold(automaton.start(), |state, byte| automaton.accept(&state, *byte));
+        state.and_then(|state| state.distance(automaton))
+    }
+
+    const WORDS: [&str; 12] = [
+        "", "a", "cat", "cart", "act", "coat", "scat", "dog", "kitten", "sitting", "café", "cafe",
+    ];
+
+    #[test]
+    fn matches_edit_distance_up_to_the_limit() {
+        for max_distance in MIN_DISTANCE..=MAX_DISTANCE {
+            for query in WORDS {
+                let automaton = Levenshtein::new(query, max_distance);
+                for key in WORDS {
+                    let expected = Some(distance(query, key)).filter(|distance| *distance <= max_distance);
+                    assert_eq!(by_chars(&automaton, key), expected, "{query:?} to {key:?}");
+                    assert_eq!(by_bytes(&automaton, key.as_bytes()), expected, "{query:?} to {key:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clamps_the_distance() {
+        assert_eq!(Levenshtein::new("cat", 0).max_distance(), MIN_DISTANCE);
+        assert_eq!(Levenshtein::new("cat", 9).max_distance(), MAX_DISTANCE);
+    }
+
+    #[test]
+    fn empty_query_matches_short_keys() {
+        let automaton = Levenshtein::new("", 1);
+        assert_eq!(by_chars(&automaton, ""), Some(0));
+        assert_eq!(by_chars(&automaton, "a"), Some(1));
+        assert_eq!(by_chars(&automaton, "ab"), None);
+    }
+
+    #[test]
+    fn stops_once_nothing_longer_can_match() {
+        let automaton = Levenshtein::new("cat", 1);
+        let row = "xyz"
+            .chars()
+            .fold(automaton.first_row(), |row, char| automaton.next_row(&row, char));
+        assert!(!automaton.row_can_match(&row));
+        assert!(!automaton.can_match(&Some(LevenshteinState { row, pending: Vec::new() })));
+        assert!(automaton.can_match(&automaton.start()));
+    }
+
+    #[test]
+    fn waits_for_whole_characters() {
+        let automaton = Levenshtein::new("café", 1);
+        let bytes = "café".as_bytes();
+        // Half of the `é` is not a key yet
+        assert_eq!(by_bytes(&automaton, &bytes[..bytes.len() - 1]), None);
+        assert_eq!(by_bytes(&automaton, bytes), Some(0));
+    }
+
+    #[test]
+    fn invalid_utf8_is_a_dead_end() {
+        let automaton = Levenshtein::new("a", 2);
+        let state = automaton.accept(&automaton.start(), 0xff);
+        assert!(state.is_none());
+        assert!(!automaton.is_match(&state));
+        assert!(!automaton.can_match(&state));
+    }
+
+    #[test]
+    fn searches_an_fst() {
+        let set = fst::Set::from_iter(["bat", "cat", "category", "cats", "cut", "dog"]).unwrap();
+        let automaton = Levenshtein::new("cat", 1);
+        let found = fst::IntoStreamer::into_stream(set.search(&automaton)).into_strs().unwrap();
+        assert_eq!(found, ["bat", "cat", "cats", "cut"]);
+    }
+}
@@ -1 +1,12 @@
+pub mod document;
+pub mod error;
+pub mod format;
+pub mod fuzzy;
+pub mod mapped;
+pub mod pool;
+pub mod regex_query;
+pub mod segment;
+pub mod structure;
+pub mod text;
 pub mod trees;
+pub mod wildcard;
@@ -0,0 +1,144 @@
+use std::path::Path;
+
+use crate::error::FormatError;
+
+pub mod file;
+pub mod pool;
+pub mod dictionary;
+pub mod suffix_array;
+pub mod ngram;
+pub mod locations;
+pub mod store;
+
+use file::{Kind, MappedFile};
+
+// Read-only structures that are queried in place over a mapped file instead
+// of being decoded into the heap first
+pub trait Mapped: Sized {
+    const KIND: Kind;
+
+    // Checks the sections the structure needs are present; nothing else is
+    // read up front
+    fn open(file: MappedFile) -> Result<Self, FormatError>;
+
+    fn from_bytes<B>(bytes: B) -> Result<Self, FormatError>
+    where
+        B: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        Self::open(MappedFile::new(bytes, Self::KIND)?)
+    }
+}
+
+// Maps `path` and opens it as `T`; a missing file is reported separately from
+// a malformed one
+pub fn map<T: Mapped>(path: &Path) -> std::io::Result<Result<T, FormatError>> {
+    Ok(T::from_bytes(file::map_file(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapped::dictionary::MappedDictionary;
+    use crate::mapped::ngram::MappedNGram;
+    use crate::mapped::pool::MappedPool;
+    use crate::mapped::suffix_array::MappedSuffixArray;
+    use crate::pool::{StringPool, TermId, TermLookup};
+    use crate::structure::{BuildStructure, SearchStructure};
+    use crate::trees::ngram::{NGramIndex, SearchScopeNgram};
+    use crate::trees::{suffix::SuffixTree, suffix_array::SuffixArray, trie::Trie};
+    use crate::wildcard::Pattern;
+
+    const WORDS: [&str; 10] = ["car", "Cart", "carton", "scar", "cat", "Cat", "dog", "hotdog", "東京", "東京都"];
+    const QUERIES: [&str; 8] = ["c", "car", "CA", "ar", "og", "dog", "東", "zzz"];
+    const PATTERNS: [&str; 4] = ["c?t", "*ar*", "*og", "東*"];
+
+    fn fill<T: BuildStructure>(mut structure: T, pool: &mut StringPool) -> T {
+        for (line, word) in WORDS.iter().enumerate() {
+            structure.store(word, pool.intern(word).unwrap(), line as u64);
+        }
+        structure.build(pool);
+        structure
+    }
+
+    fn sorted(mut ids: Vec<TermId>) -> Vec<TermId> {
+        ids.sort_unstable();
+        ids
+    }
+
+    // Matches shortest first, which they have to come in, then by id
+    fn by_length(pool: &dyn TermLookup, structure: &dyn SearchStructure, query: &str) -> Vec<(usize, TermId)> {
+        let mut matches = Vec::new();
+        structure
+            .search_by_length(pool, query, &mut |extra, id| {
+                matches.push((extra, id));
+                std::ops::ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert!(matches.is_sorted_by_key(|(extra, _)| *extra), "{}", structure.describe());
+        matches.sort_unstable();
+        matches
+    }
+
+    // Every read the runtime makes gives the same answer on both
+    fn assert_same(pool: &dyn TermLookup, serial: &dyn SearchStructure, mapped: &dyn SearchStructure) {
+        let name = serial.describe();
+        for query in QUERIES {
+            assert_eq!(
+                sorted(serial.search(pool, query).unwrap()),
+                sorted(mapped.search(pool, query).unwrap()),
+                "{name} {query}"
+            );
+            let tops = (serial.top_completions(query).unwrap(), mapped.top_completions(query).unwrap());
+            assert_eq!(tops.0, tops.1, "{name} {query}");
+            let fuzzy = (serial.search_fuzzy(query, 1).unwrap(), mapped.search_fuzzy(query, 1).unwrap());
+            assert_eq!(fuzzy.0, fuzzy.1, "{name} {query}");
+            assert_eq!(serial.stats(query), mapped.stats(query), "{name} {query}");
+            assert_eq!(by_length(pool, serial, query), by_length(pool, mapped, query), "{name} {query}");
+        }
+        for pattern in PATTERNS {
+            let pattern = Pattern::parse(pattern).unwrap();
+            assert_eq!(
+                serial.search_wildcard(pool, &pattern).unwrap().map(sorted),
+                mapped.search_wildcard(pool, &pattern).unwrap().map(sorted),
+                "{name}"
+            );
+        }
+
+        assert_eq!(serial.len(), mapped.len(), "{name}");
+        let keys = |structure: &dyn SearchStructure| {
+            let mut keys = Vec::new();
+            structure.for_each_key(&mut |key, ids, stats| keys.push((key.to_string(), sorted(ids.to_vec()), stats)));
+            keys.sort_by(|a, b| a.0.cmp(&b.0));
+            keys
+        };
+        assert_eq!(keys(serial), keys(mapped), "{name}");
+    }
+
+    #[test]
+    fn mapped_layouts_answer_like_the_serial_structures() {
+        let mut pool = StringPool::new();
+        let mut trie = fill(Trie::new(), &mut pool);
+        trie.build_top_k(2);
+        let mut suffix = fill(SuffixTree::new(), &mut pool);
+        suffix.build_top_k(2);
+        let suffix_array = fill(SuffixArray::new(), &mut pool);
+        let ngram = fill(NGramIndex::builder().scope(SearchScopeNgram::Words).gram_size(2).build(), &mut pool);
+
+        let mapped_pool = MappedPool::from_bytes(MappedPool::write(&pool)).unwrap();
+        assert_eq!(mapped_pool.len(), pool.len());
+        for id in 0..pool.len() as TermId {
+            assert_eq!(mapped_pool.get(id), pool.get(id));
+        }
+
+        let mapped_trie = MappedDictionary::from_bytes(MappedDictionary::write_trie(&trie).unwrap()).unwrap();
+        let mapped_suffix =
+            MappedDictionary::from_bytes(MappedDictionary::write_suffix_tree(&suffix).unwrap()).unwrap();
+        let mapped_array = MappedSuffixArray::from_bytes(MappedSuffixArray::write(&suffix_array)).unwrap();
+        let mapped_ngram = MappedNGram::from_bytes(MappedNGram::write(&ngram).unwrap()).unwrap();
+
+        assert_same(&mapped_pool, &trie, &mapped_trie);
+        assert_same(&mapped_pool, &suffix, &mapped_suffix);
+        assert_same(&mapped_pool, &suffix_array, &mapped_array);
+        assert_same(&mapped_pool, &ngram, &mapped_ngram);
+    }
+}
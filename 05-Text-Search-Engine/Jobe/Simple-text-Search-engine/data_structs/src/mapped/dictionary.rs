@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
+use fst::{Map, MapBuilder};
+
+use crate::error::{FormatError, SearchError};
+use crate::pool::{TermId, TermLookup};
+use crate::structure::{KeyStats, SearchStructure};
+use crate::text::{normalize, normalize_query, reversed_graphemes};
+use crate::trees::fst_dict::{self, FstDictionary, FstDictionaryBuilder};
+use crate::trees::suffix::SuffixTree;
+use crate::trees::suffix_array::SEPARATOR;
+use crate::trees::trie::Trie;
+use crate::wildcard::Pattern;
+
+use super::file::{Kind, MappedFile, MappedWriter};
+use super::Mapped;
+
+// After the sections of the dictionary itself
+const META: usize = fst_dict::SECTIONS;
+// Precomputed completions: an fst from node path to slot, where each slot's
+// ids start, and the ids themselves
+const TOPS: usize = META + 1;
+const TOP_STARTS: usize = META + 2;
+const TOP_IDS: usize = META + 3;
+
+// Mapped form of a `Trie` or `SuffixTree`: an `FstDictionary` of its keys,
+// plus the precomputed completions of its nodes. Searching walks the fst in
+// place, so nothing is decoded to open it.
+//
+// Keys of a suffix tree are stored reversed, one grapheme at a time with a
+// separator after each, so a suffix query can only match whole graphemes.
+#[derive(Clone, Debug)]
+pub struct MappedDictionary {
+    dictionary: FstDictionary,
+    reversed: bool,
+    has_top: bool,
+}
+
+fn reversed_key(graphemes: impl Iterator<Item = impl AsRef<str>>) -> String {
+    let mut key = String::new();
+    for grapheme in graphemes {
+        key.push_str(grapheme.as_ref());
+        key.push(SEPARATOR);
+    }
+    key
+}
+
+impl MappedDictionary {
+    pub fn write_trie(trie: &Trie) -> Result<Vec<u8>, FormatError> {
+        let mut keys = FstDictionary::builder();
+        trie.for_each_key(&mut |key, ids, stats| keys.insert(key.to_string(), ids, stats));
+        let mut tops = BTreeMap::new();
+        trie.for_each_top(&mut |path, ids| {
+            tops.insert(path.to_string(), ids.to_vec());
+        });
+        Self::write(keys, tops, false, trie.has_top_k())
+    }
+
+    pub fn write_suffix_tree(suffix_tree: &SuffixTree) -> Result<Vec<u8>, FormatError> {
+        let mut keys = FstDictionary::builder();
+        suffix_tree.for_each_key(&mut |key, ids, stats| {
+            keys.insert(reversed_key(reversed_graphemes(key).into_iter()), ids, stats)
+        });
+        let mut tops = BTreeMap::new();
+        suffix_tree.for_each_top(&mut |path, ids| {
+            tops.insert(reversed_key(path.iter()), ids.to_vec());
+        });
+        Self::write(keys, tops, true, suffix_tree.has_top_k())
+    }
+
+    // The fst wants its paths in byte order, which the BTreeMap gives us
+    fn write(
+        keys: FstDictionaryBuilder,
+        tops: BTreeMap<String, Vec<TermId>>,
+        reversed: bool,
+        has_top: bool,
+    ) -> Result<Vec<u8>, FormatError> {
+        let mut writer = MappedWriter::new(Kind::Dictionary);
+        keys.write(&mut writer)?;
+
+        let mut top_builder = MapBuilder::memory();
+        let mut top_starts = vec![0];
+        let mut top_ids = Vec::new();
+        for (slot, (path, path_ids)) in tops.into_iter().enumerate() {
+            top_builder.insert(path, slot as u64)?;
+            top_ids.extend(path_ids);
+            top_starts.push(top_ids.len() as u32);
+        }
+
+        Ok(writer
+            .u32s(&[u32::from(reversed), u32::from(has_top)])
+            .bytes(&top_builder.into_inner()?)
+            .u32s(&top_starts)
+            .u32s(&top_ids)
+            .finish())
+    }
+
+    // The keys on their own, as a plain dictionary
+    pub fn dictionary(&self) -> &FstDictionary {
+        &self.dictionary
+    }
+
+    // A normalized key in the form it is stored under
+    fn key(&self, normalized: &str) -> String {
+        if self.reversed {
+            reversed_key(reversed_graphemes(normalized).into_iter())
+        } else {
+            normalized.to_string()
+        }
+    }
+
+    // Same semantics as `Trie::search` (or `SuffixTree::search` when built
+    // from one): keys equal to the query itself are not part of the result
+    pub fn search(&self, key: String) -> Result<Vec<TermId>, SearchError> {
+        let mut results = Vec::new();
+        self.search_each(&key, &mut |id| {
+            results.push(id);
+            ControlFlow::Continue(())
+        })?;
+        Ok(results)
+    }
+
+    // Hands every match to `visit`, in stored key order, until it breaks
+    pub fn search_each(
+        &self,
+        key: &str,
+        visit: &mut dyn FnMut(TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        self.dictionary.search_stored(&self.key(&normalize_query(key)?), visit);
+        Ok(())
+    }
+
+    // Same as `Trie::search_by_length`; reversed keys are measured without
+    // their separators
+    pub fn search_by_length(
+        &self,
+        key: &str,
+        visit: &mut dyn FnMut(usize, TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        self.dictionary.search_stored_by_length(&self.key(&normalize_query(key)?), visit);
+        Ok(())
+    }
+
+    // Same as `Trie::top_completions` (or `SuffixTree::top_completions`)
+    pub fn top_completions(&self, key: &str) -> Result<Option<Vec<TermId>>, SearchError> {
+        let path = self.key(&normalize_query(key)?);
+        if !self.has_top {
+            return Ok(None);
+        }
+        let file = self.dictionary.file();
+        // Checked when the file was opened
+        let tops = Map::new(file.section(TOPS)).expect("fst validated on open");
+        let Some(slot) = tops.get(path) else {
+            return Ok(Some(Vec::new()));
+        };
+        let starts = file.u32s(TOP_STARTS);
+        let ids = file.u32s(TOP_IDS);
+        let start = starts.get(slot as usize).unwrap_or_default();
+        let end = starts.get(slot as usize + 1).unwrap_or_default();
+        Ok(Some((start..end).filter_map(|index| ids.get(index as usize)).collect()))
+    }
+
+    // Same as `Trie::search_fuzzy`; reversed keys carry separators the
+    // automaton would count as edits, so a suffix tree has no fuzzy search
+    pub fn search_fuzzy(&self, key: &str, max_distance: u32) -> Result<Option<Vec<(u32, TermId)>>, SearchError> {
+        if self.reversed {
+            normalize_query(key)?;
+            return Ok(None);
+        }
+        self.dictionary.search_fuzzy(key, max_distance).map(Some)
+    }
+
+    // Same as `Trie::search_wildcard` (or `SuffixTree::search_wildcard`)
+    pub fn search_wildcard(&self, pattern: &Pattern) -> Vec<TermId> {
+        if self.reversed {
+            self.dictionary.search_automaton(pattern.reversed().automaton(true))
+        } else {
+            self.dictionary.search_wildcard(pattern)
+        }
+    }
+
+    pub fn term_frequency(&self, key: &str) -> u64 {
+        SearchStructure::stats(self, key).term_frequency
+    }
+
+    pub fn line_frequency(&self, key: &str) -> u64 {
+        SearchStructure::stats(self, key).line_frequency
+    }
+
+    // Number of distinct (normalized) keys stored
+    pub fn vocabulary_size(&self) -> usize {
+        self.dictionary.vocabulary_size()
+    }
+}
+
+impl SearchStructure for MappedDictionary {
+    fn search(&self, _pool: &dyn TermLookup, query: &str) -> Result<Vec<TermId>, SearchError> {
+        MappedDictionary::search(self, query.to_string())
+    }
+
+    fn search_each(
+        &self,
+        _pool: &dyn TermLookup,
+        query: &str,
+        visit: &mut dyn FnMut(TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        MappedDictionary::search_each(self, query, visit)
+    }
+
+    fn search_by_length(
+        &self,
+        _pool: &dyn TermLookup,
+        query: &str,
+        visit: &mut dyn FnMut(usize, TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        MappedDictionary::search_by_length(self, query, visit)
+    }
+
+    fn top_completions(&self, query: &str) -> Result<Option<Vec<TermId>>, SearchError> {
+        MappedDictionary::top_completions(self, query)
+    }
+
+    fn search_fuzzy(&self, query: &str, max_distance: u32) -> Result<Option<Vec<(u32, TermId)>>, SearchError> {
+        MappedDictionary::search_fuzzy(self, query, max_distance)
+    }
+
+    fn search_wildcard(
+        &self,
+        _pool: &dyn TermLookup,
+        pattern: &Pattern,
+    ) -> Result<Option<Vec<TermId>>, SearchError> {
+        Ok(Some(MappedDictionary::search_wildcard(self, pattern)))
+    }
+
+    fn len(&self) -> usize {
+        self.vocabulary_size()
+    }
+
+    // Keys come in the order they are stored in, reversed ones included
+    fn for_each_key(&self, visit: &mut dyn FnMut(&str, &[TermId], KeyStats)) {
+        if !self.reversed {
+            return self.dictionary.for_each_stored(visit);
+        }
+        self.dictionary.for_each_stored(&mut |key, ids, stats| {
+            let key: String = key.split_terminator(SEPARATOR).rev().collect();
+            visit(&key, ids, stats);
+        });
+    }
+
+    fn stats(&self, key: &str) -> KeyStats {
+        self.dictionary.stats_stored(&self.key(&normalize(key)))
+    }
+}
+
+impl Mapped for MappedDictionary {
+    const KIND: Kind = Kind::Dictionary;
+
+    fn open(file: MappedFile) -> Result<Self, FormatError> {
+        let dictionary = FstDictionary::open(file)?;
+        let file = dictionary.file();
+        if file.section_count() != TOP_IDS + 1 {
+            return Err(FormatError::Layout("dictionary sections are missing"));
+        }
+        let paths = Map::new(file.section(TOPS))?.len();
+        if file.u32s(TOP_STARTS).len() != paths + 1 {
+            return Err(FormatError::Layout("dictionary completions disagree"));
+        }
+        let meta = file.u32s(META);
+        Ok(Self {
+            reversed: meta.get(0) == Some(1),
+            has_top: meta.get(1) == Some(1),
+            dictionary,
+        })
+    }
+}
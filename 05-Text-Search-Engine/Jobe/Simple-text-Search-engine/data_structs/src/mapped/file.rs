@@ -0,0 +1,253 @@
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use memmap2::Mmap;
+
+use crate::error::FormatError;
+use crate::format::FORMAT_VERSION;
+
+const MAGIC: &[u8; 4] = b"XSIX";
+// magic, version, kind and section count
+const HEADER_LEN: usize = 16;
+// offset and length of one section
+const ENTRY_LEN: usize = 16;
+
+// What a mapped file holds, so one structure is never opened as another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Pool = 1,
+    Dictionary = 2,
+    SuffixArray = 3,
+    NGram = 4,
+    Locations = 5,
+    Store = 6,
+}
+
+// Lays out a mapped file: a fixed header, a table of sections, then the raw
+// sections themselves, each starting on an 8 byte boundary. Numbers are
+// little endian throughout.
+pub struct MappedWriter {
+    kind: Kind,
+    sections: Vec<Vec<u8>>,
+}
+
+impl MappedWriter {
+    pub fn new(kind: Kind) -> Self {
+        Self {
+            kind,
+            sections: Vec::new(),
+        }
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.sections.push(bytes.to_vec());
+        self
+    }
+
+    pub fn u32s(&mut self, values: &[u32]) -> &mut Self {
+        self.sections
+            .push(values.iter().flat_map(|value| value.to_le_bytes()).collect());
+        self
+    }
+
+    pub fn u64s(&mut self, values: &[u64]) -> &mut Self {
+        self.sections
+            .push(values.iter().flat_map(|value| value.to_le_bytes()).collect());
+        self
+    }
+
+    pub fn finish(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        output.extend_from_slice(MAGIC);
+        output.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        output.extend_from_slice(&(self.kind as u32).to_le_bytes());
+        output.extend_from_slice(&(self.sections.len() as u32).to_le_bytes());
+
+        let mut offset = align(HEADER_LEN + ENTRY_LEN * self.sections.len());
+        for section in self.sections.iter() {
+            output.extend_from_slice(&(offset as u64).to_le_bytes());
+            output.extend_from_slice(&(section.len() as u64).to_le_bytes());
+            offset = align(offset + section.len());
+        }
+        for section in self.sections.iter() {
+            output.resize(align(output.len()), 0);
+            output.extend_from_slice(section);
+        }
+        output
+    }
+}
+
+fn align(offset: usize) -> usize {
+    offset.next_multiple_of(8)
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap_or_default())
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap_or_default())
+}
+
+// Maps a file read-only. Every process mapping the same file shares its pages
+// through the page cache.
+pub fn map_file(path: &Path) -> io::Result<Mmap> {
+    let file = File::open(path)?;
+    // SAFETY: the mapping is only sound while nothing writes the bytes under
+    // it. `compile` never writes an index file in place: every file is staged
+    // next to its final path and moved there with an atomic rename, so a
+    // mapping keeps the old inode, unchanged, until it is dropped.
+    unsafe { Mmap::map(&file) }
+}
+
+// A validated view over the sections of a mapped file. Only the header and
+// section table are read when it is opened; everything else is read in place
+// by the structure on top of it. Cloning shares the same mapping.
+#[derive(Clone)]
+pub struct MappedFile {
+    bytes: Arc<dyn AsRef<[u8]> + Send + Sync>,
+    sections: Vec<Range<usize>>,
+}
+
+impl std::fmt::Debug for MappedFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MappedFile")
+            .field("len", &self.data().len())
+            .field("sections", &self.sections)
+            .finish()
+    }
+}
+
+impl MappedFile {
+    pub fn new<B>(bytes: B, kind: Kind) -> Result<Self, FormatError>
+    where
+        B: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        let data = bytes.as_ref();
+        if data.len() < HEADER_LEN || &data[..4] != MAGIC {
+            return Err(FormatError::Layout("not a mapped index file"));
+        }
+        let found = read_u32(data, 4);
+        if found != FORMAT_VERSION {
+            return Err(FormatError::VersionMismatch {
+                expected: FORMAT_VERSION,
+                found,
+            });
+        }
+        if read_u32(data, 8) != kind as u32 {
+            return Err(FormatError::Layout("file holds a different structure"));
+        }
+
+        let count = read_u32(data, 12) as usize;
+        if data.len() < HEADER_LEN + ENTRY_LEN * count {
+            return Err(FormatError::Layout("section table is truncated"));
+        }
+        let mut sections = Vec::with_capacity(count);
+        for index in 0..count {
+            let entry = HEADER_LEN + ENTRY_LEN * index;
+            let start = read_u64(data, entry) as usize;
+            let end = start.checked_add(read_u64(data, entry + 8) as usize);
+            match end {
+                Some(end) if end <= data.len() => sections.push(start..end),
+                _ => return Err(FormatError::Layout("section runs past the end of the file")),
+            }
+        }
+
+        Ok(Self {
+            bytes: Arc::new(bytes),
+            sections,
+        })
+    }
+
+    fn data(&self) -> &[u8] {
+        (*self.bytes).as_ref()
+    }
+
+    pub fn section_count(&self) -> usize {
+        self.sections.len()
+    }
+
+    pub fn section(&self, index: usize) -> &[u8] {
+        &self.data()[self.sections[index].clone()]
+    }
+
+    pub fn u32s(&self, index: usize) -> U32s<'_> {
+        U32s(self.section(index))
+    }
+
+    pub fn u64s(&self, index: usize) -> U64s<'_> {
+        U64s(self.section(index))
+    }
+}
+
+// A little endian u32 array read straight out of a section
+#[derive(Clone, Copy)]
+pub struct U32s<'a>(&'a [u8]);
+
+impl U32s<'_> {
+    pub fn len(&self) -> usize {
+        self.0.len() / 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<u32> {
+        (index < self.len()).then(|| read_u32(self.0, index * 4))
+    }
+
+    // Same contract as `slice::partition_point`
+    pub fn partition_point(&self, mut pred: impl FnMut(u32) -> bool) -> usize {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if pred(read_u32(self.0, middle * 4)) {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap_or_default()))
+    }
+}
+
+// A little endian u64 array read straight out of a section
+#[derive(Clone, Copy)]
+pub struct U64s<'a>(&'a [u8]);
+
+impl U64s<'_> {
+    pub fn len(&self) -> usize {
+        self.0.len() / 8
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<u64> {
+        (index < self.len()).then(|| read_u64(self.0, index * 8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_counts_past_u32() {
+        let counts = [0, u64::from(u32::MAX) + 1, u64::MAX];
+        let file = MappedFile::new(MappedWriter::new(Kind::Dictionary).u64s(&counts).finish(), Kind::Dictionary).unwrap();
+        let read = file.u64s(0);
+        assert_eq!(read.len(), 3);
+        assert_eq!([read.get(0), read.get(1), read.get(2), read.get(3)], [Some(0), Some(1 << 32), Some(u64::MAX), None]);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::document::Location;
+use crate::error::FormatError;
+use crate::pool::TermId;
+
+use super::file::{Kind, MappedFile, MappedWriter};
+use super::Mapped;
+
+const STARTS: usize = 0;
+const DOCUMENTS: usize = 1;
+const LINES: usize = 2;
+const OFFSETS: usize = 3;
+
+// The locations of every line of a segment, grouped by the line's pool id:
+// `STARTS` holds where each id's run begins in the three columns (plus one
+// past the end), so the locations of a matched line are a single range.
+#[derive(Clone, Debug)]
+pub struct MappedLocations {
+    file: MappedFile,
+}
+
+impl MappedLocations {
+    // `ids[index]` is the pool entry found at `locations[index]`, and
+    // `entries` the number of entries in the pool
+    pub fn write(ids: &[TermId], locations: &[Location], entries: usize) -> Vec<u8> {
+        let mut sorted: Vec<(TermId, Location)> = ids
+            .iter()
+            .copied()
+            .zip(locations.iter().copied())
+            .filter(|(id, _)| (*id as usize) < entries)
+            .collect();
+        sorted.sort_unstable();
+
+        // Counts per id, then running totals
+        let mut starts = vec![0u32; entries + 1];
+        for (id, _) in sorted.iter() {
+            starts[*id as usize + 1] += 1;
+        }
+        for id in 1..starts.len() {
+            starts[id] += starts[id - 1];
+        }
+
+        let documents: Vec<u32> = sorted.iter().map(|(_, location)| location.document).collect();
+        let lines: Vec<u64> = sorted.iter().map(|(_, location)| location.line).collect();
+        let offsets: Vec<u64> = sorted.iter().map(|(_, location)| location.offset).collect();
+
+        let mut writer = MappedWriter::new(Kind::Locations);
+        writer.u32s(&starts).u32s(&documents).u64s(&lines).u64s(&offsets);
+        writer.finish()
+    }
+
+    // Every place the line `id` occurs, in document and line order
+    pub fn get(&self, id: TermId) -> Vec<Location> {
+        let starts = self.file.u32s(STARTS);
+        let (Some(start), Some(end)) = (starts.get(id as usize), starts.get(id as usize + 1)) else {
+            return Vec::new();
+        };
+        let (documents, lines, offsets) = (
+            self.file.u32s(DOCUMENTS),
+            self.file.u64s(LINES),
+            self.file.u64s(OFFSETS),
+        );
+        (start as usize..end as usize)
+            .filter_map(|index| {
+                Some(Location {
+                    document: documents.get(index)?,
+                    line: lines.get(index)?,
+                    offset: offsets.get(index)?,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Mapped for MappedLocations {
+    const KIND: Kind = Kind::Locations;
+
+    fn open(file: MappedFile) -> Result<Self, FormatError> {
+        if file.section_count() != 4 || file.u32s(STARTS).is_empty() {
+            return Err(FormatError::Layout("location sections are missing"));
+        }
+        Ok(Self { file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(document: u32, line: u64, offset: u64) -> Location {
+        Location { document, line, offset }
+    }
+
+    #[test]
+    fn groups_locations_by_line() {
+        // Line 1 is on two lines of document 0 and once in document 1; the
+        // offset is past what 32 bits hold
+        let ids = [1, 0, 1, 1, 3];
+        let locations = [at(1, 7, 5_000_000_000), at(0, 1, 0), at(0, 4, 30), at(0, 2, 12), at(2, 1, 0)];
+        let mapped = MappedLocations::from_bytes(MappedLocations::write(&ids, &locations, 3)).unwrap();
+
+        assert_eq!(mapped.get(0), [at(0, 1, 0)]);
+        assert_eq!(mapped.get(1), [at(0, 2, 12), at(0, 4, 30), at(1, 7, 5_000_000_000)]);
+        // An entry no line was kept for, and ids past the pool
+        assert!(mapped.get(2).is_empty());
+        assert!(mapped.get(3).is_empty());
+        assert!(mapped.get(u32::MAX).is_empty());
+    }
+
+    #[test]
+    fn an_empty_segment_has_no_locations() {
+        let mapped = MappedLocations::from_bytes(MappedLocations::write(&[], &[], 0)).unwrap();
+        assert!(mapped.get(0).is_empty());
+    }
+}
@@ -0,0 +1,240 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use fst::{Map, MapBuilder, Streamer};
+
+use crate::error::{FormatError, SearchError};
+use crate::pool::{TermId, TermLookup};
+use crate::regex_query::RegexQuery;
+use crate::structure::{KeyStats, SearchStructure};
+use crate::trees::ngram::{GramLookup, Grams, NGramIndex, SearchScopeNgram};
+use crate::trees::suffix_array::SEPARATOR;
+use crate::wildcard::{Pattern, WordPattern};
+
+use super::file::{Kind, MappedFile, MappedWriter};
+use super::Mapped;
+
+const META: usize = 0;
+// Entries too short to have a gram, as `NGramIndex` keeps them
+const SHORT: usize = 1;
+// Every table takes three sections after those: the fst from gram to slot,
+// where each slot's postings start, and the postings themselves
+const FIRST_TABLE: usize = 2;
+const TABLE_SECTIONS: usize = 3;
+
+// `NGramIndex` with one fst per gram size. A gram is stored as its units,
+// each followed by a separator, so grams of words cannot run into each other.
+#[derive(Clone, Debug)]
+pub struct MappedNGram {
+    file: MappedFile,
+    search_type: SearchScopeNgram,
+    tables: Vec<MappedGramTable>,
+}
+
+// One gram size of a `MappedNGram`, its sections starting at `first`
+#[derive(Clone, Debug)]
+pub(crate) struct MappedGramTable {
+    file: MappedFile,
+    first: usize,
+    gram_size: usize,
+}
+
+fn gram_key(units: &[impl AsRef<str>]) -> String {
+    let mut key = String::new();
+    for unit in units {
+        key.push_str(unit.as_ref());
+        key.push(SEPARATOR);
+    }
+    key
+}
+
+impl MappedGramTable {
+    fn map(&self) -> Map<&[u8]> {
+        // Checked when the file was opened
+        Map::new(self.file.section(self.first)).expect("fst validated on open")
+    }
+
+    fn slot_postings(&self, slot: u64) -> Vec<TermId> {
+        let starts = self.file.u32s(self.first + 1);
+        let postings = self.file.u32s(self.first + 2);
+        let start = starts.get(slot as usize).unwrap_or_default();
+        let end = starts.get(slot as usize + 1).unwrap_or_default();
+        (start..end)
+            .filter_map(|index| postings.get(index as usize))
+            .collect()
+    }
+}
+
+impl GramLookup for MappedGramTable {
+    fn gram_size(&self) -> usize {
+        self.gram_size
+    }
+
+    fn postings(&self, gram: &[String]) -> Option<Cow<'_, [TermId]>> {
+        let slot = self.map().get(gram_key(gram))?;
+        Some(Cow::Owned(self.slot_postings(slot)))
+    }
+
+    fn for_each_gram(&self, visit: &mut dyn FnMut(&[&str], &[TermId])) {
+        let map = self.map();
+        let mut stream = map.stream();
+        while let Some((gram, slot)) = stream.next() {
+            let gram: Vec<&str> = std::str::from_utf8(gram)
+                .unwrap_or_default()
+                .split_terminator(SEPARATOR)
+                .collect();
+            visit(&gram, &self.slot_postings(slot));
+        }
+    }
+}
+
+impl MappedNGram {
+    pub fn write(ngram: &NGramIndex) -> Result<Vec<u8>, FormatError> {
+        let mut meta = vec![match ngram.search_type {
+            SearchScopeNgram::Words => 0,
+            SearchScopeNgram::Lines => 1,
+        }];
+        meta.extend(ngram.tables.iter().map(|table| table.gram_size as u32));
+
+        let mut writer = MappedWriter::new(Kind::NGram);
+        writer.u32s(&meta).u32s(&ngram.short);
+        for table in ngram.tables.iter() {
+            // The fst wants its keys in byte order
+            let grams: BTreeMap<String, &Vec<TermId>> = table
+                .grams
+                .iter()
+                .map(|(gram, postings)| (gram_key(gram), postings))
+                .collect();
+
+            let mut builder = MapBuilder::memory();
+            let mut starts = vec![0];
+            let mut postings = Vec::new();
+            for (slot, (gram, list)) in grams.into_iter().enumerate() {
+                builder.insert(gram, slot as u64)?;
+                postings.extend_from_slice(list);
+                starts.push(postings.len() as u32);
+            }
+            writer
+                .bytes(&builder.into_inner()?)
+                .u32s(&starts)
+                .u32s(&postings);
+        }
+        Ok(writer.finish())
+    }
+
+    pub fn gram_sizes(&self) -> Vec<usize> {
+        self.tables.iter().map(|table| table.gram_size).collect()
+    }
+
+    fn grams(&self) -> Grams<'_, MappedGramTable> {
+        Grams {
+            tables: &self.tables,
+            search_type: &self.search_type,
+            short: Cow::Owned(self.file.u32s(SHORT).iter().collect()),
+        }
+    }
+
+    // Same algorithm and semantics as `NGramIndex::search_wildcard`
+    pub fn search_wildcard(&self, pool: &(impl TermLookup + ?Sized), pattern: &Pattern) -> Option<Vec<TermId>> {
+        self.grams().search_wildcard(pool, pattern)
+    }
+
+    // Same algorithm and semantics as `NGramIndex::search_word_wildcard`
+    pub fn search_word_wildcard(&self, pool: &(impl TermLookup + ?Sized), pattern: &WordPattern) -> Option<Vec<TermId>> {
+        self.grams().search_word_wildcard(pool, pattern)
+    }
+
+    // Same algorithm and semantics as `NGramIndex::search_regex`
+    pub fn search_regex(&self, pool: &(impl TermLookup + ?Sized), query: &RegexQuery) -> Option<Vec<TermId>> {
+        self.grams().search_regex(pool, query)
+    }
+
+    // Same algorithm and semantics as `NGramIndex::search`
+    pub fn search(&self, pool: &(impl TermLookup + ?Sized), key: String) -> Result<Vec<TermId>, SearchError> {
+        self.grams().search(pool, &key)
+    }
+}
+
+impl SearchStructure for MappedNGram {
+    fn search(&self, pool: &dyn TermLookup, query: &str) -> Result<Vec<TermId>, SearchError> {
+        MappedNGram::search(self, pool, query.to_string())
+    }
+
+    fn search_wildcard(
+        &self,
+        pool: &dyn TermLookup,
+        pattern: &Pattern,
+    ) -> Result<Option<Vec<TermId>>, SearchError> {
+        Ok(MappedNGram::search_wildcard(self, pool, pattern))
+    }
+
+    fn search_word_wildcard(
+        &self,
+        pool: &dyn TermLookup,
+        pattern: &WordPattern,
+    ) -> Result<Option<Vec<TermId>>, SearchError> {
+        Ok(MappedNGram::search_word_wildcard(self, pool, pattern))
+    }
+
+    fn search_regex(&self, pool: &dyn TermLookup, query: &RegexQuery) -> Result<Option<Vec<TermId>>, SearchError> {
+        Ok(MappedNGram::search_regex(self, pool, query))
+    }
+
+    // Grams of every size together
+    fn len(&self) -> usize {
+        self.tables.iter().map(|table| table.map().len()).sum()
+    }
+
+    // Grams come smallest size first, in byte order within a size
+    fn for_each_key(&self, visit: &mut dyn FnMut(&str, &[TermId], KeyStats)) {
+        self.grams().for_each_key(visit);
+    }
+
+    // Grams are not counted
+    fn stats(&self, _key: &str) -> KeyStats {
+        KeyStats::default()
+    }
+
+    fn describe(&self) -> String {
+        format!("MappedNGram with gram sizes {:?}", self.gram_sizes())
+    }
+}
+
+impl Mapped for MappedNGram {
+    const KIND: Kind = Kind::NGram;
+
+    fn open(file: MappedFile) -> Result<Self, FormatError> {
+        if file.section_count() == 0 {
+            return Err(FormatError::Layout("n-gram meta section is missing"));
+        }
+        let meta: Vec<u32> = file.u32s(META).iter().collect();
+        let Some((scope, gram_sizes)) = meta.split_first() else {
+            return Err(FormatError::Layout("n-gram meta section is missing"));
+        };
+        if file.section_count() != FIRST_TABLE + gram_sizes.len() * TABLE_SECTIONS {
+            return Err(FormatError::Layout("n-gram tables are missing"));
+        }
+        for table in 0..gram_sizes.len() {
+            let keys = Map::new(file.section(FIRST_TABLE + table * TABLE_SECTIONS))?.len();
+            if file.u32s(FIRST_TABLE + 1 + table * TABLE_SECTIONS).len() != keys + 1 {
+                return Err(FormatError::Layout("n-gram keys and postings disagree"));
+            }
+        }
+
+        Ok(Self {
+            search_type: match scope {
+                0 => SearchScopeNgram::Words,
+                _ => SearchScopeNgram::Lines,
+            },
+            tables: gram_sizes
+                .iter()
+                .enumerate()
+                .map(|(table, size)| MappedGramTable {
+                    file: file.clone(),
+                    first: FIRST_TABLE + table * TABLE_SECTIONS,
+                    gram_size: *size as usize,
+                })
+                .collect(),
+            file,
+        })
+    }
+}
@@ -0,0 +1,60 @@
+use crate::error::FormatError;
+use crate::pool::{StringPool, TermId, TermLookup};
+
+use super::file::{Kind, MappedFile, MappedWriter};
+use super::Mapped;
+
+const OFFSETS: usize = 0;
+const BYTES: usize = 1;
+
+// `StringPool` laid out as one byte blob plus the offset every string starts
+// at, so resolving an id is two reads into the mapping
+#[derive(Clone, Debug)]
+pub struct MappedPool {
+    file: MappedFile,
+}
+
+impl MappedPool {
+    pub fn write(pool: &StringPool) -> Vec<u8> {
+        let mut offsets = Vec::with_capacity(pool.len() + 1);
+        let mut bytes = Vec::new();
+        for id in 0..pool.len() as TermId {
+            offsets.push(bytes.len() as u32);
+            bytes.extend_from_slice(pool.get(id).unwrap_or_default().as_bytes());
+        }
+        offsets.push(bytes.len() as u32);
+
+        MappedWriter::new(Kind::Pool)
+            .u32s(&offsets)
+            .bytes(&bytes)
+            .finish()
+    }
+
+    pub fn len(&self) -> usize {
+        self.file.u32s(OFFSETS).len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl TermLookup for MappedPool {
+    fn get(&self, id: TermId) -> Option<&str> {
+        let offsets = self.file.u32s(OFFSETS);
+        let start = offsets.get(id as usize)? as usize;
+        let end = offsets.get(id as usize + 1)? as usize;
+        std::str::from_utf8(self.file.section(BYTES).get(start..end)?).ok()
+    }
+}
+
+impl Mapped for MappedPool {
+    const KIND: Kind = Kind::Pool;
+
+    fn open(file: MappedFile) -> Result<Self, FormatError> {
+        if file.section_count() != 2 || file.u32s(OFFSETS).is_empty() {
+            return Err(FormatError::Layout("pool sections are missing"));
+        }
+        Ok(Self { file })
+    }
+}
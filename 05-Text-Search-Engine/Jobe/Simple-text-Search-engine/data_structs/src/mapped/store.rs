@@ -0,0 +1,383 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use lz4_flex::block::{compress, decompress};
+
+use crate::document::{Context, DocumentId, Window};
+use crate::error::FormatError;
+
+use super::file::{Kind, MappedFile, MappedWriter};
+use super::Mapped;
+
+// Per document, its first block, plus one past the last block
+const DOCUMENTS: usize = 0;
+// Per block, the byte offset it starts at in its document
+const STARTS: usize = 1;
+// Per block, its length once decompressed
+const LENGTHS: usize = 2;
+// Per block, where its compressed bytes start in `DATA`, plus the end
+const BLOCKS: usize = 3;
+const DATA: usize = 4;
+
+// Text is cut into blocks of about this many bytes, each compressed on its
+// own as an LZ4 block, so a lookup only decompresses the blocks around a hit
+pub const BLOCK_SIZE: usize = 64 * 1024;
+
+// Lays out a document store. Documents are pushed in id order.
+pub struct StoreWriter {
+    documents: Vec<u32>,
+    starts: Vec<u64>,
+    lengths: Vec<u64>,
+    blocks: Vec<u64>,
+    data: Vec<u8>,
+}
+
+impl Default for StoreWriter {
+    fn default() -> Self {
+        Self {
+            documents: vec![0],
+            starts: Vec::new(),
+            lengths: Vec::new(),
+            blocks: vec![0],
+            data: Vec::new(),
+        }
+    }
+}
+
+impl StoreWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Number of documents pushed so far
+    pub fn len(&self) -> usize {
+        self.documents.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Takes over every document of `store` under the same ids, copying the
+    // compressed blocks as they are
+    pub fn extend_from(&mut self, store: &MappedStore) {
+        let (starts, lengths) = (store.file.u64s(STARTS), store.file.u64s(LENGTHS));
+        for document in 0..store.len() {
+            for block in store.blocks(document as DocumentId).unwrap_or_default() {
+                self.push_block(
+                    starts.get(block).unwrap_or_default(),
+                    lengths.get(block).unwrap_or_default(),
+                    store.compressed(block),
+                );
+            }
+            self.documents.push(self.starts.len() as u32);
+        }
+    }
+
+    // Adds the next document. Blocks end after a newline wherever there is
+    // one, so every block holds whole lines.
+    pub fn push(&mut self, text: &str) {
+        let bytes = text.as_bytes();
+        let mut start = 0;
+        while start < bytes.len() {
+            let limit = (start + BLOCK_SIZE).min(bytes.len());
+            let end = if limit == bytes.len() {
+                limit
+            } else {
+                match bytes[start..limit].iter().rposition(|byte| *byte == b'\n') {
+                    Some(newline) => start + newline + 1,
+                    // A line longer than a block gets a block of its own
+                    None => bytes[limit..]
+                        .iter()
+                        .position(|byte| *byte == b'\n')
+                        .map_or(bytes.len(), |newline| limit + newline + 1),
+                }
+            };
+            self.push_block(start as u64, (end - start) as u64, &compress(&bytes[start..end]));
+            start = end;
+        }
+        self.documents.push(self.starts.len() as u32);
+    }
+
+    fn push_block(&mut self, start: u64, length: u64, compressed: &[u8]) {
+        self.starts.push(start);
+        self.lengths.push(length);
+        self.data.extend_from_slice(compressed);
+        self.blocks.push(self.data.len() as u64);
+    }
+
+    pub fn finish(&self) -> Vec<u8> {
+        let mut writer = MappedWriter::new(Kind::Store);
+        writer
+            .u32s(&self.documents)
+            .u64s(&self.starts)
+            .u64s(&self.lengths)
+            .u64s(&self.blocks)
+            .bytes(&self.data);
+        writer.finish()
+    }
+}
+
+// The text of every document, compressed block by block
+#[derive(Clone, Debug)]
+pub struct MappedStore {
+    file: MappedFile,
+}
+
+// A line of a document and the byte offset it starts at
+type Line = (u64, String);
+
+fn is_blank(line: &Line) -> bool {
+    line.1.trim().is_empty()
+}
+
+impl MappedStore {
+    pub fn len(&self) -> usize {
+        self.file.u32s(DOCUMENTS).len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn blocks(&self, document: DocumentId) -> Option<Range<usize>> {
+        let documents = self.file.u32s(DOCUMENTS);
+        let start = documents.get(document as usize)?;
+        let end = documents.get(document as usize + 1)?;
+        Some(start as usize..end as usize)
+    }
+
+    fn compressed(&self, block: usize) -> &[u8] {
+        let blocks = self.file.u64s(BLOCKS);
+        match (blocks.get(block), blocks.get(block + 1)) {
+            (Some(start), Some(end)) => self.file.section(DATA).get(start as usize..end as usize).unwrap_or_default(),
+            _ => &[],
+        }
+    }
+
+    fn block_text(&self, block: usize) -> Result<String, FormatError> {
+        let length = self.file.u64s(LENGTHS).get(block).unwrap_or_default() as usize;
+        let bytes = decompress(self.compressed(block), length)
+            .ok()
+            .filter(|bytes| bytes.len() == length)
+            .ok_or(FormatError::Layout("compressed block is damaged"))?;
+        String::from_utf8(bytes).map_err(|_| FormatError::Layout("stored block is not text"))
+    }
+
+    // The lines of a block, split as `compile` splits them
+    fn block_lines(&self, block: usize) -> Result<Vec<Line>, FormatError> {
+        let mut offset = self.file.u64s(STARTS).get(block).unwrap_or_default();
+        Ok(self
+            .block_text(block)?
+            .split_inclusive('\n')
+            .map(|raw| {
+                let line = raw.strip_suffix('\n').unwrap_or(raw);
+                let line = (offset, line.strip_suffix('\r').unwrap_or(line).to_string());
+                offset += raw.len() as u64;
+                line
+            })
+            .collect())
+    }
+
+    // The whole text of a document
+    pub fn text(&self, document: DocumentId) -> Result<Option<String>, FormatError> {
+        let Some(blocks) = self.blocks(document) else {
+            return Ok(None);
+        };
+        let mut text = String::new();
+        for block in blocks {
+            text.push_str(&self.block_text(block)?);
+        }
+        Ok(Some(text))
+    }
+
+    // The lines around byte `offset` of a document; `None` when there is no
+    // such document or it is shorter than that. Blocks are decompressed one
+    // at a time, outwards from the hit, until the window is covered.
+    pub fn context(&self, document: DocumentId, offset: u64, window: Window) -> Result<Option<Context>, FormatError> {
+        let Some(blocks) = self.blocks(document) else {
+            return Ok(None);
+        };
+        let (starts, lengths) = (self.file.u64s(STARTS), self.file.u64s(LENGTHS));
+        let Some(block) = blocks.clone().rev().find(|block| starts.get(*block).is_some_and(|start| start <= offset))
+        else {
+            return Ok(None);
+        };
+        if offset >= starts.get(block).unwrap_or_default() + lengths.get(block).unwrap_or_default() {
+            return Ok(None);
+        }
+
+        let mut lines: VecDeque<Line> = self.block_lines(block)?.into();
+        let (mut first, mut last) = (block, block);
+        let hit = loop {
+            let hit = lines.iter().rposition(|(start, _)| *start <= offset).unwrap_or_default();
+            let (wants_before, wants_after) = match window {
+                Window::Lines { before, after } => (hit < before, lines.len() - hit - 1 < after),
+                Window::Paragraph => (
+                    !lines.range(..=hit).any(is_blank),
+                    !lines.range(hit..).any(is_blank),
+                ),
+            };
+            if wants_before && first > blocks.start {
+                first -= 1;
+                for line in self.block_lines(first)?.into_iter().rev() {
+                    lines.push_front(line);
+                }
+            } else if wants_after && last + 1 < blocks.end {
+                last += 1;
+                lines.extend(self.block_lines(last)?);
+            } else {
+                break hit;
+            }
+        };
+
+        let lines = Vec::from(lines);
+        let (from, to) = match window {
+            Window::Lines { before, after } => (hit.saturating_sub(before), (hit + after).min(lines.len() - 1)),
+            Window::Paragraph if is_blank(&lines[hit]) => (hit, hit),
+            Window::Paragraph => (
+                lines[..hit].iter().rposition(is_blank).map_or(0, |blank| blank + 1),
+                lines[hit..].iter().position(is_blank).map_or(lines.len() - 1, |blank| hit + blank - 1),
+            ),
+        };
+        Ok(Some(Context {
+            offset: lines[from].0,
+            lines: lines[from..=to].iter().map(|(_, line)| line.clone()).collect(),
+            hit: hit - from,
+        }))
+    }
+}
+
+impl Mapped for MappedStore {
+    const KIND: Kind = Kind::Store;
+
+    fn open(file: MappedFile) -> Result<Self, FormatError> {
+        if file.section_count() != 5 || file.u32s(DOCUMENTS).is_empty() || file.u64s(BLOCKS).is_empty() {
+            return Err(FormatError::Layout("document store sections are missing"));
+        }
+        Ok(Self { file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(texts: &[&str]) -> MappedStore {
+        let mut writer = StoreWriter::new();
+        for text in texts {
+            writer.push(text);
+        }
+        MappedStore::from_bytes(writer.finish()).unwrap()
+    }
+
+    fn lines(before: usize, after: usize) -> Window {
+        Window::Lines { before, after }
+    }
+
+    const POEM: &str = "one\ntwo\nthree\n\nfour\nfive\r\nsix";
+
+    #[test]
+    fn keeps_the_text() {
+        let store = store(&[POEM, "", "last"]);
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.text(0).unwrap().as_deref(), Some(POEM));
+        assert_eq!(store.text(1).unwrap().as_deref(), Some(""));
+        assert_eq!(store.text(2).unwrap().as_deref(), Some("last"));
+        assert_eq!(store.text(3).unwrap(), None);
+    }
+
+    #[test]
+    fn lines_around_a_hit() {
+        let store = store(&[POEM]);
+        // "three" starts at byte 8; any byte of it finds the same line
+        for offset in [8, 10] {
+            let context = store.context(0, offset, lines(1, 1)).unwrap().unwrap();
+            assert_eq!(context.lines, ["two", "three", ""]);
+            assert_eq!((context.offset, context.hit), (4, 1));
+        }
+    }
+
+    #[test]
+    fn hits_on_the_first_and_last_line() {
+        let store = store(&[POEM]);
+        let first = store.context(0, 0, lines(2, 1)).unwrap().unwrap();
+        assert_eq!(first.lines, ["one", "two"]);
+        assert_eq!((first.offset, first.hit), (0, 0));
+
+        // No newline after the last line, and a `\r\n` before it
+        let last = store.context(0, POEM.len() as u64 - 1, lines(1, 2)).unwrap().unwrap();
+        assert_eq!(last.lines, ["five", "six"]);
+        assert_eq!(last.hit, 1);
+    }
+
+    #[test]
+    fn paragraphs() {
+        let store = store(&[POEM]);
+        let top = store.context(0, 4, Window::Paragraph).unwrap().unwrap();
+        assert_eq!(top.lines, ["one", "two", "three"]);
+        assert_eq!(top.hit, 1);
+        let bottom = store.context(0, 20, Window::Paragraph).unwrap().unwrap();
+        assert_eq!(bottom.lines, ["four", "five", "six"]);
+        // A blank line is a paragraph of its own
+        let blank = store.context(0, 14, Window::Paragraph).unwrap().unwrap();
+        assert_eq!((blank.lines, blank.offset), (vec![String::new()], 14));
+    }
+
+    #[test]
+    fn nothing_past_the_end() {
+        let store = store(&[POEM, ""]);
+        assert_eq!(store.context(0, POEM.len() as u64, lines(1, 1)).unwrap(), None);
+        assert_eq!(store.context(1, 0, lines(1, 1)).unwrap(), None);
+        assert_eq!(store.context(2, 0, lines(1, 1)).unwrap(), None);
+    }
+
+    #[test]
+    fn windows_span_blocks() {
+        // Lines of 100 bytes, so blocks hold 655 of them
+        let text: String = (0..2_000).map(|line| format!("{line:099}\n")).collect();
+        let store = store(&[&text]);
+        assert!(store.blocks(0).unwrap().len() > 2);
+        assert!(store.file.section(DATA).len() < text.len() / 2);
+
+        let per_block = BLOCK_SIZE / 100;
+        for line in [0, per_block - 1, per_block, 2 * per_block, 1_999] {
+            let context = store.context(0, line as u64 * 100 + 50, lines(3, 3)).unwrap().unwrap();
+            let expected: Vec<String> = (line.saturating_sub(3)..=(line + 3).min(1_999))
+                .map(|line| format!("{line:099}"))
+                .collect();
+            assert_eq!(context.lines, expected, "hit on line {line}");
+            assert_eq!(context.offset, line.saturating_sub(3) as u64 * 100);
+        }
+        // The whole text is one paragraph
+        let context = store.context(0, 100_000, Window::Paragraph).unwrap().unwrap();
+        assert_eq!(context.lines.len(), 2_000);
+        assert_eq!(context.hit, 1_000);
+    }
+
+    #[test]
+    fn extending_copies_documents() {
+        let old = store(&[POEM, "second"]);
+        let mut writer = StoreWriter::new();
+        writer.extend_from(&old);
+        writer.push("third");
+        let store = MappedStore::from_bytes(writer.finish()).unwrap();
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.text(0).unwrap().as_deref(), Some(POEM));
+        assert_eq!(store.text(2).unwrap().as_deref(), Some("third"));
+        assert_eq!(store.context(1, 3, lines(0, 0)).unwrap().unwrap().lines, ["second"]);
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let mut bytes = StoreWriter::new();
+        bytes.push(POEM);
+        let mut bytes = bytes.finish();
+        assert!(MappedStore::from_bytes(bytes[..bytes.len() / 2].to_vec()).is_err());
+        // A flipped byte in the compressed text fails on lookup, not silently
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        let store = MappedStore::from_bytes(bytes).unwrap();
+        assert!(store.text(0).is_err() || store.text(0).unwrap().as_deref() != Some(POEM));
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeSet;
+
+use crate::error::{FormatError, SearchError};
+use crate::pool::{TermId, TermLookup};
+use crate::structure::{KeyStats, SearchStructure};
+use crate::text::normalize_query;
+use crate::trees::suffix_array::{strictly_inside, SuffixArray, SEPARATOR};
+
+use super::file::{Kind, MappedFile, MappedWriter};
+use super::Mapped;
+
+const ENTRIES: usize = 0;
+const TEXT: usize = 1;
+const STARTS: usize = 2;
+const SUFFIXES: usize = 3;
+
+// `SuffixArray` with its four arrays written out as sections. The text is
+// compared as raw bytes, which sorts exactly like the `str` it was built from.
+#[derive(Clone, Debug)]
+pub struct MappedSuffixArray {
+    file: MappedFile,
+}
+
+impl MappedSuffixArray {
+    // `suffix_array` has to be built already
+    pub fn write(suffix_array: &SuffixArray) -> Vec<u8> {
+        MappedWriter::new(Kind::SuffixArray)
+            .u32s(&suffix_array.entries)
+            .bytes(suffix_array.text.as_bytes())
+            .u32s(&suffix_array.starts)
+            .u32s(&suffix_array.suffixes)
+            .finish()
+    }
+
+    fn suffix(&self, offset: u32) -> &[u8] {
+        self.file.section(TEXT).get(offset as usize..).unwrap_or_default()
+    }
+
+    // Normalized text of the entry at `index`, without its separator
+    fn entry_text(&self, index: usize) -> &[u8] {
+        let text = self.file.section(TEXT);
+        let starts = self.file.u32s(STARTS);
+        let start = starts.get(index).unwrap_or_default() as usize;
+        let end = starts.get(index + 1).map_or(text.len(), |next| next as usize);
+        text.get(start..end.saturating_sub(SEPARATOR.len_utf8()))
+            .unwrap_or_default()
+    }
+
+    pub fn search(&self, key: String) -> Result<Vec<TermId>, SearchError> {
+        let query = normalize_query(&key)?;
+        let query = query.as_bytes();
+        let suffixes = self.file.u32s(SUFFIXES);
+        let starts = self.file.u32s(STARTS);
+
+        // The suffixes starting with the query form one contiguous run
+        let lower = suffixes.partition_point(|suffix| self.suffix(suffix) < query);
+        let upper = suffixes.partition_point(|suffix| {
+            let suffix = self.suffix(suffix);
+            suffix < query || suffix.starts_with(query)
+        });
+
+        let matched: BTreeSet<usize> = (lower..upper)
+            .filter_map(|index| suffixes.get(index))
+            .map(|suffix| starts.partition_point(|start| start <= suffix) - 1)
+            .collect();
+
+        let entries = self.file.u32s(ENTRIES);
+        Ok(matched
+            .into_iter()
+            .filter(|index| strictly_inside(self.entry_text(*index), query))
+            .filter_map(|index| entries.get(index))
+            .collect())
+    }
+}
+
+impl SearchStructure for MappedSuffixArray {
+    fn search(&self, _pool: &dyn TermLookup, query: &str) -> Result<Vec<TermId>, SearchError> {
+        MappedSuffixArray::search(self, query.to_string())
+    }
+
+    fn len(&self) -> usize {
+        self.file.u32s(ENTRIES).len()
+    }
+
+    fn for_each_key(&self, visit: &mut dyn FnMut(&str, &[TermId], KeyStats)) {
+        for (index, id) in self.file.u32s(ENTRIES).iter().enumerate() {
+            let text = std::str::from_utf8(self.entry_text(index)).unwrap_or_default();
+            visit(text, &[id], KeyStats::default());
+        }
+    }
+
+    // Entries are not counted
+    fn stats(&self, _key: &str) -> KeyStats {
+        KeyStats::default()
+    }
+}
+
+impl Mapped for MappedSuffixArray {
+    const KIND: Kind = Kind::SuffixArray;
+
+    fn open(file: MappedFile) -> Result<Self, FormatError> {
+        if file.section_count() != 4 || file.u32s(ENTRIES).len() != file.u32s(STARTS).len() {
+            return Err(FormatError::Layout("suffix array sections are missing"));
+        }
+        Ok(Self { file })
+    }
+}
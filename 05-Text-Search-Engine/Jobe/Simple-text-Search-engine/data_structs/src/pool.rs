@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{impl_borrow_decode, Decode, Encode};
+
+use crate::error::FormatError;
+
+pub type TermId = u32;
+
+// Anything pool ids can be resolved against: the in-memory pool or its
+// mapped view
+pub trait TermLookup {
+    fn get(&self, id: TermId) -> Option<&str>;
+
+    fn resolve(&self, ids: &[TermId]) -> Vec<String> {
+        ids.iter()
+            .filter_map(|id| self.get(*id))
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+// Interned term/line dictionary shared by every index of a scope. Each
+// distinct string is stored once and the indexes only keep its `TermId`.
+//
+// Only `strings` goes to disk; the reverse lookup is rebuilt the first time
+// something is interned into a decoded pool, so a read-only runtime never
+// pays for it.
+#[derive(Clone, Debug, Default)]
+pub struct StringPool {
+    strings: Vec<String>,
+    lookup: HashMap<String, TermId>,
+}
+
+impl StringPool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Fails once every `TermId` is taken
+    pub fn intern(&mut self, key: &str) -> Result<TermId, FormatError> {
+        if self.lookup.len() != self.strings.len() {
+            self.lookup = self
+                .strings
+                .iter()
+                .enumerate()
+                .map(|(id, string)| Ok((string.clone(), Self::term_id(id)?)))
+                .collect::<Result<_, FormatError>>()?;
+        }
+
+        if let Some(id) = self.lookup.get(key) {
+            return Ok(*id);
+        }
+        let id = Self::term_id(self.strings.len())?;
+        self.strings.push(key.to_string());
+        self.lookup.insert(key.to_string(), id);
+        Ok(id)
+    }
+
+    fn term_id(index: usize) -> Result<TermId, FormatError> {
+        TermId::try_from(index).map_err(|_| FormatError::PoolFull(index))
+    }
+
+    pub fn get(&self, id: TermId) -> Option<&str> {
+        self.strings.get(id as usize).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+impl TermLookup for StringPool {
+    fn get(&self, id: TermId) -> Option<&str> {
+        StringPool::get(self, id)
+    }
+}
+
+impl Encode for StringPool {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.strings.encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for StringPool {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(Self {
+            strings: Vec::decode(decoder)?,
+            lookup: HashMap::new(),
+        })
+    }
+}
+impl_borrow_decode!(StringPool);
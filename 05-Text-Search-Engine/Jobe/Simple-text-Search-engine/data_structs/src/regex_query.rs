@@ -0,0 +1,259 @@
+use regex::{Regex, RegexBuilder};
+use regex_syntax::hir::{Class, Hir, HirKind};
+
+use crate::error::SearchError;
+use crate::text::normalize;
+
+// Alternatives kept for one part of the expression before it is treated as
+// unknown, e.g. `gr[ae]y` is {gray, grey} but `[a-z]{3}` is not spelled out
+const MAX_EXACT: usize = 16;
+const MAX_CLASS: usize = 8;
+
+// A regular expression over normalized entries, along with the literals any
+// match has to contain, so an index can narrow the candidates down before the
+// regex runs. It matches anywhere in the entry (anchor it with `^` and `$`)
+// and ignores case, since entries are lowercased when they are normalized.
+//
+// The literals form clauses: every match contains at least one literal of
+// each clause. `^un.*able$` gives [["un"], ["able"]], `gr(a|e)y` gives
+// [["gray", "grey"]], and `.*` gives none at all.
+#[derive(Debug, Clone)]
+pub struct RegexQuery {
+    regex: Regex,
+    clauses: Vec<Vec<String>>,
+}
+
+// What is known about the strings part of an expression matches: all of them
+// when there are few enough (`exact`), plus clauses of literals they contain
+#[derive(Debug, Default)]
+struct Analysis {
+    exact: Option<Vec<String>>,
+    clauses: Vec<Vec<String>>,
+}
+
+impl Analysis {
+    fn exact(strings: Vec<String>) -> Self {
+        Self {
+            exact: Some(strings),
+            clauses: Vec::new(),
+        }
+    }
+
+    // Everything that holds for any match, as clauses only
+    fn into_clauses(mut self) -> Vec<Vec<String>> {
+        self.clauses.extend(self.exact);
+        self.clauses
+    }
+}
+
+fn cross(left: &[String], right: &[String]) -> Vec<String> {
+    left.iter()
+        .flat_map(|left| right.iter().map(move |right| format!("{}{}", left, right)))
+        .collect()
+}
+
+fn analyze(hir: &Hir) -> Analysis {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => Analysis::exact(vec![String::new()]),
+        HirKind::Literal(literal) => Analysis::exact(vec![String::from_utf8_lossy(&literal.0).into_owned()]),
+        HirKind::Class(Class::Unicode(class)) => {
+            let mut chars = Vec::new();
+            for range in class.ranges() {
+                chars.extend((range.start()..=range.end()).map(String::from));
+                if chars.len() > MAX_CLASS {
+                    return Analysis::default();
+                }
+            }
+            Analysis::exact(chars)
+        }
+        HirKind::Class(Class::Bytes(_)) => Analysis::default(),
+        HirKind::Capture(capture) => analyze(&capture.sub),
+        HirKind::Repetition(repetition) => {
+            let sub = analyze(&repetition.sub);
+            match (repetition.min, repetition.max) {
+                (0, _) => Analysis::default(),
+                (1, Some(1)) => sub,
+                // At least one copy is there, but not how many
+                _ => Analysis {
+                    exact: None,
+                    clauses: sub.into_clauses(),
+                },
+            }
+        }
+        HirKind::Concat(subs) => {
+            // Neighbouring exact parts are spelled out together, so `un` and
+            // `[ae]` in `un[ae]` give {una, une}; anything unknown in between
+            // ends the current run and makes it a clause of its own
+            let mut clauses = Vec::new();
+            let mut current = vec![String::new()];
+            let mut whole = true;
+            for sub in subs.iter() {
+                let sub = analyze(sub);
+                clauses.extend(sub.clauses);
+                match sub.exact {
+                    Some(exact) if current.len() * exact.len() <= MAX_EXACT => {
+                        current = cross(&current, &exact);
+                    }
+                    exact => {
+                        whole = false;
+                        clauses.push(std::mem::take(&mut current));
+                        current = exact.unwrap_or_else(|| vec![String::new()]);
+                    }
+                }
+            }
+            if whole {
+                Analysis {
+                    exact: Some(current),
+                    clauses,
+                }
+            } else {
+                clauses.push(current);
+                Analysis {
+                    exact: None,
+                    clauses,
+                }
+            }
+        }
+        HirKind::Alternation(subs) => {
+            let subs: Vec<Analysis> = subs.iter().map(analyze).collect();
+            let mut exact = Vec::new();
+            let mut clause = Vec::new();
+            let (mut all_exact, mut all_clause) = (true, true);
+            for sub in subs.into_iter() {
+                match sub.exact {
+                    Some(strings) => {
+                        exact.extend(strings.iter().cloned());
+                        clause.extend(strings);
+                    }
+                    None => {
+                        all_exact = false;
+                        // One clause of each alternative, any of which holds
+                        match sub.clauses.into_iter().next() {
+                            Some(strings) => clause.extend(strings),
+                            None => all_clause = false,
+                        }
+                    }
+                }
+            }
+            if all_exact && exact.len() <= MAX_EXACT {
+                Analysis::exact(exact)
+            } else if all_clause && clause.len() <= MAX_EXACT {
+                Analysis {
+                    exact: None,
+                    clauses: vec![clause],
+                }
+            } else {
+                Analysis::default()
+            }
+        }
+    }
+}
+
+impl RegexQuery {
+    pub fn parse(pattern: &str) -> Result<Self, SearchError> {
+        if pattern.trim().is_empty() {
+            return Err(SearchError::EmptyQuery);
+        }
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|error| SearchError::InvalidRegex(error.to_string()))?;
+        let hir = regex_syntax::parse(pattern).map_err(|error| SearchError::InvalidRegex(error.to_string()))?;
+
+        // Literals are looked up in normalized grams; a clause holding the
+        // empty string is always true and narrows nothing down
+        let mut clauses: Vec<Vec<String>> = analyze(&hir)
+            .into_clauses()
+            .into_iter()
+            .map(|clause| {
+                let mut clause: Vec<String> = clause.iter().map(|literal| normalize(literal)).collect();
+                clause.sort_unstable();
+                clause.dedup();
+                clause
+            })
+            .filter(|clause| !clause.is_empty() && clause.iter().all(|literal| !literal.is_empty()))
+            .collect();
+        clauses.sort_unstable();
+        clauses.dedup();
+        Ok(Self { regex, clauses })
+    }
+
+    // `key` has to be normalized already
+    pub fn is_match(&self, key: &str) -> bool {
+        self.regex.is_match(key)
+    }
+
+    pub fn clauses(&self) -> &[Vec<String>] {
+        &self.clauses
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.regex.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clauses(pattern: &str) -> Vec<Vec<String>> {
+        RegexQuery::parse(pattern).unwrap().clauses().to_vec()
+    }
+
+    #[test]
+    fn extracts_literals() {
+        assert_eq!(clauses("^un.*able$"), [vec!["able"], vec!["un"]]);
+        assert_eq!(clauses("gr(a|e)y"), [vec!["gray", "grey"]]);
+        // An optional piece splits the literals around it
+        assert_eq!(clauses("colou?r"), [vec!["colo"], vec!["r"]]);
+        assert_eq!(clauses("w(ha|he)t"), [vec!["what", "whet"]]);
+        assert_eq!(clauses("Whale"), [vec!["whale"]]);
+    }
+
+    #[test]
+    fn gives_up_where_nothing_is_certain() {
+        assert!(clauses(".*").is_empty());
+        assert!(clauses("[a-z]{3}").is_empty());
+        assert!(clauses("a?").is_empty());
+        assert!(clauses("(foo|.*)").is_empty());
+    }
+
+    #[test]
+    fn literals_hold_for_every_match() {
+        let keys = [
+            "unbelievable", "unable", "gray", "grey", "color", "colour", "whet", "what", "abc", "aabbc",
+            "the mad dog", "said the old man", "sea breeze", "seabreeze",
+        ];
+        let patterns = [
+            "^un.*able$", "gr[ae]y", "colou?r", "w(ha|he)t", "(?:ab)+c", "a.c", "mad dog", "the (old|mad)",
+            "sea ?breeze", "(ab|bb)c", "^(foo|bar|aab)",
+        ];
+        for pattern in patterns {
+            let query = RegexQuery::parse(pattern).unwrap();
+            for key in keys.iter().filter(|key| query.is_match(key)) {
+                for clause in query.clauses() {
+                    assert!(
+                        clause.iter().any(|literal| key.contains(literal.as_str())),
+                        "{pattern:?} matches {key:?} without any of {clause:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ignores_case() {
+        let query = RegexQuery::parse("^The").unwrap();
+        assert!(query.is_match("the whale"));
+        assert_eq!(query.clauses(), [vec!["the"]]);
+    }
+
+    #[test]
+    fn rejects_bad_patterns() {
+        assert!(matches!(RegexQuery::parse(""), Err(SearchError::EmptyQuery)));
+        assert!(matches!(RegexQuery::parse("   "), Err(SearchError::EmptyQuery)));
+        assert!(matches!(RegexQuery::parse("("), Err(SearchError::InvalidRegex(_))));
+        assert!(matches!(RegexQuery::parse("a\\"), Err(SearchError::InvalidRegex(_))));
+        assert!(matches!(RegexQuery::parse("a{99999999}"), Err(SearchError::InvalidRegex(_))));
+    }
+}
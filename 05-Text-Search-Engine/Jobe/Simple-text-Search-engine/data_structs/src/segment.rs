@@ -0,0 +1,94 @@
+use bincode::{Decode, Encode};
+
+use crate::document::Location;
+use crate::pool::TermId;
+
+// File in a scope's directory listing its live segments
+pub const MANIFEST: &str = "segments.bin";
+
+// File in a segment's directory holding its tokens, for merges
+pub const TOKENS: &str = "tokens-serial.bin";
+
+// Every token of a segment, as its pool id and the line it sits on (counted
+// from the start of the segment), in input order. Merging segments replays
+// these into fresh structures.
+pub type SegmentTokens = Vec<(TermId, u64)>;
+
+// File in a line scope segment's directory holding where its lines came from
+pub const LOCATIONS: &str = "locations-serial.bin";
+
+// The location of every token of a line scope segment, in the same order as
+// its `SegmentTokens`, so merges carry them along
+pub type SegmentLocations = Vec<Location>;
+
+// The segments of one scope, oldest first. Every segment is a directory with
+// its own pool and structures; an index is the union of its segments.
+//
+// The manifest is the only file that ever changes in place: new segments are
+// written next to the live ones and then swapped in with a new manifest, so a
+// reader sees either the old set or the new one.
+#[derive(Encode, Decode, Debug, Clone, Default)]
+pub struct Manifest {
+    pub segments: Vec<SegmentInfo>,
+    next_segment: u64, // only grows, so a name is never reused
+}
+
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct SegmentInfo {
+    pub name: String,
+    pub lines: u64,
+    pub tokens: u64,
+    pub bytes: u64, // of the text of its tokens
+}
+
+impl Manifest {
+    // Name for a segment about to be written
+    pub fn next_name(&mut self) -> String {
+        let name = format!("segment-{:06}", self.next_segment);
+        self.next_segment += 1;
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::StringPool;
+    use crate::structure::SearchStructure;
+    use crate::trees::trie::Trie;
+
+    fn store_all(pool: &StringPool, tokens: &[(TermId, u64)]) -> Trie {
+        let mut trie = Trie::new();
+        for (id, line) in tokens {
+            trie.store(pool.get(*id).unwrap(), *id, *line);
+        }
+        trie
+    }
+
+    #[test]
+    fn removing_matches_never_storing() {
+        let mut pool = StringPool::new();
+        let words = ["The", "cat", "the", "dog", "cat", "the"];
+        let lines = [0, 0, 0, 1, 1, 2];
+        let mut tokens: SegmentTokens = words.iter().zip(lines).map(|(word, line)| (pool.intern(word).unwrap(), line)).collect();
+        let mut trie = store_all(&pool, &tokens);
+
+        // Take the tokens back one at a time, checking against a trie that
+        // only ever saw the rest
+        while !tokens.is_empty() {
+            let (id, line) = tokens[0];
+            assert!(trie.remove(pool.get(id).unwrap(), id, line));
+            tokens.remove(0);
+
+            let expected = store_all(&pool, &tokens);
+            for word in ["the", "cat", "dog"] {
+                assert_eq!(trie.stats(word), expected.stats(word), "{word} after removing {id} on {line}");
+                assert_eq!(
+                    trie.search(word.to_string()).unwrap(),
+                    expected.search(word.to_string()).unwrap()
+                );
+            }
+        }
+        assert_eq!(trie.len(), 0);
+    }
+}
@@ -0,0 +1,414 @@
+use std::ops::ControlFlow;
+use bincode::Encode;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::error::{FormatError, SearchError};
+use crate::format;
+use crate::pool::{TermId, TermLookup};
+use crate::regex_query::RegexQuery;
+use crate::text::{normalize, normalize_query};
+use crate::wildcard::{Pattern, WordPattern};
+
+// How often a key occurs. Structures that do not count keys report zeros.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyStats {
+    pub term_frequency: u64,
+    pub line_frequency: u64,
+}
+
+// The read side every index shares, in memory or mapped, so callers can hold
+// any of them as a `dyn SearchStructure` and never need to know which one
+// answers a query.
+pub trait SearchStructure: Send + Sync {
+    // Ids of the pool entries matching `query`, in the structure's own
+    // semantics (prefix, suffix or contains). Structures that only store ids
+    // use `pool` to check their candidates.
+    fn search(&self, pool: &dyn TermLookup, query: &str) -> Result<Vec<TermId>, SearchError>;
+
+    // Hands matches to `visit` one at a time until it breaks. Tree walks stop
+    // right there; the default runs the whole search first.
+    fn search_each(
+        &self,
+        pool: &dyn TermLookup,
+        query: &str,
+        visit: &mut dyn FnMut(TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        for id in self.search(pool, query)? {
+            if visit(id).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // The matches of `search_each`, shortest key first, each with how many
+    // characters its key has beyond the query. By default the whole search
+    // is sorted first; structures that walk their keys level by level stop
+    // as soon as `visit` breaks.
+    fn search_by_length(
+        &self,
+        pool: &dyn TermLookup,
+        query: &str,
+        visit: &mut dyn FnMut(usize, TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        let query_len = normalize_query(query)?.chars().count();
+        let mut matches: Vec<(usize, TermId)> = self
+            .search(pool, query)?
+            .into_iter()
+            .map(|id| {
+                let len = pool.get(id).map_or(0, |key| normalize(key).chars().count());
+                (len.saturating_sub(query_len), id)
+            })
+            .collect();
+        // Stable, so matches of one length keep the structure's order
+        matches.sort_by_key(|(extra, _)| *extra);
+        for (extra, id) in matches {
+            if visit(extra, id).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Precomputed most frequent matches, best first, for structures that
+    // keep them; `None` means the caller has to rank a full search itself
+    fn top_completions(&self, _query: &str) -> Result<Option<Vec<TermId>>, SearchError> {
+        Ok(None)
+    }
+
+    // Entries within `max_distance` edits of `query`, with their distance,
+    // closest first; `None` means the structure cannot walk its keys with a
+    // Levenshtein automaton
+    fn search_fuzzy(
+        &self,
+        _query: &str,
+        _max_distance: u32,
+    ) -> Result<Option<Vec<(u32, TermId)>>, SearchError> {
+        Ok(None)
+    }
+
+    // Entries whose whole key matches `pattern`; `None` means the structure
+    // cannot narrow the candidates down for this pattern, and the caller
+    // should ask another one
+    fn search_wildcard(
+        &self,
+        _pool: &dyn TermLookup,
+        _pattern: &Pattern,
+    ) -> Result<Option<Vec<TermId>>, SearchError> {
+        Ok(None)
+    }
+
+    // Entries whose words match `pattern` somewhere; `None` means the
+    // structure cannot narrow the candidates down. By default every key is
+    // checked, which suits structures keyed by whole entries.
+    fn search_word_wildcard(
+        &self,
+        _pool: &dyn TermLookup,
+        pattern: &WordPattern,
+    ) -> Result<Option<Vec<TermId>>, SearchError> {
+        let mut results = Vec::new();
+        self.for_each_key(&mut |key, ids, _| {
+            let words: Vec<&str> = key.unicode_words().collect();
+            if pattern.is_match(&words) {
+                results.extend_from_slice(ids);
+            }
+        });
+        Ok(Some(results))
+    }
+
+    // Entries `query` matches; `None` means the structure cannot narrow the
+    // candidates down. By default every key is checked, like
+    // `search_word_wildcard` does.
+    fn search_regex(
+        &self,
+        _pool: &dyn TermLookup,
+        query: &RegexQuery,
+    ) -> Result<Option<Vec<TermId>>, SearchError> {
+        let mut results = Vec::new();
+        self.for_each_key(&mut |key, ids, _| {
+            if query.is_match(key) {
+                results.extend_from_slice(ids);
+            }
+        });
+        Ok(Some(results))
+    }
+
+    // Number of distinct keys, i.e. how many times `for_each_key` visits
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Visits every key in the form it is indexed under (normalized, and in
+    // reading order even for reversed structures) with its ids and stats
+    fn for_each_key(&self, visit: &mut dyn FnMut(&str, &[TermId], KeyStats));
+
+    fn stats(&self, key: &str) -> KeyStats;
+
+    // One line about the structure, for debug output
+    fn describe(&self) -> String {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name).to_string()
+    }
+}
+
+// The write side of the in-memory structures `compile` builds
+pub trait BuildStructure: SearchStructure + Encode {
+    fn store(&mut self, key: &str, id: TermId, line: u64);
+
+    // Takes back one earlier `store` with the same key, id and line, keeping
+    // ids, counts and postings as if it had never happened; false when it
+    // was never stored. Structures laid out by `build` need it to run again
+    // afterwards.
+    fn remove(&mut self, key: &str, id: TermId, line: u64) -> bool;
+
+    // Moves one occurrence on `line` from `old` to `new`, e.g. when the line
+    // or a word in it was edited; false, with nothing stored, when `old` was
+    // never there
+    fn replace(&mut self, old: (&str, TermId), new: (&str, TermId), line: u64) -> bool {
+        if !self.remove(old.0, old.1, line) {
+            return false;
+        }
+        self.store(new.0, new.1, line);
+        true
+    }
+
+    // Runs once after the last `store`, for structures that are laid out in
+    // one go rather than as keys come in
+    fn build(&mut self, _pool: &dyn TermLookup) {}
+
+    fn serialize(&self) -> Result<Vec<u8>, FormatError>
+    where
+        Self: Sized,
+    {
+        format::encode(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapped::dictionary::MappedDictionary;
+    use crate::mapped::ngram::MappedNGram;
+    use crate::mapped::Mapped;
+    use crate::pool::StringPool;
+    use crate::regex_query::RegexQuery;
+    use crate::trees::ngram::{NGramIndex, SearchScopeNgram};
+    use crate::trees::{radix::RadixTrie, suffix::SuffixTree, suffix_array::SuffixArray, trie::Trie};
+    use crate::wildcard::Pattern;
+
+    // Words and the line each one is on
+    const TOKENS: [(&str, u64); 7] = [
+        ("The", 0),
+        ("cat", 0),
+        ("the", 0),
+        ("Cat", 1),
+        ("dog", 1),
+        ("the", 2),
+        ("cats", 2),
+    ];
+
+    fn fill<T: BuildStructure>(mut structure: T, pool: &mut StringPool) -> T {
+        for (word, line) in TOKENS {
+            structure.store(word, pool.intern(word).unwrap(), line);
+        }
+        structure
+    }
+
+    // A vocabulary to run whole queries against
+    const WORDS: [&str; 10] = [
+        "color", "colour", "collar", "cellar", "gray", "grey", "greet", "Straße", "café", "cafe",
+    ];
+
+    // The trie, its mapped layout, and the gram index with its mapped layout,
+    // all over `WORDS`
+    fn searchable(pool: &mut StringPool) -> Vec<Box<dyn SearchStructure>> {
+        let mut trie = Trie::new();
+        let mut ngram = NGramIndex::builder().scope(SearchScopeNgram::Words).gram_size(2).build();
+        for (line, word) in WORDS.iter().enumerate() {
+            let id = pool.intern(word).unwrap();
+            trie.store(word, id, line as u64);
+            BuildStructure::store(&mut ngram, word, id, line as u64);
+        }
+        let mapped_trie = MappedDictionary::from_bytes(MappedDictionary::write_trie(&trie).unwrap()).unwrap();
+        let mapped_ngram = MappedNGram::from_bytes(MappedNGram::write(&ngram).unwrap()).unwrap();
+        vec![Box::new(trie), Box::new(mapped_trie), Box::new(ngram), Box::new(mapped_ngram)]
+    }
+
+    fn stats(term_frequency: u64, line_frequency: u64) -> KeyStats {
+        KeyStats { term_frequency, line_frequency }
+    }
+
+    #[test]
+    fn counts_terms_and_lines() {
+        let mut pool = StringPool::new();
+        let trie = fill(Trie::new(), &mut pool);
+        let radix = fill(RadixTrie::new(), &mut pool);
+        let suffix = fill(SuffixTree::new(), &mut pool);
+        let mapped_trie = MappedDictionary::from_bytes(MappedDictionary::write_trie(&trie).unwrap()).unwrap();
+        let mapped_suffix = MappedDictionary::from_bytes(MappedDictionary::write_suffix_tree(&suffix).unwrap()).unwrap();
+
+        for structure in [&trie as &dyn SearchStructure, &radix, &suffix, &mapped_trie, &mapped_suffix] {
+            let name = structure.describe();
+            // "The" and "the" on line 0 are one line
+            assert_eq!(structure.stats("the"), stats(3, 2), "{name}");
+            assert_eq!(structure.stats("THE"), stats(3, 2), "{name}");
+            assert_eq!(structure.stats("cat"), stats(2, 2), "{name}");
+            assert_eq!(structure.stats("cats"), stats(1, 1), "{name}");
+            assert_eq!(structure.stats("ca"), KeyStats::default(), "{name}");
+            assert_eq!(structure.stats("mouse"), KeyStats::default(), "{name}");
+            assert_eq!(structure.len(), 4, "{name}");
+
+            let mut total = 0;
+            structure.for_each_key(&mut |_, _, stats| total += stats.term_frequency);
+            assert_eq!(total, TOKENS.len() as u64, "{name}");
+        }
+    }
+
+    // Takes back every token of `TOKENS`, then moves one from "cat" to "dog",
+    // checking both against a structure that only ever saw the outcome
+    fn round_trip<T: BuildStructure>(empty: impl Fn() -> T) {
+        let mut pool = StringPool::new();
+        let mut structure = fill(empty(), &mut pool);
+        let name = structure.describe();
+        for (word, line) in TOKENS {
+            assert!(structure.remove(word, pool.intern(word).unwrap(), line), "{name} {word}");
+        }
+        structure.build(&pool);
+        assert!(structure.is_empty(), "{name}");
+        for (word, line) in TOKENS {
+            assert!(!structure.remove(word, pool.intern(word).unwrap(), line), "{name} {word}");
+            assert_eq!(structure.stats(word), KeyStats::default(), "{name} {word}");
+            for query in [word, &word[..2], &word[1..]] {
+                assert!(structure.search(&pool, query).unwrap().is_empty(), "{name} {query}");
+            }
+        }
+
+        let (cat, dog) = (pool.intern("cat").unwrap(), pool.intern("dog").unwrap());
+        let mut structure = fill(empty(), &mut pool);
+        assert!(structure.replace(("cat", cat), ("dog", dog), 0), "{name}");
+        // The one "cat" on line 0 is gone; "Cat" on line 1 is another entry
+        assert!(!structure.replace(("cat", cat), ("dog", dog), 0), "{name}");
+        structure.build(&pool);
+        let mut expected = empty();
+        for (word, line) in TOKENS {
+            let word = if (word, line) == ("cat", 0) { "dog" } else { word };
+            expected.store(word, pool.intern(word).unwrap(), line);
+        }
+        expected.build(&pool);
+        assert_eq!(structure.len(), expected.len(), "{name}");
+        for query in ["the", "cat", "dog", "ca", "at", "og"] {
+            assert_eq!(structure.stats(query), expected.stats(query), "{name} {query}");
+            let mut found = structure.search(&pool, query).unwrap();
+            let mut wanted = expected.search(&pool, query).unwrap();
+            found.sort_unstable();
+            wanted.sort_unstable();
+            assert_eq!(found, wanted, "{name} {query}");
+        }
+    }
+
+    #[test]
+    fn removes_and_replaces_in_every_structure() {
+        round_trip(Trie::new);
+        round_trip(SuffixTree::new);
+        round_trip(RadixTrie::new);
+        round_trip(SuffixArray::new);
+        round_trip(|| NGramIndex::builder().scope(SearchScopeNgram::Words).gram_size(2).build());
+    }
+
+    #[test]
+    fn fuzzy_searches_walk_the_dictionaries() {
+        let mut pool = StringPool::new();
+        for structure in searchable(&mut pool) {
+            let name = structure.describe();
+            let Some(found) = structure.search_fuzzy("colr", 1).unwrap() else {
+                // The gram index has no automaton walk, the runtime asks the trie
+                assert!(name.contains("NGram"), "{name}");
+                continue;
+            };
+            let found: Vec<(u32, String)> =
+                found.into_iter().map(|(distance, id)| (distance, pool.get(id).unwrap().to_string())).collect();
+            assert_eq!(found, [(1, "color".to_string())], "{name}");
+
+            let found = structure.search_fuzzy("GRAY", 2).unwrap().unwrap();
+            let ids: Vec<TermId> = found.iter().map(|(_, id)| *id).collect();
+            // Closest first: the exact key, then one substitution; "greet" is
+            // three edits away
+            assert_eq!(pool.resolve(&ids), ["gray", "grey"], "{name}");
+            assert_eq!(found.iter().map(|(distance, _)| *distance).collect::<Vec<_>>(), [0, 1], "{name}");
+
+            // Edits count characters, not bytes, and keys are folded first
+            let found = structure.search_fuzzy("strase", 1).unwrap().unwrap();
+            let ids: Vec<TermId> = found.iter().map(|(_, id)| *id).collect();
+            assert_eq!(pool.resolve(&ids), ["Straße"], "{name}");
+            let found = structure.search_fuzzy("cafè", 1).unwrap().unwrap();
+            assert_eq!(found.len(), 2, "{name}");
+            assert!(structure.search_fuzzy(" ", 1).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn character_groups_search_every_structure() {
+        let mut pool = StringPool::new();
+        for structure in searchable(&mut pool) {
+            let name = structure.describe();
+            let narrowed = |pattern: &str| {
+                let pattern = Pattern::parse(pattern).unwrap();
+                structure.search_wildcard(&pool, &pattern).unwrap().map(|ids| {
+                    let mut found = pool.resolve(&ids);
+                    found.sort();
+                    found
+                })
+            };
+            let search = |pattern: &str| narrowed(pattern).unwrap();
+            assert_eq!(search("gr[ae]y"), ["gray", "grey"], "{name}");
+            assert_eq!(search("gr[^a]y"), ["grey"], "{name}");
+            assert_eq!(search("colo(u)r"), ["color", "colour"], "{name}");
+            assert_eq!(search("(co|ce)llar"), ["cellar", "collar"], "{name}");
+            assert_eq!(search("c[a-f]llar"), ["cellar"], "{name}");
+            // No literal is a gram long, so the gram index leaves it to the
+            // trie
+            match narrowed("[cg]*r") {
+                Some(found) => assert_eq!(found, ["cellar", "collar", "color", "colour"], "{name}"),
+                None => assert!(name.contains("NGram"), "{name}"),
+            }
+            // Classes take whole characters, accented ones included
+            assert_eq!(search("caf[eé]"), ["cafe", "café"], "{name}");
+            assert_eq!(search("STRA[ß]E"), ["Straße"], "{name}");
+            assert!(search("gr[io]y").is_empty(), "{name}");
+        }
+    }
+
+    #[test]
+    fn regexes_search_every_structure() {
+        let mut pool = StringPool::new();
+        for structure in searchable(&mut pool) {
+            let name = structure.describe();
+            let narrowed = |pattern: &str| {
+                let query = RegexQuery::parse(pattern).unwrap();
+                structure.search_regex(&pool, &query).unwrap().map(|ids| {
+                    let mut found = pool.resolve(&ids);
+                    found.sort();
+                    found
+                })
+            };
+            let search = |pattern: &str| narrowed(pattern).unwrap();
+            assert_eq!(search("^gr[ae]y$"), ["gray", "grey"], "{name}");
+            assert_eq!(search("^colou?r$"), ["color", "colour"], "{name}");
+            // Unanchored, so it matches inside entries, and case is ignored
+            assert_eq!(search("LLA"), ["cellar", "collar"], "{name}");
+            assert_eq!(search("^c(o|e)llar"), ["cellar", "collar"], "{name}");
+            assert_eq!(search("^caf(e|é)$"), ["cafe", "café"], "{name}");
+            assert_eq!(search("^straße$"), ["Straße"], "{name}");
+            assert!(search("^gr[io]y$").is_empty(), "{name}");
+            // Nothing a gram long has to be in a match, so the gram index
+            // leaves it to the trie
+            match narrowed("^.r.y$") {
+                Some(found) => assert_eq!(found, ["gray", "grey"], "{name}"),
+                None => assert!(name.contains("NGram"), "{name}"),
+            }
+        }
+        assert!(RegexQuery::parse("gr(a").is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::error::SearchError;
+
+/// Folds a key into the form the trees index it under: Unicode lowercase in
+/// NFC (so a precomposed "é" and "e" + U+0301 are the same key), every
+/// whitespace character collapsed to a plain space, and control characters
+/// dropped.
+pub fn normalize(key: &str) -> String {
+    let mut folded = String::with_capacity(key.len());
+    for char in key.chars() {
+        if char.is_whitespace() {
+            folded.push(' ');
+        } else if !char.is_control() {
+            folded.extend(char.to_lowercase());
+        }
+    }
+    folded.nfc().collect()
+}
+
+/// Normalizes a user query exactly like `normalize` does for stored keys.
+/// Characters that `normalize` would silently drop (control characters) are
+/// reported instead, since dropping them would change what is searched for.
+pub fn normalize_query(query: &str) -> Result<String, SearchError> {
+    if let Some(char) = query.chars().find(|c| c.is_control() && !c.is_whitespace()) {
+        return Err(SearchError::UnsupportedCharacter(char));
+    }
+
+    let normalized = normalize(query);
+    if normalized.trim().is_empty() {
+        return Err(SearchError::EmptyQuery);
+    }
+    Ok(normalized)
+}
+
+/// Splits a normalized key into grapheme clusters, last one first, the order
+/// suffix keys are stored in. The clusters are found front to back and then
+/// reversed: walking the graphemes from the back does not always agree with
+/// the forward split (it merges a whole Devanagari word like "नमस्ते" into
+/// one cluster), and a key has to split the same way wherever it appears.
+pub fn reversed_graphemes(key: &str) -> Vec<&str> {
+    let mut graphemes: Vec<&str> = key.graphemes(true).collect();
+    graphemes.reverse();
+    graphemes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_queries_like_keys() {
+        assert_eq!(normalize_query("Café").unwrap(), normalize("cafe\u{301}"));
+        assert_eq!(normalize_query("New\tYork").unwrap(), "new york");
+        assert_eq!(normalize_query(" ÉTÉ ").unwrap(), " été ");
+    }
+
+    #[test]
+    fn rejects_queries_that_search_nothing() {
+        assert_eq!(normalize_query(""), Err(SearchError::EmptyQuery));
+        assert_eq!(normalize_query(" \t\n"), Err(SearchError::EmptyQuery));
+        // Whitespace is folded rather than rejected, other controls are not
+        assert_eq!(normalize_query("cat\u{0}"), Err(SearchError::UnsupportedCharacter('\u{0}')));
+        assert_eq!(normalize_query("\u{1b}[1m"), Err(SearchError::UnsupportedCharacter('\u{1b}')));
+    }
+}
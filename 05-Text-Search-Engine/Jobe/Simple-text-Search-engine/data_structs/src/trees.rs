@@ -1,3 +1,16 @@
 pub mod trie;
+pub mod radix;
 pub mod suffix;
+pub mod suffix_array;
 pub mod ngram;
+pub mod fst_dict;
+mod node;
+
+use crate::pool::TermId;
+
+// Keeps the `k` most frequent of `(count, id)` candidates, most frequent
+// first; ties go to the lower id, i.e. the entry seen first in the dataset
+pub(crate) fn keep_top(candidates: &mut Vec<(u64, TermId)>, k: usize) {
+    candidates.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    candidates.truncate(k);
+}
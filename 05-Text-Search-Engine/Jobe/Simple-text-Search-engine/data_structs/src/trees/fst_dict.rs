@@ -0,0 +1,452 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::ControlFlow;
+use fst::raw::Output;
+use fst::{IntoStreamer, Map, MapBuilder, Streamer};
+
+use crate::error::{FormatError, SearchError};
+use crate::fuzzy::Levenshtein;
+use crate::mapped::file::{Kind, MappedFile, MappedWriter};
+use crate::mapped::Mapped;
+use crate::pool::{TermId, TermLookup};
+use crate::structure::{KeyStats, SearchStructure};
+use crate::text::{normalize, normalize_query};
+use crate::wildcard::Pattern;
+
+pub use fst::automaton;
+pub use fst::Automaton;
+
+const FST: usize = 0;
+const GROUPS: usize = 1;
+const IDS: usize = 2;
+const COUNTS: usize = 3;
+// Structures built on top of the dictionary add their sections after these
+pub(crate) const SECTIONS: usize = 4;
+
+// Immutable prefix index over the vocabulary, backed by a finite state
+// transducer: every key maps to a slot, which holds the pool entries stored
+// under the key and its occurrence and line counts. Shared prefixes and
+// suffixes of the keys are stored once, so it is far smaller than a `Trie`.
+//
+// It lives in one buffer of sections, searched in place, so the same
+// dictionary works built in memory or mapped from a file; `MappedDictionary`
+// is one with a few sections of its own added.
+//
+// It cannot change once built; collect the keys with `FstDictionaryBuilder`.
+#[derive(Clone, Debug)]
+pub struct FstDictionary {
+    file: MappedFile,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FstDictionaryBuilder {
+    keys: BTreeMap<String, (Vec<TermId>, KeyStats)>,
+}
+
+impl FstDictionaryBuilder {
+    // Keys stored this way are not counted
+    pub fn store(&mut self, key: &str, id: TermId) {
+        let (ids, _) = self.keys.entry(normalize(key)).or_default();
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+
+    // Adds a key exactly as given, along with its ids and counts
+    pub(crate) fn insert(&mut self, key: String, ids: &[TermId], stats: KeyStats) {
+        self.keys.insert(key, (ids.to_vec(), stats));
+    }
+
+    // Appends the dictionary's sections to `writer`. The transducer wants its
+    // keys in byte order, which a BTreeMap of strings already gives us.
+    pub(crate) fn write(self, writer: &mut MappedWriter) -> Result<(), FormatError> {
+        let mut builder = MapBuilder::memory();
+        let mut groups = vec![0];
+        let mut ids = Vec::new();
+        let mut counts = Vec::with_capacity(self.keys.len() * 2);
+        for (slot, (key, (key_ids, stats))) in self.keys.into_iter().enumerate() {
+            builder.insert(key, slot as u64)?;
+            ids.extend(key_ids);
+            groups.push(ids.len() as u32);
+            counts.extend([stats.term_frequency, stats.line_frequency]);
+        }
+        writer
+            .bytes(&builder.into_inner()?)
+            .u32s(&groups)
+            .u32s(&ids)
+            .u64s(&counts);
+        Ok(())
+    }
+
+    pub fn build(self) -> Result<FstDictionary, FormatError> {
+        let mut writer = MappedWriter::new(Kind::Dictionary);
+        self.write(&mut writer)?;
+        FstDictionary::from_bytes(writer.finish())
+    }
+}
+
+impl FstDictionary {
+    pub fn builder() -> FstDictionaryBuilder {
+        FstDictionaryBuilder::default()
+    }
+
+    // The whole buffer, for structures that keep sections after the
+    // dictionary's own
+    pub(crate) fn file(&self) -> &MappedFile {
+        &self.file
+    }
+
+    fn map(&self) -> Map<&[u8]> {
+        // Checked when the dictionary was opened
+        Map::new(self.file.section(FST)).expect("fst validated on open")
+    }
+
+    fn ids(&self, slot: u64) -> impl Iterator<Item = TermId> + '_ {
+        let groups = self.file.u32s(GROUPS);
+        let ids = self.file.u32s(IDS);
+        let start = groups.get(slot as usize).unwrap_or_default();
+        let end = groups.get(slot as usize + 1).unwrap_or_default();
+        (start..end).filter_map(move |index| ids.get(index as usize))
+    }
+
+    fn collect<S>(&self, mut stream: S) -> Vec<TermId>
+    where
+        S: for<'b> Streamer<'b, Item = (&'b [u8], u64)>,
+    {
+        let mut results = Vec::new();
+        while let Some((_, slot)) = stream.next() {
+            results.extend(self.ids(slot));
+        }
+        results
+    }
+
+    // Same semantics as `Trie::search`: words equal to the prefix itself are
+    // not part of the result
+    pub fn search(&self, prefix: String) -> Result<Vec<TermId>, SearchError> {
+        let mut results = Vec::new();
+        self.search_each(&prefix, &mut |id| {
+            results.push(id);
+            ControlFlow::Continue(())
+        })?;
+        Ok(results)
+    }
+
+    // Hands every match of `search` to `visit`, in key order, until it breaks
+    pub fn search_each(
+        &self,
+        prefix: &str,
+        visit: &mut dyn FnMut(TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        self.search_stored(&normalize_query(prefix)?, visit);
+        Ok(())
+    }
+
+    // `search_each` for a query already in the form keys are stored in
+    pub(crate) fn search_stored(&self, query: &str, visit: &mut dyn FnMut(TermId) -> ControlFlow<()>) {
+        let map = self.map();
+        let mut stream = map.search(automaton::Str::new(query).starts_with()).into_stream();
+        while let Some((key, slot)) = stream.next() {
+            if key == query.as_bytes() {
+                continue;
+            }
+            for id in self.ids(slot) {
+                if visit(id).is_break() {
+                    return;
+                }
+            }
+        }
+    }
+
+    // The matches of `search_each`, shortest key first, each with how many
+    // characters its key has beyond the prefix
+    pub fn search_by_length(
+        &self,
+        prefix: &str,
+        visit: &mut dyn FnMut(usize, TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        self.search_stored_by_length(&normalize_query(prefix)?, visit);
+        Ok(())
+    }
+
+    // `search_by_length` for a query already in the form keys are stored in.
+    // Below the query the transducer is walked breadth first by character:
+    // bytes continuing a character, and the separators of reversed keys, do
+    // not start a new one, so they are taken before anything longer.
+    pub(crate) fn search_stored_by_length(
+        &self,
+        query: &str,
+        visit: &mut dyn FnMut(usize, TermId) -> ControlFlow<()>,
+    ) {
+        let map = self.map();
+        let fst = map.as_fst();
+        let mut node = fst.root();
+        let mut output = Output::zero();
+        for byte in query.bytes() {
+            let Some(index) = node.find_input(byte) else {
+                return;
+            };
+            let transition = node.transition(index);
+            output = output.cat(transition.out);
+            node = fst.node(transition.addr);
+        }
+
+        let mut queue = VecDeque::from([(node.addr(), output, 0)]);
+        while let Some((addr, output, extra)) = queue.pop_front() {
+            let node = fst.node(addr);
+            // The query itself is not part of the result
+            if node.is_final() && extra > 0 {
+                for id in self.ids(output.cat(node.final_output()).value()) {
+                    if visit(extra, id).is_break() {
+                        return;
+                    }
+                }
+            }
+            for transition in node.transitions() {
+                let output = output.cat(transition.out);
+                if transition.inp & 0xC0 == 0x80 || transition.inp == 0 {
+                    queue.push_front((transition.addr, output, extra));
+                } else {
+                    queue.push_back((transition.addr, output, extra + 1));
+                }
+            }
+        }
+    }
+
+    // Entries whose normalized key falls in `[start, end)`, in key order
+    pub fn range(&self, start: &str, end: &str) -> Vec<TermId> {
+        let map = self.map();
+        self.collect(map.range().ge(normalize(start)).lt(normalize(end)).into_stream())
+    }
+
+    // Entries whose stored key is accepted by `automaton`, in key order
+    pub fn search_automaton<A: Automaton>(&self, automaton: A) -> Vec<TermId> {
+        let map = self.map();
+        self.collect(map.search(automaton).into_stream())
+    }
+
+    // Same semantics as `Trie::search_fuzzy`
+    pub fn search_fuzzy(&self, query: &str, max_distance: u32) -> Result<Vec<(u32, TermId)>, SearchError> {
+        let automaton = Levenshtein::new(&normalize_query(query)?, max_distance);
+        let map = self.map();
+        let mut stream = map.search_with_state(&automaton).into_stream();
+        let mut results = Vec::new();
+        while let Some((_, slot, state)) = stream.next() {
+            if let Some(distance) = state.and_then(|state| state.distance(&automaton)) {
+                results.extend(self.ids(slot).map(|id| (distance, id)));
+            }
+        }
+        results.sort_unstable();
+        Ok(results)
+    }
+
+    // Entries whose whole key matches `pattern`, in key order
+    pub fn search_wildcard(&self, pattern: &Pattern) -> Vec<TermId> {
+        self.search_automaton(pattern.automaton(false))
+    }
+
+    // Entries stored under exactly this key
+    pub fn get(&self, key: &str) -> Vec<TermId> {
+        self.map()
+            .get(normalize(key))
+            .map_or_else(Vec::new, |slot| self.ids(slot).collect())
+    }
+
+    // Counts of a key in the form it is stored in
+    pub(crate) fn stats_stored(&self, key: &str) -> KeyStats {
+        let Some(slot) = self.map().get(key) else {
+            return KeyStats::default();
+        };
+        let counts = self.file.u64s(COUNTS);
+        KeyStats {
+            term_frequency: counts.get(slot as usize * 2).unwrap_or_default(),
+            line_frequency: counts.get(slot as usize * 2 + 1).unwrap_or_default(),
+        }
+    }
+
+    // Every key as it is stored, in order
+    pub(crate) fn for_each_stored(&self, visit: &mut dyn FnMut(&str, &[TermId], KeyStats)) {
+        let map = self.map();
+        let mut stream = map.stream();
+        let counts = self.file.u64s(COUNTS);
+        while let Some((key, slot)) = stream.next() {
+            let key = std::str::from_utf8(key).unwrap_or_default();
+            let ids: Vec<TermId> = self.ids(slot).collect();
+            let stats = KeyStats {
+                term_frequency: counts.get(slot as usize * 2).unwrap_or_default(),
+                line_frequency: counts.get(slot as usize * 2 + 1).unwrap_or_default(),
+            };
+            visit(key, &ids, stats);
+        }
+    }
+
+    // Number of distinct keys stored
+    pub fn vocabulary_size(&self) -> usize {
+        self.map().len()
+    }
+
+    // Size of the transducer itself, without the id groups
+    pub fn fst_bytes(&self) -> usize {
+        self.file.section(FST).len()
+    }
+}
+
+impl SearchStructure for FstDictionary {
+    fn search(&self, _pool: &dyn TermLookup, query: &str) -> Result<Vec<TermId>, SearchError> {
+        FstDictionary::search(self, query.to_string())
+    }
+
+    fn search_each(
+        &self,
+        _pool: &dyn TermLookup,
+        query: &str,
+        visit: &mut dyn FnMut(TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        FstDictionary::search_each(self, query, visit)
+    }
+
+    fn search_by_length(
+        &self,
+        _pool: &dyn TermLookup,
+        query: &str,
+        visit: &mut dyn FnMut(usize, TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        FstDictionary::search_by_length(self, query, visit)
+    }
+
+    fn search_fuzzy(&self, query: &str, max_distance: u32) -> Result<Option<Vec<(u32, TermId)>>, SearchError> {
+        FstDictionary::search_fuzzy(self, query, max_distance).map(Some)
+    }
+
+    fn search_wildcard(
+        &self,
+        _pool: &dyn TermLookup,
+        pattern: &Pattern,
+    ) -> Result<Option<Vec<TermId>>, SearchError> {
+        Ok(Some(FstDictionary::search_wildcard(self, pattern)))
+    }
+
+    fn len(&self) -> usize {
+        self.vocabulary_size()
+    }
+
+    // Keys come in order
+    fn for_each_key(&self, visit: &mut dyn FnMut(&str, &[TermId], KeyStats)) {
+        self.for_each_stored(visit);
+    }
+
+    fn stats(&self, key: &str) -> KeyStats {
+        self.stats_stored(&normalize(key))
+    }
+}
+
+// Opening checks the transducer and the slots up front, so a damaged file
+// fails to load instead of failing the first query
+impl Mapped for FstDictionary {
+    const KIND: Kind = Kind::Dictionary;
+
+    fn open(file: MappedFile) -> Result<Self, FormatError> {
+        if file.section_count() < SECTIONS {
+            return Err(FormatError::Layout("dictionary sections are missing"));
+        }
+        let keys = Map::new(file.section(FST))?.len();
+        if file.u32s(GROUPS).len() != keys + 1 || file.u64s(COUNTS).len() != keys * 2 {
+            return Err(FormatError::Layout("dictionary keys and slots disagree"));
+        }
+        Ok(Self { file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapped::dictionary::MappedDictionary;
+    use crate::pool::StringPool;
+    use crate::trees::trie::Trie;
+
+    const WORDS: [&str; 6] = ["car", "Cart", "carton", "cat", "dog", "card"];
+
+    fn dictionary() -> (StringPool, FstDictionary) {
+        let mut pool = StringPool::new();
+        let mut builder = FstDictionary::builder();
+        for word in WORDS {
+            builder.store(word, pool.intern(word).unwrap());
+        }
+        (pool, builder.build().unwrap())
+    }
+
+    #[test]
+    fn searches_prefixes_without_the_key_itself() {
+        let (pool, dictionary) = dictionary();
+        let found = |prefix: &str| pool.resolve(&dictionary.search(prefix.to_string()).unwrap());
+        assert_eq!(found("car"), ["card", "Cart", "carton"]);
+        assert_eq!(found("CA"), ["car", "card", "Cart", "carton", "cat"]);
+        assert!(found("cart").contains(&"carton".to_string()));
+        assert!(found("dog").is_empty());
+        assert!(dictionary.search(" ".to_string()).is_err());
+        assert_eq!(dictionary.vocabulary_size(), WORDS.len());
+    }
+
+    #[test]
+    fn search_is_search_each_collected() {
+        let (_, dictionary) = dictionary();
+        for prefix in ["", "c", "car", "cart", "x"] {
+            let Ok(all) = dictionary.search(prefix.to_string()) else {
+                continue;
+            };
+            let mut visited = Vec::new();
+            dictionary
+                .search_each(prefix, &mut |id| {
+                    visited.push(id);
+                    ControlFlow::Continue(())
+                })
+                .unwrap();
+            assert_eq!(visited, all, "{prefix}");
+
+            // Breaking stops right there
+            let mut first = Vec::new();
+            dictionary
+                .search_each(prefix, &mut |id| {
+                    first.push(id);
+                    ControlFlow::Break(())
+                })
+                .unwrap();
+            assert_eq!(first, all.iter().copied().take(1).collect::<Vec<_>>(), "{prefix}");
+        }
+    }
+
+    #[test]
+    fn gets_ranges_and_fuzzy_matches() {
+        let (pool, dictionary) = dictionary();
+        assert_eq!(pool.resolve(&dictionary.get("CART")), ["Cart"]);
+        assert!(dictionary.get("ca").is_empty());
+        assert_eq!(pool.resolve(&dictionary.range("card", "cat")), ["card", "Cart", "carton"]);
+
+        let fuzzy = dictionary.search_fuzzy("cot", 1).unwrap();
+        let ids: Vec<TermId> = fuzzy.iter().map(|(_, id)| *id).collect();
+        assert_eq!(pool.resolve(&ids), ["cat"]);
+        assert_eq!(fuzzy[0].0, 1);
+    }
+
+    #[test]
+    fn mapped_dictionary_is_built_on_it() {
+        let mut pool = StringPool::new();
+        let mut trie = Trie::new();
+        for (line, word) in WORDS.iter().enumerate() {
+            trie.store(word, pool.intern(word).unwrap(), line as u64);
+        }
+        let bytes = MappedDictionary::write_trie(&trie).unwrap();
+        let mapped = MappedDictionary::from_bytes(bytes.clone()).unwrap();
+        // The mapped file opens as a plain dictionary too, keeping its counts
+        let plain = FstDictionary::from_bytes(bytes).unwrap();
+        let (_, built) = dictionary();
+
+        for prefix in ["c", "car", "cart", "d"] {
+            let expected = built.search(prefix.to_string()).unwrap();
+            assert_eq!(mapped.search(prefix.to_string()).unwrap(), expected, "{prefix}");
+            assert_eq!(plain.search(prefix.to_string()).unwrap(), expected, "{prefix}");
+            assert_eq!(mapped.dictionary().search(prefix.to_string()).unwrap(), expected, "{prefix}");
+        }
+        assert_eq!(SearchStructure::stats(&plain, "cart").term_frequency, 1);
+        assert_eq!(SearchStructure::stats(&built, "cart").term_frequency, 0);
+        assert!(MappedDictionary::from_bytes(MappedWriter::new(Kind::Dictionary).finish()).is_err());
+    }
+}
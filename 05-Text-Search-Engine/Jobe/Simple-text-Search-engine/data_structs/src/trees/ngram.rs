@@ -1,13 +1,32 @@
-use std::{cmp::Ordering,collections::{HashMap, HashSet}};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use unicode_segmentation::UnicodeSegmentation;
 use bincode::{Encode,Decode};
 
+use crate::error::SearchError;
+use crate::pool::{TermId, TermLookup};
+use crate::regex_query::RegexQuery;
+use crate::structure::{BuildStructure, KeyStats, SearchStructure};
+use crate::text::{normalize, normalize_query};
+use crate::trees::suffix_array::strictly_inside;
+use crate::wildcard::{Pattern, WordPattern};
+
 #[derive(Encode, Decode, Debug, Clone)]
 pub struct NGramIndex {
-    grams: Option<HashMap<Vec<String>, Vec<usize>>>,
-    words: Option<Vec<String>>,
-    gram_size: usize,
+    pub(crate) tables: Vec<GramTable>, // one per gram size, smallest first
     pub search_type: SearchScopeNgram,
+    // Entries shorter than the smallest gram, ascending; no gram lists them,
+    // so short queries check them one by one
+    pub(crate) short: Vec<TermId>,
+    // Stores of each entry not taken back; an entry keeps its postings until
+    // the last one goes
+    counts: HashMap<TermId, u64>,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub(crate) struct GramTable {
+    pub(crate) gram_size: usize,
+    pub(crate) grams: HashMap<Vec<String>, Vec<TermId>>,
 }
 
 #[derive(Encode, Decode, Debug, Clone)]
@@ -22,128 +41,624 @@ impl Default for NGramIndex {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct NGramIndexBuilder {
+    search_type: SearchScopeNgram,
+    gram_sizes: Vec<usize>,
+}
+
+impl NGramIndexBuilder {
+    pub fn scope(mut self, search_type: SearchScopeNgram) -> Self {
+        self.search_type = search_type;
+        self
+    }
+
+    pub fn gram_size(self, gram_size: usize) -> Self {
+        self.gram_sizes(&[gram_size])
+    }
+
+    // Several sizes build one table each; queries use the largest size that
+    // fits them, so e.g. bigram + trigram keeps short queries cheap while
+    // longer ones get the more selective trigram lists
+    pub fn gram_sizes(mut self, gram_sizes: &[usize]) -> Self {
+        self.gram_sizes = gram_sizes.to_vec();
+        self
+    }
+
+    pub fn build(self) -> NGramIndex {
+        let mut gram_sizes: Vec<usize> = self.gram_sizes.into_iter().filter(|size| *size > 0).collect();
+        if gram_sizes.is_empty() {
+            // Nothing usable was picked, fall back to the scope's default:
+            // characters are cheap to gram so words get bigrams and
+            // trigrams, lines stick to word pairs
+            gram_sizes = match self.search_type {
+                SearchScopeNgram::Words => vec![2, 3],
+                SearchScopeNgram::Lines => vec![2],
+            };
+        }
+        gram_sizes.sort_unstable();
+        gram_sizes.dedup();
+
+        NGramIndex {
+            tables: gram_sizes
+                .into_iter()
+                .map(|gram_size| GramTable {
+                    gram_size,
+                    grams: HashMap::new(),
+                })
+                .collect(),
+            search_type: self.search_type,
+            short: Vec::new(),
+            counts: HashMap::new(),
+        }
+    }
+}
+
+// Intersects two ascending, duplicate-free posting lists
+pub(crate) fn intersect(left: &[TermId], right: &[TermId]) -> Vec<TermId> {
+    let mut result = Vec::with_capacity(left.len().min(right.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < left.len() && j < right.len() {
+        match left[i].cmp(&right[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                result.push(left[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    result
+}
+
+// Intersects every list, rarest first; `None` when there are no lists
+pub(crate) fn intersect_all<L: AsRef<[TermId]>>(mut lists: Vec<L>) -> Option<Vec<TermId>> {
+    lists.sort_by_key(|list| list.as_ref().len());
+    let (first, rest) = lists.split_first()?;
+    let mut result = first.as_ref().to_vec();
+    for list in rest.iter() {
+        if result.is_empty() {
+            break;
+        }
+        result = intersect(&result, list.as_ref());
+    }
+    Some(result)
+}
+
+// The units grams are made of: graphemes (visible characters) for words,
+// words for lines
+pub(crate) fn units(search_type: &SearchScopeNgram, normalized: &str) -> Vec<String> {
+    match search_type {
+        SearchScopeNgram::Words => normalized.graphemes(true).map(str::to_string).collect(),
+        SearchScopeNgram::Lines => normalized.unicode_words().map(str::to_string).collect(),
+    }
+}
+
+// The grams of one size and their posting lists, whether in memory or
+// mapped; every search goes through this
+pub(crate) trait GramLookup {
+    fn gram_size(&self) -> usize;
+
+    // The entries holding `gram`, ascending; `None` for a gram no entry has
+    fn postings(&self, gram: &[String]) -> Option<Cow<'_, [TermId]>>;
+
+    fn for_each_gram(&self, visit: &mut dyn FnMut(&[&str], &[TermId]));
+}
+
+impl GramLookup for GramTable {
+    fn gram_size(&self) -> usize {
+        self.gram_size
+    }
+
+    fn postings(&self, gram: &[String]) -> Option<Cow<'_, [TermId]>> {
+        self.grams.get(gram).map(|list| Cow::Borrowed(list.as_slice()))
+    }
+
+    fn for_each_gram(&self, visit: &mut dyn FnMut(&[&str], &[TermId])) {
+        for (gram, postings) in self.grams.iter() {
+            let gram: Vec<&str> = gram.iter().map(String::as_str).collect();
+            visit(&gram, postings);
+        }
+    }
+}
+
+// An n-gram index as the searches see it, so `NGramIndex` and
+// `MappedNGram` share them
+pub(crate) struct Grams<'a, T> {
+    pub(crate) tables: &'a [T], // smallest gram size first
+    pub(crate) search_type: &'a SearchScopeNgram,
+    pub(crate) short: Cow<'a, [TermId]>, // entries shorter than the smallest gram
+}
+
+impl<T: GramLookup> Grams<'_, T> {
+    fn units(&self, normalized: &str) -> Vec<String> {
+        units(self.search_type, normalized)
+    }
+
+    // The table with the largest gram size that still fits `length` units
+    fn fitting(&self, length: usize) -> Option<&T> {
+        self.tables.iter().rev().find(|table| table.gram_size() <= length)
+    }
+
+    // Entries holding every gram `units` is cut into; one unknown gram rules
+    // out every entry
+    fn holding(table: &T, units: &[String]) -> Vec<TermId> {
+        let mut postings = Vec::new();
+        for gram in units.windows(table.gram_size()) {
+            match table.postings(gram) {
+                Some(list) => postings.push(list),
+                None => return Vec::new(),
+            }
+        }
+        intersect_all(postings).unwrap_or_default()
+    }
+
+    // Entries with a smallest gram holding `units`, for runs too short to
+    // cut into grams
+    fn holding_within(&self, units: &[String]) -> Vec<TermId> {
+        let mut holding = Vec::new();
+        if let Some(smallest) = self.tables.first() {
+            smallest.for_each_gram(&mut |gram, postings| {
+                if gram.windows(units.len()).any(|part| part == units) {
+                    holding.extend_from_slice(postings);
+                }
+            });
+        }
+        holding.sort_unstable();
+        holding.dedup();
+        holding
+    }
+
+    // Entries of a word index whose whole key matches `pattern`. Candidates
+    // come from the grams of its literal runs, each cut with the largest gram
+    // size that fits it; `None` when no literal is a gram long, or for lines.
+    pub(crate) fn search_wildcard(&self, pool: &(impl TermLookup + ?Sized), pattern: &Pattern) -> Option<Vec<TermId>> {
+        if matches!(self.search_type, SearchScopeNgram::Lines) {
+            return None;
+        }
+        let mut postings = Vec::new();
+        for literal in pattern.literals() {
+            let units = self.units(&literal);
+            if let Some(table) = self.fitting(units.len()) {
+                postings.push(Self::holding(table, &units));
+            }
+        }
+        let mut candidates = intersect_all(postings)?;
+
+        // Grams only prove the literals are present, in some order
+        candidates.retain(|id| pattern.is_match(&normalize(pool.get(*id).unwrap_or_default())));
+        Some(candidates)
+    }
+
+    // Entries of a line index whose words match `pattern`. Candidates come
+    // from the grams of its literal word runs; a run shorter than a gram
+    // takes every gram holding it. `None` when the pattern has no literal
+    // word, when it can match lines too short to have any gram, or for words.
+    pub(crate) fn search_word_wildcard(
+        &self,
+        pool: &(impl TermLookup + ?Sized),
+        pattern: &WordPattern,
+    ) -> Option<Vec<TermId>> {
+        let smallest = self.tables.first()?;
+        if matches!(self.search_type, SearchScopeNgram::Words) || pattern.min_words() < smallest.gram_size() {
+            return None;
+        }
+        let postings: Vec<Vec<TermId>> = pattern
+            .literal_runs()
+            .iter()
+            .map(|run| match self.fitting(run.len()) {
+                Some(table) => Self::holding(table, run),
+                None => self.holding_within(run),
+            })
+            .collect();
+        let mut candidates = intersect_all(postings)?;
+
+        // Grams only prove the literal runs are present, in some order
+        candidates.retain(|id| pattern.is_match(&self.units(&normalize(pool.get(*id).unwrap_or_default()))));
+        Some(candidates)
+    }
+
+    // Entries `query` matches. Every clause of literals narrows the
+    // candidates down to the entries holding the grams of one of its
+    // literals; a clause with a literal shorter than any gram is skipped, and
+    // `None` comes back when no clause is left. In a line index only the
+    // words a literal holds whole count, since its first and last word may be
+    // parts of longer ones.
+    pub(crate) fn search_regex(&self, pool: &(impl TermLookup + ?Sized), query: &RegexQuery) -> Option<Vec<TermId>> {
+        let mut postings = Vec::new();
+        'clauses: for clause in query.clauses() {
+            let mut holding = Vec::new();
+            for literal in clause.iter() {
+                let units = self.literal_units(literal);
+                match self.fitting(units.len()) {
+                    Some(table) => holding.extend(Self::holding(table, &units)),
+                    // Too short to look up, so the clause rules nothing out
+                    None => continue 'clauses,
+                }
+            }
+            holding.sort_unstable();
+            holding.dedup();
+            postings.push(holding);
+        }
+        let mut candidates = intersect_all(postings)?;
+
+        // Grams only prove the literals are present
+        candidates.retain(|id| query.is_match(&normalize(pool.get(*id).unwrap_or_default())));
+        Some(candidates)
+    }
+
+    // The units of a regex literal that every entry containing it holds
+    fn literal_units(&self, literal: &str) -> Vec<String> {
+        let mut units = self.units(literal);
+        if matches!(self.search_type, SearchScopeNgram::Lines) {
+            if literal.chars().last().is_some_and(char::is_alphanumeric) {
+                units.pop();
+            }
+            if literal.chars().next().is_some_and(char::is_alphanumeric) && !units.is_empty() {
+                units.remove(0);
+            }
+        }
+        units
+    }
+
+    pub(crate) fn search(&self, pool: &(impl TermLookup + ?Sized), key: &str) -> Result<Vec<TermId>, SearchError> {
+        let query = self.units(&normalize_query(key)?);
+        if query.is_empty() {
+            return Err(SearchError::EmptyQuery);
+        }
+        if self.tables.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let candidates = match self.fitting(query.len()) {
+            Some(table) => Self::holding(table, &query),
+            // Shorter than a gram: every gram holding the query contributes,
+            // and so may any entry too short to have a gram
+            None => {
+                let mut candidates = self.holding_within(&query);
+                candidates.extend_from_slice(&self.short);
+                candidates.sort_unstable();
+                candidates.dedup();
+                candidates
+            }
+        };
+
+        // Grams only prove the pieces are present; check the whole query
+        // sits strictly inside the entry (not at its start or end)
+        Ok(candidates
+            .into_iter()
+            .filter(|id| {
+                let units = self.units(&normalize(pool.get(*id).unwrap_or_default()));
+                units.len() > query.len()
+                    && strictly_inside(&units, &query)
+                    && units.windows(query.len()).any(|part| part == query.as_slice())
+            })
+            .collect())
+    }
+
+    // Every gram, smallest size first, joined back into text
+    pub(crate) fn for_each_key(&self, visit: &mut dyn FnMut(&str, &[TermId], KeyStats)) {
+        let joiner = match self.search_type {
+            SearchScopeNgram::Words => "",
+            SearchScopeNgram::Lines => " ",
+        };
+        for table in self.tables.iter() {
+            table.for_each_gram(&mut |gram, postings| visit(&gram.join(joiner), postings, KeyStats::default()));
+        }
+    }
+}
+
 impl NGramIndex {
 
     pub fn new() -> Self {
-        Self { 
-            grams: None, 
-            words: None, 
-            gram_size: 2, 
-            search_type: SearchScopeNgram::Words
+        Self::builder().build()
+    }
+
+    pub fn builder() -> NGramIndexBuilder {
+        NGramIndexBuilder {
+            search_type: SearchScopeNgram::Words,
+            gram_sizes: Vec::new(),
+        }
+    }
+
+    pub fn gram_sizes(&self) -> Vec<usize> {
+        self.tables.iter().map(|table| table.gram_size).collect()
+    }
+
+    fn units(&self, normalized: &str) -> Vec<String> {
+        units(&self.search_type, normalized)
+    }
+
+    fn grams(&self) -> Grams<'_, GramTable> {
+        Grams {
+            tables: &self.tables,
+            search_type: &self.search_type,
+            short: Cow::Borrowed(&self.short),
         }
     }
 
-    pub fn store(&mut self, key: String) {
-        let ngram = self;
-        let line_segment = key.unicode_words().map(|x| x.to_string()).collect::<Vec<String>>();
+    fn is_short(&self, units: &[String]) -> bool {
+        self.tables.first().is_some_and(|table| units.len() < table.gram_size)
+    }
+
+    pub fn store(&mut self, key: &str, id: TermId) {
+        *self.counts.entry(id).or_default() += 1;
+        let units = self.units(&normalize(key));
+        if self.is_short(&units) {
+            if let Err(position) = self.short.binary_search(&id) {
+                self.short.insert(position, id);
+            }
+        }
 
-        let key_length = match ngram.search_type {
-            SearchScopeNgram::Words => {
-                // For words, we need to count graphemes (visible characters)
-                key.graphemes(true).count()
-            },
-            SearchScopeNgram::Lines => line_segment.len(),
+        for table in self.tables.iter_mut() {
+            for gram in units.windows(table.gram_size) {
+                let postings = table.grams.entry(gram.to_vec()).or_default();
+                // Keep the list sorted and unique; ids mostly arrive in
+                // ascending order, so this is usually an append
+                if let Err(position) = postings.binary_search(&id) {
+                    postings.insert(position, id);
+                }
+            }
+        }
+    }
+
+    // Takes back one `store` of `key` under `id`. Once every store of the
+    // entry has been taken back it leaves the posting list of every gram it
+    // has, and grams no entry holds any more are dropped. False when the
+    // entry was never stored.
+    pub fn remove(&mut self, key: &str, id: TermId) -> bool {
+        let Some(count) = self.counts.get_mut(&id) else {
+            return false;
         };
+        *count -= 1;
+        if *count > 0 {
+            return true;
+        }
+        self.counts.remove(&id);
 
-        if ngram.words.is_none() {
-            let mut new_hash = HashMap::new();
-
-            for index in (ngram.gram_size - 1)..key_length {
-                match ngram.search_type {
-                    SearchScopeNgram::Words => {
-                        // Get graphemes properly
-                        let graphemes: Vec<&str> = key.graphemes(true).collect();
-                        if index + ngram.gram_size > graphemes.len() {
-                            continue;
-                        }
-                        let word_segment = graphemes[index..index + ngram.gram_size].join("");
-                        let keys: Vec<String> = vec![word_segment];
-                        new_hash.insert(keys.clone(), vec![0]);
-                    },
-                    SearchScopeNgram::Lines => {
-                        if index + ngram.gram_size > line_segment.len() {
-                            continue;
-                        }
-                        let keys: Vec<String> = line_segment[index..index + ngram.gram_size].to_vec();
-                        new_hash.insert(keys, vec![0]);
-                    },
+        let units = self.units(&normalize(key));
+        if let Ok(position) = self.short.binary_search(&id) {
+            self.short.remove(position);
+        }
+        for table in self.tables.iter_mut() {
+            for gram in units.windows(table.gram_size) {
+                let Some(postings) = table.grams.get_mut(gram) else {
+                    continue;
                 };
-            }
-            ngram.grams = Some(new_hash);
-            ngram.words = Some(vec![key]);
-        } else {
-            for index in (ngram.gram_size - 1)..key_length {
-                match ngram.search_type {
-                    SearchScopeNgram::Words => {
-                        let graphemes: Vec<&str> = key.graphemes(true).collect();
-                        if index + ngram.gram_size > graphemes.len() {
-                            continue;
-                        }
-                        let word_segment = graphemes[index..index + ngram.gram_size].join("");
-                        let keys: Vec<String> = vec![word_segment];
-                        ngram.grams.as_mut().unwrap()
-                            .entry(keys)
-                            .and_modify(|v| v.push(ngram.words.as_ref().unwrap().len()))
-                            .or_insert(vec![ngram.words.as_ref().unwrap().len()]);
-                    },
-                    SearchScopeNgram::Lines => {
-                        if index + ngram.gram_size > line_segment.len() {
-                            continue;
-                        }
-                        let keys: Vec<String> = line_segment[index..index + ngram.gram_size].to_vec();
-                        ngram.grams.as_mut().unwrap()
-                            .entry(keys)
-                            .and_modify(|v| v.push(ngram.words.as_ref().unwrap().len()))
-                            .or_insert(vec![ngram.words.as_ref().unwrap().len()]);
-                    },
+                if let Ok(position) = postings.binary_search(&id) {
+                    postings.remove(position);
+                }
+                if postings.is_empty() {
+                    table.grams.remove(gram);
                 }
             }
-            ngram.words.as_mut().unwrap().push(key);
         }
+        true
     }
 
-    pub fn search(&self, key: String) -> Result<Vec<String>, &str> {
-        let ngram = self;
-        let mut results = HashSet::new(); 
+    // See `Grams::search_wildcard`
+    pub fn search_wildcard(&self, pool: &(impl TermLookup + ?Sized), pattern: &Pattern) -> Option<Vec<TermId>> {
+        self.grams().search_wildcard(pool, pattern)
+    }
 
-        for (key_in_gram, values) in ngram.grams.as_ref().unwrap().iter() {
-            let condition = match ngram.search_type {
-                SearchScopeNgram::Words => {
-                    match key.len().cmp(&ngram.gram_size) {
-                        Ordering::Less => key_in_gram.iter().any(|x| x.contains(&key)),
-                        Ordering::Equal => key_in_gram.iter().any(|x| x == &key),
-                        Ordering::Greater => false
-                    }
-                },
-                SearchScopeNgram::Lines => {
-                    let key_parts: Vec<&str> = key.split_whitespace().collect();
-                    match key_parts.len().cmp(&ngram.gram_size) {
-                        Ordering::Less => {
-                            key_in_gram.iter().any(|word| word == &key)
-                        },
-                        Ordering::Equal => {
-                            let joined = key_in_gram.join(" ");
-                            joined == key
-                        },
-                        Ordering::Greater => false
-                    }
-                },
-            };
-            if condition {
-                for value in values.iter() {
-                    if let Some(word) = ngram.words.as_ref().unwrap().get(*value) {
-                        if !word.starts_with(&key) && !word.ends_with(&key) && word.contains(&key) {
-                            results.insert(word.clone()); // HashSet automatically handles duplicates
-                        }
+    // See `Grams::search_word_wildcard`
+    pub fn search_word_wildcard(&self, pool: &(impl TermLookup + ?Sized), pattern: &WordPattern) -> Option<Vec<TermId>> {
+        self.grams().search_word_wildcard(pool, pattern)
+    }
+
+    // See `Grams::search_regex`
+    pub fn search_regex(&self, pool: &(impl TermLookup + ?Sized), query: &RegexQuery) -> Option<Vec<TermId>> {
+        self.grams().search_regex(pool, query)
+    }
+
+    pub fn search(&self, pool: &(impl TermLookup + ?Sized), key: String) -> Result<Vec<TermId>, SearchError> {
+        self.grams().search(pool, &key)
+    }
+}
+
+impl SearchStructure for NGramIndex {
+    fn search(&self, pool: &dyn TermLookup, query: &str) -> Result<Vec<TermId>, SearchError> {
+        NGramIndex::search(self, pool, query.to_string())
+    }
+
+    fn search_wildcard(
+        &self,
+        pool: &dyn TermLookup,
+        pattern: &Pattern,
+    ) -> Result<Option<Vec<TermId>>, SearchError> {
+        Ok(NGramIndex::search_wildcard(self, pool, pattern))
+    }
+
+    fn search_word_wildcard(
+        &self,
+        pool: &dyn TermLookup,
+        pattern: &WordPattern,
+    ) -> Result<Option<Vec<TermId>>, SearchError> {
+        Ok(NGramIndex::search_word_wildcard(self, pool, pattern))
+    }
+
+    fn search_regex(&self, pool: &dyn TermLookup, query: &RegexQuery) -> Result<Option<Vec<TermId>>, SearchError> {
+        Ok(NGramIndex::search_regex(self, pool, query))
+    }
+
+    // Grams of every size together
+    fn len(&self) -> usize {
+        self.tables.iter().map(|table| table.grams.len()).sum()
+    }
+
+    // Grams come smallest size first, in no particular order within a size
+    fn for_each_key(&self, visit: &mut dyn FnMut(&str, &[TermId], KeyStats)) {
+        self.grams().for_each_key(visit);
+    }
+
+    // Grams are not counted
+    fn stats(&self, _key: &str) -> KeyStats {
+        KeyStats::default()
+    }
+
+    fn describe(&self) -> String {
+        format!("NGramIndex with gram sizes {:?}", self.gram_sizes())
+    }
+}
+
+impl BuildStructure for NGramIndex {
+    fn store(&mut self, key: &str, id: TermId, _line: u64) {
+        NGramIndex::store(self, key, id);
+    }
+
+    fn remove(&mut self, key: &str, id: TermId, _line: u64) -> bool {
+        NGramIndex::remove(self, key, id)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mapped::ngram::MappedNGram;
+    use crate::mapped::Mapped;
+    use crate::pool::StringPool;
+    use crate::structure::SearchStructure;
+
+    fn index(entries: &[&str]) -> (StringPool, NGramIndex) {
+        index_with(SearchScopeNgram::Words, &[4], entries)
+    }
+
+    fn index_with(scope: SearchScopeNgram, gram_sizes: &[usize], entries: &[&str]) -> (StringPool, NGramIndex) {
+        let mut pool = StringPool::new();
+        let mut ngram = NGramIndex::builder().scope(scope).gram_sizes(gram_sizes).build();
+        for entry in entries {
+            let id = pool.intern(entry).unwrap();
+            ngram.store(entry, id);
+        }
+        (pool, ngram)
+    }
+
+    #[test]
+    fn intersects_posting_lists() {
+        assert_eq!(intersect(&[1, 3, 5, 7], &[2, 3, 4, 7, 9]), [3, 7]);
+        assert!(intersect(&[], &[1, 2]).is_empty());
+        assert_eq!(intersect_all(Vec::<Vec<TermId>>::new()), None);
+        assert_eq!(intersect_all(vec![vec![1, 2, 3, 4], vec![2, 4], vec![0, 2, 3, 4, 5]]), Some(vec![2, 4]));
+        assert_eq!(intersect_all(vec![vec![1, 2], vec![], vec![2]]), Some(vec![]));
+    }
+
+    #[test]
+    fn finds_queries_of_every_length() {
+        let words = ["banana", "bandana", "cabana", "ananas", "nab", "an", "b"];
+        let (pool, ngram) = index(&words);
+        let mapped = MappedNGram::from_bytes(MappedNGram::write(&ngram).unwrap()).unwrap();
+        // Shorter than a gram, one gram, and cut into several grams
+        for word in words {
+            for start in 0..word.len() {
+                for end in start + 1..=word.len() {
+                    let query = &word[start..end];
+                    let expected: Vec<&str> = words
+                        .into_iter()
+                        .filter(|word| word.len() > query.len() && word[1..word.len() - 1].contains(query))
+                        .filter(|word| !word.starts_with(query) && !word.ends_with(query))
+                        .collect();
+                    for structure in [&ngram as &dyn SearchStructure, &mapped] {
+                        let found = pool.resolve(&structure.search(&pool, query).unwrap());
+                        assert_eq!(found, expected, "{query} in {}", structure.describe());
                     }
                 }
             }
         }
+    }
+
+    #[test]
+    fn picks_gram_sizes_per_scope() {
+        assert_eq!(NGramIndex::builder().scope(SearchScopeNgram::Words).build().gram_sizes(), [2, 3]);
+        assert_eq!(NGramIndex::builder().scope(SearchScopeNgram::Lines).build().gram_sizes(), [2]);
+        let ngram = NGramIndex::builder().gram_sizes(&[5, 0, 2, 5, 3]).build();
+        assert_eq!(ngram.gram_sizes(), [2, 3, 5]);
+    }
 
-        if results.is_empty() {
-            Err("couldn't find a match mate")
-        } else {
-            Ok(results.into_iter().collect()) 
+    #[test]
+    fn gram_sizes_agree_on_words() {
+        let words = ["banana", "bandana", "cabana", "ananas", "nab", "an", "b", "bananarama"];
+        let (pool, reference) = index(&words);
+        for gram_sizes in [&[1][..], &[2], &[3], &[2, 3], &[2, 3, 5]] {
+            let (_, ngram) = index_with(SearchScopeNgram::Words, gram_sizes, &words);
+            let mapped = MappedNGram::from_bytes(MappedNGram::write(&ngram).unwrap()).unwrap();
+            for query in ["a", "n", "an", "nan", "anan", "nana", "anana", "narama", "ab", "zz"] {
+                let expected = reference.search(&pool, query.to_string()).unwrap();
+                for structure in [&ngram as &dyn SearchStructure, &mapped] {
+                    let found = structure.search(&pool, query).unwrap();
+                    assert_eq!(found, expected, "{query} with {gram_sizes:?} in {}", structure.describe());
+                }
+            }
         }
     }
+
+    #[test]
+    fn searches_lines_by_whole_words() {
+        let lines = ["the quick brown fox", "a quick brown dog jumps", "quick brown", "brown", "brownie points"];
+        for gram_sizes in [&[2][..], &[2, 3], &[3]] {
+            let (pool, ngram) = index_with(SearchScopeNgram::Lines, gram_sizes, &lines);
+            let mapped = MappedNGram::from_bytes(MappedNGram::write(&ngram).unwrap()).unwrap();
+            for structure in [&ngram as &dyn SearchStructure, &mapped] {
+                let search = |query: &str| pool.resolve(&structure.search(&pool, query).unwrap());
+                let both = ["the quick brown fox", "a quick brown dog jumps"];
+                assert_eq!(search("quick brown"), both, "{gram_sizes:?}");
+                assert_eq!(search("QUICK \t brown"), both);
+                assert_eq!(search("brown"), both);
+                assert_eq!(search("quick brown dog"), ["a quick brown dog jumps"]);
+                // Units are whole words, and the line's own ends don't count
+                assert!(search("quick bro").is_empty());
+                assert!(search("the quick").is_empty());
+                assert!(search("points").is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn matches_word_wildcards_in_lines() {
+        let lines = ["the quick brown fox", "a quick red fox", "quick fox", "the slow brown dog", "fox quick"];
+        for gram_sizes in [&[2][..], &[2, 3]] {
+            let (pool, ngram) = index_with(SearchScopeNgram::Lines, gram_sizes, &lines);
+            let mapped = MappedNGram::from_bytes(MappedNGram::write(&ngram).unwrap()).unwrap();
+            for structure in [&ngram as &dyn SearchStructure, &mapped] {
+                let name = structure.describe();
+                let search = |pattern: &str| {
+                    let pattern = WordPattern::parse(pattern).unwrap();
+                    structure.search_word_wildcard(&pool, &pattern).unwrap().map(|ids| pool.resolve(&ids))
+                };
+                assert_eq!(search("quick * fox").unwrap(), ["the quick brown fox", "a quick red fox"], "{name}");
+                assert_eq!(
+                    search("quick ** fox").unwrap(),
+                    ["the quick brown fox", "a quick red fox", "quick fox"],
+                    "{name}"
+                );
+                assert_eq!(search("* slow brown *").unwrap(), ["the slow brown dog"], "{name}");
+                assert_eq!(search("the ** b?own").unwrap(), ["the quick brown fox", "the slow brown dog"], "{name}");
+                // Too few words for a gram, so the caller has to check every line
+                assert_eq!(search("fox"), None, "{name}");
+            }
+        }
+
+        // A word index has no words to match
+        let (pool, ngram) = index(&["quick"]);
+        assert_eq!(ngram.search_word_wildcard(&pool, &WordPattern::parse("quick ** fox").unwrap()), None);
+    }
+
+    #[test]
+    fn finds_entries_shorter_than_a_gram() {
+        let (pool, ngram) = index(&["abc", "abcdef"]);
+        let mapped = MappedNGram::from_bytes(MappedNGram::write(&ngram).unwrap()).unwrap();
+        for structure in [&ngram as &dyn SearchStructure, &mapped] {
+            let found = pool.resolve(&structure.search(&pool, "b").unwrap());
+            assert_eq!(found, ["abc", "abcdef"], "{}", structure.describe());
+        }
+    }
+
+    #[test]
+    fn removes_short_entries() {
+        let (pool, mut ngram) = index(&["abc", "abcdef"]);
+        assert!(ngram.remove("abc", 0));
+        assert!(!ngram.remove("abc", 0));
+        assert_eq!(pool.resolve(&ngram.search(&pool, "b".to_string()).unwrap()), ["abcdef"]);
+    }
 }
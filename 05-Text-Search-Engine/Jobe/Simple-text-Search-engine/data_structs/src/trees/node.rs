@@ -0,0 +1,258 @@
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
+use bincode::{Decode, Encode};
+
+use crate::pool::TermId;
+use crate::structure::KeyStats;
+use crate::trees::keep_top;
+
+// Every `store` of one key that has not been taken back, counted per id and
+// per line, so a removal knows when an id or a line leaves the key without
+// looking at anything else that was stored
+#[derive(Clone, Encode, Decode, Debug, Default)]
+pub(crate) struct Occurrences {
+    ids: Vec<TermId>,          // distinct pool entries that normalize to the key
+    id_counts: Vec<u64>,       // occurrences of each of `ids`
+    lines: BTreeMap<u64, u64>, // occurrences on each line the key is on
+}
+
+impl Occurrences {
+    pub(crate) fn add(&mut self, id: TermId, line: u64) {
+        match self.ids.iter().position(|other| *other == id) {
+            Some(index) => self.id_counts[index] += 1,
+            None => {
+                self.ids.push(id);
+                self.id_counts.push(1);
+            }
+        }
+        *self.lines.entry(line).or_default() += 1;
+    }
+
+    // Takes back one `add`: `None` when `id` was never added on `line`,
+    // otherwise whether that was the last occurrence of `id`
+    pub(crate) fn take(&mut self, id: TermId, line: u64) -> Option<bool> {
+        let index = self.ids.iter().position(|other| *other == id)?;
+        let on_line = self.lines.get_mut(&line)?;
+        *on_line -= 1;
+        if *on_line == 0 {
+            self.lines.remove(&line);
+        }
+        self.id_counts[index] -= 1;
+        let last_of_id = self.id_counts[index] == 0;
+        if last_of_id {
+            self.ids.remove(index);
+            self.id_counts.remove(index);
+        }
+        Some(last_of_id)
+    }
+
+    pub(crate) fn ids(&self) -> &[TermId] {
+        &self.ids
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.id_counts.iter().sum()
+    }
+
+    pub(crate) fn stats(&self) -> KeyStats {
+        KeyStats {
+            term_frequency: self.count(),
+            line_frequency: self.lines.len() as u64,
+        }
+    }
+}
+
+// A node of the character trees: `Trie` keys its edges by character,
+// `SuffixTree` by grapheme, last one first. Everything that does not depend
+// on how a key is split into edges lives here.
+#[derive(Clone, Encode, Decode, Debug)]
+pub(crate) struct Node<K: Ord> {
+    pub(crate) children: BTreeMap<K, Node<K>>, // sparse, keyed by edge
+    pub(crate) is_terminal: bool,
+    occurrences: Occurrences,
+    top: Vec<TermId>, // most frequent completions below this node, filled by `rank_top`
+}
+
+impl<K: Ord> Default for Node<K> {
+    fn default() -> Self {
+        Self {
+            children: BTreeMap::new(),
+            is_terminal: false,
+            occurrences: Occurrences::default(),
+            top: Vec::new(),
+        }
+    }
+}
+
+impl<K: Ord> Node<K> {
+    pub(crate) fn store(&mut self, path: impl IntoIterator<Item = K>, id: TermId, line: u64) {
+        let mut node = self;
+
+        // If the node for an edge doesn't exist, create a new one, then move
+        // to it
+        for edge in path {
+            node = node.children.entry(edge).or_default();
+        }
+
+        // When we've traversed the whole key, mark this as a terminal node
+        // and count the occurrence
+        node.occurrences.add(id, line);
+        node.is_terminal = true;
+    }
+
+    // Distinct pool entries that normalize to the key ending here
+    pub(crate) fn ids(&self) -> &[TermId] {
+        self.occurrences.ids()
+    }
+
+    // Takes back one `store` of the key at `path` under `id` on `line`: the
+    // id leaves the key once it no longer occurs under it, and nodes left
+    // with neither a key nor children are pruned. Returns false when the key
+    // was never stored with that id on that line. Precomputed completions
+    // lose ids that are gone but are not re-ranked.
+    pub(crate) fn remove<'q, Q>(&mut self, path: impl Iterator<Item = &'q Q>, id: TermId, line: u64) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized + 'q,
+    {
+        self.take(path, id, line).is_some()
+    }
+
+    // `remove`, telling whether it was the last occurrence of the id
+    fn take<'q, Q>(&mut self, mut path: impl Iterator<Item = &'q Q>, id: TermId, line: u64) -> Option<bool>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized + 'q,
+    {
+        let last_of_id = match path.next() {
+            None => {
+                let last_of_id = self.occurrences.take(id, line)?;
+                self.is_terminal = !self.occurrences.is_empty();
+                last_of_id
+            }
+            Some(edge) => {
+                let child = self.children.get_mut(edge)?;
+                let last_of_id = child.take(path, id, line)?;
+                if child.is_unused() {
+                    self.children.remove(edge);
+                }
+                last_of_id
+            }
+        };
+        if last_of_id {
+            self.top.retain(|top| *top != id);
+        }
+        Some(last_of_id)
+    }
+
+    fn is_unused(&self) -> bool {
+        !self.is_terminal && self.children.is_empty()
+    }
+
+    // Node at the end of `path`, key or not
+    pub(crate) fn descend<'q, Q>(&self, path: impl IntoIterator<Item = &'q Q>) -> Option<&Self>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized + 'q,
+    {
+        let mut node = self;
+        for edge in path {
+            node = node.children.get(edge)?;
+        }
+        Some(node)
+    }
+
+    // Node holding exactly the key at `path`, if it was ever stored
+    pub(crate) fn find<'q, Q>(&self, path: impl IntoIterator<Item = &'q Q>) -> Option<&Self>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized + 'q,
+    {
+        self.descend(path).filter(|node| node.is_terminal)
+    }
+
+    // Hands the ids of every key strictly below this node to `visit`, in
+    // edge order, until it breaks
+    pub(crate) fn visit_below(&self, visit: &mut dyn FnMut(TermId) -> ControlFlow<()>) -> ControlFlow<()> {
+        for child in self.children.values() {
+            if child.is_terminal {
+                for id in child.ids() {
+                    visit(*id)?;
+                }
+            }
+            child.visit_below(visit)?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    // `visit_below` one level at a time, so shorter keys come first, each
+    // with how many edges below this node it is
+    pub(crate) fn visit_by_length(&self, visit: &mut dyn FnMut(usize, TermId) -> ControlFlow<()>) {
+        let mut level: Vec<&Self> = self.children.values().collect();
+        let mut depth = 1;
+        while !level.is_empty() {
+            for node in level.iter().filter(|node| node.is_terminal) {
+                for id in node.ids() {
+                    if visit(depth, *id).is_break() {
+                        return;
+                    }
+                }
+            }
+            level = level.iter().flat_map(|node| node.children.values()).collect();
+            depth += 1;
+        }
+    }
+
+    // Visits every node with the edges leading to it
+    pub(crate) fn walk<'a>(&'a self, path: &mut Vec<&'a K>, visit: &mut dyn FnMut(&[&'a K], &'a Self)) {
+        visit(path, self);
+        for (edge, child) in self.children.iter() {
+            path.push(edge);
+            child.walk(path, visit);
+            path.pop();
+        }
+    }
+
+    pub(crate) fn stats(&self) -> KeyStats {
+        self.occurrences.stats()
+    }
+
+    // Precomputed completions below this node, best first
+    pub(crate) fn top(&self) -> &[TermId] {
+        &self.top
+    }
+
+    // Precomputes, for this node and every one below, the `k` most frequent
+    // keys strictly below it. Returns this subtree's `k` most frequent keys,
+    // its own key included.
+    pub(crate) fn rank_top(&mut self, k: usize) -> Vec<(u64, TermId)> {
+        let mut best: Vec<(u64, TermId)> = self
+            .children
+            .values_mut()
+            .flat_map(|child| child.rank_top(k))
+            .collect();
+        keep_top(&mut best, k);
+        self.top = best.iter().map(|(_, id)| *id).collect();
+
+        if self.is_terminal {
+            let count = self.occurrences.count();
+            best.extend(self.ids().iter().map(|id| (count, *id)));
+            keep_top(&mut best, k);
+        }
+        best
+    }
+
+    pub(crate) fn node_count(&self) -> usize {
+        1 + self.children.values().map(Self::node_count).sum::<usize>()
+    }
+
+    // Number of distinct keys at and below this node
+    pub(crate) fn vocabulary_size(&self) -> usize {
+        usize::from(self.is_terminal) + self.children.values().map(Self::vocabulary_size).sum::<usize>()
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
+use bincode::{Decode, Encode};
+
+use crate::error::SearchError;
+use crate::pool::{TermId, TermLookup};
+use crate::structure::{BuildStructure, KeyStats, SearchStructure};
+use crate::text::{normalize, normalize_query};
+use crate::trees::node::Occurrences;
+use crate::wildcard::Pattern;
+
+// Path-compressed (Patricia) variant of `Trie`: every edge carries a whole
+// run of characters instead of one, so chains of single-child nodes collapse
+// into a single node. Children are keyed by the first character of their
+// label, which is unique among siblings.
+#[derive(Clone, Encode, Decode, Debug, Default)]
+pub struct RadixTrie {
+    label: String,
+    children: BTreeMap<char, RadixTrie>,
+    is_terminal: bool,
+    occurrences: Occurrences,
+}
+
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((index, _), _)| index)
+}
+
+impl RadixTrie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn leaf(label: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            ..Self::default()
+        }
+    }
+
+    pub fn store(&mut self, key: &str, id: TermId, line: u64) {
+        let normalized = normalize(key);
+        let mut node = self;
+        let mut rest = normalized.as_str();
+
+        // Walk down, splitting an edge whenever the key leaves it half way
+        while let Some(first) = rest.chars().next() {
+            let child = node
+                .children
+                .entry(first)
+                .or_insert_with(|| RadixTrie::leaf(rest));
+
+            let shared = common_prefix_len(&child.label, rest);
+            if shared < child.label.len() {
+                // Split: the shared part becomes a new inner node that keeps
+                // the old child (with the remainder of its label) below it
+                let tail = child.label.split_off(shared);
+                let inner = RadixTrie::leaf(&child.label);
+                let mut lower = std::mem::replace(child, inner);
+                lower.label = tail;
+                let lower_first = lower.label.chars().next().unwrap_or_default();
+                child.children.insert(lower_first, lower);
+            }
+
+            node = child;
+            rest = &rest[shared..];
+        }
+
+        // When we've traversed the whole key, mark this as a terminal node
+        // and count the occurrence
+        node.occurrences.add(id, line);
+        node.is_terminal = true;
+    }
+
+    // Takes back one `store` of `key` under `id` on `line`, like
+    // `Trie::remove`. An inner node left with a single child and no key of
+    // its own is merged with that child again, so the tree stays compressed.
+    pub fn remove(&mut self, key: &str, id: TermId, line: u64) -> bool {
+        self.remove_below(&normalize(key), id, line)
+    }
+
+    fn remove_below(&mut self, rest: &str, id: TermId, line: u64) -> bool {
+        let Some(first) = rest.chars().next() else {
+            return self.forget(id, line);
+        };
+        let Some(child) = self.children.get_mut(&first) else {
+            return false;
+        };
+        let Some(remaining) = rest.strip_prefix(child.label.as_str()) else {
+            return false;
+        };
+        if !child.remove_below(remaining, id, line) {
+            return false;
+        }
+
+        if !child.is_terminal && child.children.len() <= 1 {
+            match child.children.pop_first() {
+                None => {
+                    self.children.remove(&first);
+                }
+                Some((_, mut lower)) => {
+                    // Undo the split that once made room for another key
+                    lower.label.insert_str(0, &child.label);
+                    *child = lower;
+                }
+            }
+        }
+        true
+    }
+
+    // Takes back one occurrence of the key ending at this node
+    fn forget(&mut self, id: TermId, line: u64) -> bool {
+        if self.occurrences.take(id, line).is_none() {
+            return false;
+        }
+        self.is_terminal = !self.occurrences.is_empty();
+        true
+    }
+
+    pub fn search(&self, prefix: String) -> Result<Vec<TermId>, SearchError> {
+        let mut results = Vec::new();
+        self.search_each(&prefix, &mut |id| {
+            results.push(id);
+            ControlFlow::Continue(())
+        })?;
+        Ok(results)
+    }
+
+    // Hands every match to `visit`, in key order, until it breaks
+    pub fn search_each(
+        &self,
+        prefix: &str,
+        visit: &mut dyn FnMut(TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        let normalized = normalize_query(prefix)?;
+        let mut node = self;
+        let mut rest = normalized.as_str();
+
+        // Traverse to the end of the prefix, which may stop inside an edge
+        while let Some(first) = rest.chars().next() {
+            let Some(child) = node.children.get(&first) else {
+                return Ok(());
+            };
+
+            if child.label.len() > rest.len() && child.label.starts_with(rest) {
+                // Every word below this edge extends the prefix
+                let _ = Self::visit_words(child, visit);
+                return Ok(());
+            }
+            match rest.strip_prefix(child.label.as_str()) {
+                Some(remaining) => rest = remaining,
+                None => return Ok(()),
+            }
+            node = child;
+        }
+
+        // Words equal to the prefix itself live on this node and are not
+        // part of the result
+        for child in node.children.values() {
+            if Self::visit_words(child, visit).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_words(node: &RadixTrie, visit: &mut dyn FnMut(TermId) -> ControlFlow<()>) -> ControlFlow<()> {
+        if node.is_terminal {
+            for id in node.occurrences.ids() {
+                visit(*id)?;
+            }
+        }
+
+        for child in node.children.values() {
+            Self::visit_words(child, visit)?;
+        }
+        ControlFlow::Continue(())
+    }
+
+    // Same as `Trie::search_wildcard`, stepping through each label in turn
+    pub fn search_wildcard(&self, pattern: &Pattern) -> Vec<TermId> {
+        let mut results = Vec::new();
+        self.walk_wildcard(pattern, pattern.first_positions(), &mut results);
+        results
+    }
+
+    fn walk_wildcard(&self, pattern: &Pattern, mut positions: Vec<usize>, results: &mut Vec<TermId>) {
+        for char in self.label.chars() {
+            positions = pattern.next_positions(&positions, char);
+            if positions.is_empty() {
+                return;
+            }
+        }
+        if self.is_terminal && pattern.positions_match(&positions) {
+            results.extend_from_slice(self.occurrences.ids());
+        }
+        for child in self.children.values() {
+            child.walk_wildcard(pattern, positions.clone(), results);
+        }
+    }
+
+    pub fn node_count(&self) -> usize {
+        1 + self.children.values().map(RadixTrie::node_count).sum::<usize>()
+    }
+
+    // Node holding exactly this key, if it was ever stored
+    fn find(&self, key: &str) -> Option<&RadixTrie> {
+        let normalized = normalize(key);
+        let mut node = self;
+        let mut rest = normalized.as_str();
+        while let Some(first) = rest.chars().next() {
+            node = node.children.get(&first)?;
+            rest = rest.strip_prefix(node.label.as_str())?;
+        }
+        node.is_terminal.then_some(node)
+    }
+
+    pub fn term_frequency(&self, key: &str) -> u64 {
+        self.find(key).map_or(0, |node| node.stats_here().term_frequency)
+    }
+
+    pub fn line_frequency(&self, key: &str) -> u64 {
+        self.find(key).map_or(0, |node| node.stats_here().line_frequency)
+    }
+
+    // Number of distinct (normalized) keys stored
+    pub fn vocabulary_size(&self) -> usize {
+        usize::from(self.is_terminal)
+            + self.children.values().map(RadixTrie::vocabulary_size).sum::<usize>()
+    }
+
+    fn walk(&self, path: &mut String, visit: &mut dyn FnMut(&str, &[TermId], KeyStats)) {
+        path.push_str(&self.label);
+        if self.is_terminal {
+            visit(path, self.occurrences.ids(), self.stats_here());
+        }
+        for child in self.children.values() {
+            child.walk(path, visit);
+        }
+        path.truncate(path.len() - self.label.len());
+    }
+
+    fn stats_here(&self) -> KeyStats {
+        self.occurrences.stats()
+    }
+}
+
+impl SearchStructure for RadixTrie {
+    fn search(&self, _pool: &dyn TermLookup, query: &str) -> Result<Vec<TermId>, SearchError> {
+        RadixTrie::search(self, query.to_string())
+    }
+
+    fn search_each(
+        &self,
+        _pool: &dyn TermLookup,
+        query: &str,
+        visit: &mut dyn FnMut(TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        RadixTrie::search_each(self, query, visit)
+    }
+
+    fn search_wildcard(
+        &self,
+        _pool: &dyn TermLookup,
+        pattern: &Pattern,
+    ) -> Result<Option<Vec<TermId>>, SearchError> {
+        Ok(Some(RadixTrie::search_wildcard(self, pattern)))
+    }
+
+    fn len(&self) -> usize {
+        self.vocabulary_size()
+    }
+
+    // Keys come in order
+    fn for_each_key(&self, visit: &mut dyn FnMut(&str, &[TermId], KeyStats)) {
+        self.walk(&mut String::new(), visit);
+    }
+
+    fn stats(&self, key: &str) -> KeyStats {
+        self.find(key).map(RadixTrie::stats_here).unwrap_or_default()
+    }
+}
+
+impl BuildStructure for RadixTrie {
+    fn store(&mut self, key: &str, id: TermId, line: u64) {
+        RadixTrie::store(self, key, id, line);
+    }
+
+    fn remove(&mut self, key: &str, id: TermId, line: u64) -> bool {
+        RadixTrie::remove(self, key, id, line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::StringPool;
+
+    fn radix(words: &[&str]) -> (StringPool, RadixTrie) {
+        let mut pool = StringPool::new();
+        let mut radix = RadixTrie::new();
+        for (line, word) in words.iter().enumerate() {
+            radix.store(word, pool.intern(word).unwrap(), line as u64);
+        }
+        (pool, radix)
+    }
+
+    fn search(pool: &StringPool, radix: &RadixTrie, prefix: &str) -> Vec<String> {
+        pool.resolve(&radix.search(prefix.to_string()).unwrap())
+    }
+
+    fn keys(radix: &RadixTrie) -> Vec<String> {
+        let mut keys = Vec::new();
+        radix.for_each_key(&mut |key, _, _| keys.push(key.to_string()));
+        keys
+    }
+
+    #[test]
+    fn splits_edges_where_keys_part() {
+        let (pool, radix) = radix(&["team", "tea", "ten", "to", "東京", "東北"]);
+        // root, t, e, a, m, n, o, 東, 京, 北
+        assert_eq!(radix.node_count(), 10);
+        assert_eq!(keys(&radix), ["tea", "team", "ten", "to", "東京", "東北"]);
+        assert_eq!(search(&pool, &radix, "te"), ["tea", "team", "ten"]);
+        assert_eq!(search(&pool, &radix, "tea"), ["team"]);
+        assert_eq!(search(&pool, &radix, "東"), ["東京", "東北"]);
+        assert!(search(&pool, &radix, "tem").is_empty());
+        assert!(search(&pool, &radix, "teams").is_empty());
+    }
+
+    #[test]
+    fn finds_prefixes_ending_inside_an_edge() {
+        let (pool, radix) = radix(&["romane", "romanus"]);
+        assert_eq!(radix.node_count(), 4);
+        assert_eq!(search(&pool, &radix, "ro"), ["romane", "romanus"]);
+        assert_eq!(search(&pool, &radix, "romanu"), ["romanus"]);
+        assert!(search(&pool, &radix, "romanx").is_empty());
+    }
+
+    #[test]
+    fn merges_edges_again_after_removal() {
+        let (mut pool, mut radix) = radix(&["tea", "team", "ten"]);
+        let (ten, tea) = (pool.intern("ten").unwrap(), pool.intern("tea").unwrap());
+
+        // "te" is left with one child and no key, so it joins "a" again
+        assert!(radix.remove("ten", ten, 2));
+        assert_eq!(radix.node_count(), self::radix(&["tea", "team"]).1.node_count());
+        assert_eq!(keys(&radix), ["tea", "team"]);
+        assert_eq!(search(&pool, &radix, "te"), ["tea", "team"]);
+
+        assert!(radix.remove("tea", tea, 0));
+        assert_eq!(radix.node_count(), 2);
+        assert_eq!(keys(&radix), ["team"]);
+        assert_eq!(search(&pool, &radix, "t"), ["team"]);
+        assert!(!radix.remove("tea", tea, 0));
+    }
+}
@@ -1,112 +1,269 @@
+use std::ops::ControlFlow;
 use bincode::{Decode, Encode};
 
-#[derive(Clone, Encode, Decode, Debug)]
-pub struct SuffixTree {
-    children: [Option<Box<SuffixTree>>; 27], // 26 letters + space
-    is_terminal: bool,
-    value: Option<Vec<String>>,
-    count: u16,
-}
+use crate::error::SearchError;
+use crate::pool::{TermId, TermLookup};
+use crate::structure::{BuildStructure, KeyStats, SearchStructure};
+use crate::text::{normalize, normalize_query, reversed_graphemes};
+use crate::trees::node::Node;
+use crate::wildcard::Pattern;
 
-impl Default for SuffixTree {
-    fn default() -> Self {
-        Self::new()
-    }
+#[derive(Clone, Encode, Decode, Debug, Default)]
+pub struct SuffixTree {
+    root: Node<String>, // edges keyed by extended grapheme cluster, last one first
+    top_k: Option<usize>, // how many completions `build_top_k` kept per node, if it ran
 }
 
 impl SuffixTree {
     pub fn new() -> Self {
-        Self {
-            children: std::array::from_fn(|_| None),
-            is_terminal: false,
-            value: None,
-            count: 0,
+        Self::default()
+    }
+
+    pub fn store(&mut self, key: &str, id: TermId, line: u64) {
+        // Traverse the tree for each grapheme of the normalized key, last
+        // grapheme first, so combining marks and multi-codepoint clusters
+        // stay in one piece when the key is reversed
+        let normalized = normalize(key);
+        let path = reversed_graphemes(&normalized).into_iter().map(str::to_string);
+        self.root.store(path, id, line);
+    }
+
+    // Takes back one `store` of `key` under `id` on `line`, see `Node::remove`.
+    // Precomputed completions are not re-ranked; run `build_top_k` again for
+    // that.
+    pub fn remove(&mut self, key: &str, id: TermId, line: u64) -> bool {
+        let normalized = normalize(key);
+        self.root.remove(reversed_graphemes(&normalized).into_iter(), id, line)
+    }
+
+    pub fn search(&self, suffix: String) -> Result<Vec<TermId>, SearchError> {
+        let mut results = Vec::new();
+        self.search_each(&suffix, &mut |id| {
+            results.push(id);
+            ControlFlow::Continue(())
+        })?;
+        Ok(results)
+    }
+
+    // Node at the end of the reversed query, if any key goes through it
+    fn descend(&self, normalized: &str) -> Option<&Node<String>> {
+        self.root.descend(reversed_graphemes(normalized))
+    }
+
+    // Hands every match to `visit`, ordered by reversed key, until it
+    // breaks; words equal to the suffix itself live on its node and are not
+    // part of the result
+    pub fn search_each(
+        &self,
+        suffix: &str,
+        visit: &mut dyn FnMut(TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        if let Some(node) = self.descend(&normalize_query(suffix)?) {
+            let _ = node.visit_below(visit);
         }
+        Ok(())
     }
 
-    pub fn store(&mut self, key: String) {
-        let mut node = self;
-        let mut value = Vec::new();
-        let key: String = key.chars().rev().collect();
+    // Keys matching the whole of `pattern`, ordered by reversed key. The
+    // pattern is run back to front, so a literal suffix is walked straight
+    // down.
+    pub fn search_wildcard(&self, pattern: &Pattern) -> Vec<TermId> {
+        let reversed = pattern.reversed();
+        let mut results = Vec::new();
+        walk_wildcard(&self.root, &reversed, &reversed.first_positions(), &mut results);
+        results
+    }
 
-        // Traverse the Trie for each character of the key
-        for char in key.chars() {
-            let lower = char.to_ascii_lowercase();
-            let index = match lower {
-                'a'..='z' => (lower as u8 - b'a') as usize,
-                ' ' => 26,     // Handle space characters
-                _ => continue, // Skip non-alphabetic characters
-            };
+    pub fn node_count(&self) -> usize {
+        self.root.node_count()
+    }
 
-            // If the node for this character doesn't exist, create a new one
-            if node.children[index].is_none() {
-                node.children[index] = Some(Box::new(SuffixTree::new()));
-            }
+    // Node holding exactly this key, if it was ever stored
+    fn find(&self, key: &str) -> Option<&Node<String>> {
+        self.root.find(reversed_graphemes(&normalize(key)))
+    }
+
+    pub fn term_frequency(&self, key: &str) -> u64 {
+        self.find(key).map_or(0, |node| node.stats().term_frequency)
+    }
+
+    pub fn line_frequency(&self, key: &str) -> u64 {
+        self.find(key).map_or(0, |node| node.stats().line_frequency)
+    }
+
+    // Number of distinct (normalized) keys stored
+    pub fn vocabulary_size(&self) -> usize {
+        self.root.vocabulary_size()
+    }
+
+    // Precomputes, for every node, the `k` most frequent keys strictly below
+    // it, so `top_completions` is only a walk down the query. `k = 0` drops
+    // them again.
+    pub fn build_top_k(&mut self, k: usize) {
+        self.root.rank_top(k);
+        self.top_k = (k > 0).then_some(k);
+    }
+
+    // Whether `build_top_k` ran with some `k`
+    pub fn has_top_k(&self) -> bool {
+        self.top_k.is_some()
+    }
 
-            // Move to the next node in the Trie
-            node = node.children[index].as_mut().unwrap();
+    // Most frequent words ending in `suffix`, best first, or `None` when
+    // `build_top_k` never ran
+    pub fn top_completions(&self, suffix: &str) -> Result<Option<Vec<TermId>>, SearchError> {
+        let normalized = normalize_query(suffix)?;
+        if !self.has_top_k() {
+            return Ok(None);
         }
+        Ok(Some(self.descend(&normalized).map(|node| node.top().to_vec()).unwrap_or_default()))
+    }
 
-        // When we've traversed all characters, mark this as a terminal node
-        if node.is_terminal {
-            // Word already exists, just increment count
-            match &mut node.value {
-                Some(x) => x.push(key),
-                None => panic!("Should be a value already"),
+    // Visits the precomputed completions of every node that has some, with
+    // the graphemes leading to it, last grapheme first
+    pub(crate) fn for_each_top(&self, visit: &mut dyn FnMut(&[&str], &[TermId])) {
+        self.root.walk(&mut Vec::new(), &mut |path, node| {
+            if !node.top().is_empty() {
+                let path: Vec<&str> = path.iter().map(|grapheme| grapheme.as_str()).collect();
+                visit(&path, node.top());
             }
-            node.count += 1;
-        } else {
-            // Word does not exist, store it as a new terminal node
-            value.push(key);
-            node.is_terminal = true;
-            node.value = Some(value);
-            node.count = 1; // Start the count for this word at 1
+        });
+    }
+}
+
+fn walk_wildcard(node: &Node<String>, reversed: &Pattern, positions: &[usize], results: &mut Vec<TermId>) {
+    if node.is_terminal && reversed.positions_match(positions) {
+        results.extend_from_slice(node.ids());
+    }
+    for (grapheme, child) in node.children.iter() {
+        let mut next = positions.to_vec();
+        for char in grapheme.chars().rev() {
+            next = reversed.next_positions(&next, char);
+        }
+        if !next.is_empty() {
+            walk_wildcard(child, reversed, &next, results);
         }
     }
+}
 
-    pub fn search(&self, suffix: String) -> Result<Vec<String>, String> {
-        let mut node = self;
-        let suffix: String = suffix.chars().rev().collect(); // Reverse the suffix
+impl SearchStructure for SuffixTree {
+    fn search(&self, _pool: &dyn TermLookup, query: &str) -> Result<Vec<TermId>, SearchError> {
+        SuffixTree::search(self, query.to_string())
+    }
 
-        // Traverse to the end of the suffix
-        for char in suffix.chars() {
-            let index = if char == ' ' {
-                26
-            } else {
-                (char as u8 - b'a') as usize
-            };
+    fn search_each(
+        &self,
+        _pool: &dyn TermLookup,
+        query: &str,
+        visit: &mut dyn FnMut(TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        SuffixTree::search_each(self, query, visit)
+    }
 
-            if node.children[index].is_none() {
-                return Err(format!("No words with suffix '{}'", suffix));
-            }
-            node = node.children[index].as_ref().unwrap();
-        }
+    fn top_completions(&self, query: &str) -> Result<Option<Vec<TermId>>, SearchError> {
+        SuffixTree::top_completions(self, query)
+    }
 
-        // Collect all words from this node down
-        let mut results = Vec::new();
-        self.collect_words(node, &suffix, &mut results);
+    fn search_wildcard(
+        &self,
+        _pool: &dyn TermLookup,
+        pattern: &Pattern,
+    ) -> Result<Option<Vec<TermId>>, SearchError> {
+        Ok(Some(SuffixTree::search_wildcard(self, pattern)))
+    }
 
-        if results.is_empty() {
-            Err("No words found".to_string())
-        } else {
-            Ok(results)
-        }
+    fn len(&self) -> usize {
+        self.vocabulary_size()
     }
 
-    fn collect_words(&self, node: &SuffixTree, prefix: &String, results: &mut Vec<String>) {
-        if node.is_terminal {
-            if let Some(value) = &node.value {
-                for word in value {
-                    // Only add words that start with prefix but aren't equal to it
-                    if word.starts_with(prefix) && word != prefix {
-                        results.push(word.chars().rev().collect());
-                    }
-                }
+    // Keys come ordered by their reversed form, and are rebuilt in reading
+    // order from the path
+    fn for_each_key(&self, visit: &mut dyn FnMut(&str, &[TermId], KeyStats)) {
+        self.root.walk(&mut Vec::new(), &mut |path, node| {
+            if node.is_terminal {
+                let key: String = path.iter().rev().map(|grapheme| grapheme.as_str()).collect();
+                visit(&key, node.ids(), node.stats());
             }
-        }
+        });
+    }
+
+    fn stats(&self, key: &str) -> KeyStats {
+        self.find(key).map(Node::stats).unwrap_or_default()
+    }
+}
 
-        for child in node.children.iter().flatten() {
-            self.collect_words(child, prefix, results);
+impl BuildStructure for SuffixTree {
+    fn store(&mut self, key: &str, id: TermId, line: u64) {
+        SuffixTree::store(self, key, id, line);
+    }
+
+    fn remove(&mut self, key: &str, id: TermId, line: u64) -> bool {
+        SuffixTree::remove(self, key, id, line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::StringPool;
+
+    fn tree(words: &[&str]) -> (StringPool, SuffixTree) {
+        let mut pool = StringPool::new();
+        let mut tree = SuffixTree::new();
+        for (line, word) in words.iter().enumerate() {
+            tree.store(word, pool.intern(word).unwrap(), line as u64);
         }
+        (pool, tree)
+    }
+
+    fn search(pool: &StringPool, tree: &SuffixTree, suffix: &str) -> Vec<String> {
+        pool.resolve(&tree.search(suffix.to_string()).unwrap())
+    }
+
+    #[test]
+    fn finds_suffixes_by_grapheme() {
+        let (pool, tree) = tree(&["नमस्ते", "ते", "running", "sing", "vive la 🇫🇷", "🇷"]);
+        assert_eq!(search(&pool, &tree, "स्ते"), ["नमस्ते"]);
+        // The conjunct `स्ते` is one grapheme, which `ते` is only part of
+        assert!(search(&pool, &tree, "ते").is_empty());
+        assert_eq!(search(&pool, &tree, "ing"), ["running", "sing"]);
+        assert_eq!(search(&pool, &tree, "🇫🇷"), ["vive la 🇫🇷"]);
+        // Half a flag is not the end of a key that ends in the whole flag
+        assert!(search(&pool, &tree, "🇷").is_empty());
+    }
+
+    #[test]
+    fn keeps_combining_marks_with_their_letter() {
+        // Decomposed, so the accent is a code point of its own after the `e`
+        let (pool, tree) = tree(&["cafe\u{301}", "cafe", "re\u{301}sume\u{301}"]);
+        assert_eq!(search(&pool, &tree, "fé"), ["cafe\u{301}"]);
+        assert_eq!(search(&pool, &tree, "fe"), ["cafe"]);
+        assert_eq!(search(&pool, &tree, "e\u{301}"), ["cafe\u{301}", "re\u{301}sume\u{301}"]);
+    }
+
+    #[test]
+    fn visits_keys_in_reading_order() {
+        let (_, tree) = tree(&["नमस्ते", "vive la 🇫🇷"]);
+        let mut keys = Vec::new();
+        tree.for_each_key(&mut |key, _, _| keys.push(key.to_string()));
+        keys.sort();
+        assert_eq!(keys, ["vive la 🇫🇷", "नमस्ते"]);
+    }
+
+    #[test]
+    fn walks_wildcards_back_to_front() {
+        let (pool, tree) = tree(&["sing", "song", "sting", "rings", "nein"]);
+        let found = |pattern: &str| {
+            let mut found = pool.resolve(&tree.search_wildcard(&Pattern::parse(pattern).unwrap()));
+            found.sort();
+            found
+        };
+        assert_eq!(found("*ing"), ["sing", "sting"]);
+        assert_eq!(found("s?ng"), ["sing", "song"]);
+        assert_eq!(found("s*ng"), ["sing", "song", "sting"]);
+        assert_eq!(found("*in*"), ["nein", "rings", "sing", "sting"]);
+        assert_eq!(found("?ing"), ["sing"]);
+        assert_eq!(found("??ing"), ["sting"]);
+        assert!(found("???ing").is_empty());
     }
 }
@@ -0,0 +1,253 @@
+use std::collections::BTreeSet;
+use bincode::{Decode, Encode};
+
+use crate::error::SearchError;
+use crate::pool::{TermId, TermLookup};
+use crate::structure::{BuildStructure, KeyStats, SearchStructure};
+use crate::text::{normalize, normalize_query};
+
+// Entries are separated by a character `normalize` never lets through, so no
+// query can match across the boundary of two entries.
+pub(crate) const SEPARATOR: char = '\u{0}';
+
+// Contains searches only match a query that sits strictly inside an entry,
+// not at its start or end; those are left to prefix and suffix searches.
+// Shared by every contains structure, over bytes or over grapheme units.
+pub(crate) fn strictly_inside<T: PartialEq>(entry: &[T], query: &[T]) -> bool {
+    !entry.starts_with(query) && !entry.ends_with(query)
+}
+
+// Sorts the byte offsets of every suffix of `text` by prefix doubling: after
+// the round for `width`, each offset's rank orders the first `width` bytes
+// of its suffix, and the ranks of the next round pair up the ranks `width`
+// apart. Each round is one sort, so this is O(n log² n) however repetitive
+// the text is, where comparing whole suffixes could cost O(n) per compare.
+fn sorted_suffixes(text: &[u8]) -> Vec<u32> {
+    let mut suffixes: Vec<u32> = (0..text.len() as u32).collect();
+    let mut rank: Vec<u32> = text.iter().map(|byte| u32::from(*byte)).collect();
+    let mut next = vec![0; text.len()];
+    let mut width = 1;
+    // A suffix that ends within `width` sorts before any that goes on
+    let key = |rank: &[u32], width: usize, suffix: u32| {
+        let suffix = suffix as usize;
+        (rank[suffix], rank.get(suffix + width).map_or(0, |rank| rank + 1))
+    };
+    while suffixes.len() > 1 {
+        suffixes.sort_unstable_by_key(|suffix| key(&rank, width, *suffix));
+        next[suffixes[0] as usize] = 0;
+        for pair in suffixes.windows(2) {
+            let differs = key(&rank, width, pair[0]) != key(&rank, width, pair[1]);
+            next[pair[1] as usize] = next[pair[0] as usize] + u32::from(differs);
+        }
+        std::mem::swap(&mut rank, &mut next);
+        // Every suffix has a rank of its own: they are all told apart
+        if rank[suffixes[suffixes.len() - 1] as usize] as usize == suffixes.len() - 1 {
+            break;
+        }
+        width *= 2;
+    }
+    suffixes
+}
+
+// Substring index over a vocabulary: every distinct pool entry is normalized
+// and concatenated into one text, and `suffixes` holds the byte offset of
+// every suffix of that text in sorted order. All suffixes starting with a query sit
+// next to each other, so a contains search is two binary searches
+// (O(|query| * log n)) plus one step per match.
+//
+// `store` and `remove` only keep track of the entries; `build` has to run
+// once they are done, with the pool the ids came from.
+#[derive(Clone, Encode, Decode, Debug, Default)]
+pub struct SuffixArray {
+    pub(crate) entries: Vec<TermId>, // ascending and distinct
+    counts: Vec<u64>,                // stores of each of `entries` not taken back
+    pub(crate) text: String,
+    pub(crate) starts: Vec<u32>,
+    pub(crate) suffixes: Vec<u32>,
+}
+
+impl SuffixArray {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn store(&mut self, id: TermId) {
+        match self.entries.binary_search(&id) {
+            Ok(index) => self.counts[index] += 1,
+            Err(index) => {
+                self.entries.insert(index, id);
+                self.counts.insert(index, 1);
+                self.clear();
+            }
+        }
+    }
+
+    // Takes back one `store` of `id`; the entry leaves the text once every
+    // store of it has been taken back
+    pub fn remove(&mut self, id: TermId) -> bool {
+        let Ok(index) = self.entries.binary_search(&id) else {
+            return false;
+        };
+        self.counts[index] -= 1;
+        if self.counts[index] == 0 {
+            self.entries.remove(index);
+            self.counts.remove(index);
+            self.clear();
+        }
+        true
+    }
+
+    // Anything built so far no longer covers every entry
+    fn clear(&mut self) {
+        self.text.clear();
+        self.starts.clear();
+        self.suffixes.clear();
+    }
+
+    pub fn build(&mut self, pool: &dyn TermLookup) {
+        self.text.clear();
+        self.starts.clear();
+        for id in self.entries.iter() {
+            self.starts.push(self.text.len() as u32);
+            self.text.push_str(&normalize(pool.get(*id).unwrap_or_default()));
+            self.text.push(SEPARATOR);
+        }
+
+        // Bytes sort exactly like the `str` they encode; only suffixes that
+        // start a character of an entry are kept
+        let text = &self.text;
+        self.suffixes = sorted_suffixes(text.as_bytes());
+        self.suffixes.retain(|suffix| {
+            let suffix = *suffix as usize;
+            text.is_char_boundary(suffix) && !text[suffix..].starts_with(SEPARATOR)
+        });
+    }
+
+    // Normalized text of the entry at `index`, without its separator
+    fn entry_text(&self, index: usize) -> &str {
+        let start = self.starts[index] as usize;
+        let end = self
+            .starts
+            .get(index + 1)
+            .map_or(self.text.len(), |next| *next as usize);
+        &self.text[start..end - SEPARATOR.len_utf8()]
+    }
+
+    pub fn search(&self, key: String) -> Result<Vec<TermId>, SearchError> {
+        let query = normalize_query(&key)?;
+        let text = self.text.as_str();
+
+        // The suffixes starting with the query form one contiguous run
+        let lower = self
+            .suffixes
+            .partition_point(|suffix| &text[*suffix as usize..] < query.as_str());
+        let upper = lower
+            + self.suffixes[lower..]
+                .partition_point(|suffix| text[*suffix as usize..].starts_with(&query));
+
+        let matched: BTreeSet<usize> = self.suffixes[lower..upper]
+            .iter()
+            .map(|suffix| self.starts.partition_point(|start| start <= suffix) - 1)
+            .collect();
+
+        Ok(matched
+            .into_iter()
+            .filter(|index| strictly_inside(self.entry_text(*index).as_bytes(), query.as_bytes()))
+            .map(|index| self.entries[index])
+            .collect())
+    }
+}
+
+impl SearchStructure for SuffixArray {
+    fn search(&self, _pool: &dyn TermLookup, query: &str) -> Result<Vec<TermId>, SearchError> {
+        SuffixArray::search(self, query.to_string())
+    }
+
+    fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    // Only entries that were built are visited, in id order
+    fn for_each_key(&self, visit: &mut dyn FnMut(&str, &[TermId], KeyStats)) {
+        for (index, id) in self.entries.iter().enumerate().take(self.starts.len()) {
+            visit(self.entry_text(index), std::slice::from_ref(id), KeyStats::default());
+        }
+    }
+
+    // Entries are not counted
+    fn stats(&self, _key: &str) -> KeyStats {
+        KeyStats::default()
+    }
+}
+
+impl BuildStructure for SuffixArray {
+    fn store(&mut self, _key: &str, id: TermId, _line: u64) {
+        SuffixArray::store(self, id);
+    }
+
+    fn remove(&mut self, _key: &str, id: TermId, _line: u64) -> bool {
+        SuffixArray::remove(self, id)
+    }
+
+    fn build(&mut self, pool: &dyn TermLookup) {
+        SuffixArray::build(self, pool);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::StringPool;
+    use crate::mapped::suffix_array::MappedSuffixArray;
+    use crate::mapped::Mapped;
+
+    fn suffix_array(words: &[&str]) -> (StringPool, SuffixArray) {
+        let mut pool = StringPool::new();
+        let mut suffix_array = SuffixArray::new();
+        for word in words {
+            suffix_array.store(pool.intern(word).unwrap());
+        }
+        suffix_array.build(&pool);
+        (pool, suffix_array)
+    }
+
+    #[test]
+    fn sorts_suffixes_like_comparing_them() {
+        for text in ["", "a", "banana", "aaaaaaaa", "abab\0ab\0", "mississippi\0ñandú\0"] {
+            let mut expected: Vec<u32> = (0..text.len() as u32).collect();
+            expected.sort_by_key(|suffix| &text.as_bytes()[*suffix as usize..]);
+            assert_eq!(sorted_suffixes(text.as_bytes()), expected, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn finds_queries_strictly_inside_entries() {
+        let (pool, suffix_array) = suffix_array(&["banana", "bandana", "ban", "anna", "naïve café"]);
+        let search = |query: &str| pool.resolve(&suffix_array.search(query.to_string()).unwrap());
+        assert_eq!(search("an"), ["banana", "bandana"]);
+        assert_eq!(search("NAN"), ["banana"]);
+        assert_eq!(search("ï"), ["naïve café"]);
+        assert_eq!(search("e c"), ["naïve café"]);
+        // At the start or the end of every entry that has them
+        assert!(search("ban").is_empty());
+        assert!(search("na").is_empty());
+        assert!(search("café").is_empty());
+        // Never across two entries
+        assert!(search("anaban").is_empty());
+    }
+
+    #[test]
+    fn mapped_layout_answers_the_same() {
+        let (pool, suffix_array) = suffix_array(&["banana", "bandana", "ban", "anna", "naïve café"]);
+        let mapped = MappedSuffixArray::from_bytes(MappedSuffixArray::write(&suffix_array)).unwrap();
+        for query in ["an", "nan", "ï", "e c", "ban", "na", "a", "zzz"] {
+            assert_eq!(
+                mapped.search(query.to_string()).unwrap(),
+                suffix_array.search(query.to_string()).unwrap(),
+                "{query}"
+            );
+        }
+        assert_eq!(pool.resolve(&mapped.search("and".to_string()).unwrap()), ["bandana"]);
+    }
+}
@@ -1,107 +1,357 @@
+use std::ops::ControlFlow;
 use bincode::{Encode,Decode};
 
-#[derive(Clone, Encode, Decode, Debug)]
-pub struct Trie {
-    children: [Option<Box<Trie>>; 27],  // 26 letters + space
-    is_terminal: bool,
-    value: Option<Vec<String>>,
-    count:u16,
-}
+use crate::error::SearchError;
+use crate::fuzzy::Levenshtein;
+use crate::pool::{TermId, TermLookup};
+use crate::structure::{BuildStructure, KeyStats, SearchStructure};
+use crate::text::{normalize, normalize_query};
+use crate::trees::node::Node;
+use crate::wildcard::Pattern;
 
-impl Default for Trie {
-    fn default() -> Self {
-        Self::new()
-    }
+#[derive(Clone, Encode, Decode, Debug, Default)]
+pub struct Trie {
+    root: Node<char>, // edges keyed by (lowercased) Unicode scalar value
+    top_k: Option<usize>, // how many completions `build_top_k` kept per node, if it ran
 }
 
 impl Trie {
 
     pub fn new() -> Self {
-        Self {
-            children: std::array::from_fn(|_| None),
-            is_terminal: false,
-            value: None,
-            count: 0,
+        Self::default()
+    }
+
+    pub fn store(&mut self, key: &str, id: TermId, line: u64) {
+        // Traverse the Trie for each character of the normalized key
+        self.root.store(normalize(key).chars(), id, line);
+    }
+
+    // Takes back one `store` of `key` under `id` on `line`, see `Node::remove`.
+    // Precomputed completions are not re-ranked; run `build_top_k` again for
+    // that.
+    pub fn remove(&mut self, key: &str, id: TermId, line: u64) -> bool {
+        let path: Vec<char> = normalize(key).chars().collect();
+        self.root.remove(path.iter(), id, line)
+    }
+
+    pub fn search(&self, prefix: String) -> Result<Vec<TermId>, SearchError> {
+        let mut results = Vec::new();
+        self.search_each(&prefix, &mut |id| {
+            results.push(id);
+            ControlFlow::Continue(())
+        })?;
+        Ok(results)
+    }
+
+    // Node at the end of a query, if any key goes through it
+    fn descend(&self, normalized: &str) -> Option<&Node<char>> {
+        let path: Vec<char> = normalized.chars().collect();
+        self.root.descend(path.iter())
+    }
+
+    // Hands every match to `visit`, in key order, until it breaks; words
+    // equal to the prefix itself live on its node and are not part of the
+    // result
+    pub fn search_each(
+        &self,
+        prefix: &str,
+        visit: &mut dyn FnMut(TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        if let Some(node) = self.descend(&normalize_query(prefix)?) {
+            let _ = node.visit_below(visit);
         }
+        Ok(())
     }
-    
-    pub fn store(&mut self, key: String) {
-        let mut node = self;
-        let mut value = Vec::new();
-
-        // Traverse the Trie for each character of the key
-        for char in key.chars() {
-            let lower = char.to_ascii_lowercase();
-            let index = match lower {
-                'a'..='z' => (lower as u8 - b'a') as usize,
-                ' ' => 26,  // Handle space characters
-                _ => continue,  // Skip non-alphabetic characters
-            };
-
-            // If the node for this character doesn't exist, create a new one
-            if node.children[index].is_none() {
-                node.children[index] = Some(Box::new(Trie::new()));
-            }
 
-            // Move to the next node in the Trie
-            node = node.children[index].as_mut().unwrap();
+    // The matches of `search_each` one level at a time, so shorter keys come
+    // first, each with how many characters it has beyond the prefix
+    pub fn search_by_length(
+        &self,
+        prefix: &str,
+        visit: &mut dyn FnMut(usize, TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        if let Some(node) = self.descend(&normalize_query(prefix)?) {
+            node.visit_by_length(visit);
         }
+        Ok(())
+    }
 
-        // When we've traversed all characters, mark this as a terminal node
-        if node.is_terminal {
-            // Word already exists, just increment count
-            match &mut node.value {
-                Some(x) => x.push(key),
-                None => panic!("Should be a value already")
-            }
-            node.count += 1;
-        } else {
-            // Word does not exist, store it as a new terminal node
-            value.push(key);
-            node.is_terminal = true;
-            node.value = Some(value);
-            node.count = 1; // Start the count for this word at 1
+    // Keys within `max_distance` edits of `query` (clamped to 1..=2), closest
+    // first and in dataset order within a distance. The automaton steps down
+    // each edge and subtrees it rules out are never entered.
+    pub fn search_fuzzy(&self, query: &str, max_distance: u32) -> Result<Vec<(u32, TermId)>, SearchError> {
+        let automaton = Levenshtein::new(&normalize_query(query)?, max_distance);
+        let mut results = Vec::new();
+        walk_fuzzy(&self.root, &automaton, &automaton.first_row(), &mut results);
+        results.sort_unstable();
+        Ok(results)
+    }
+
+    // Keys matching the whole of `pattern`, in key order. Subtrees the
+    // pattern rules out are never entered; where only a few characters can
+    // come next (a literal, or a group like `[ae]`) just those edges are
+    // looked up, so a literal prefix is walked straight down.
+    pub fn search_wildcard(&self, pattern: &Pattern) -> Vec<TermId> {
+        let mut results = Vec::new();
+        walk_wildcard(&self.root, pattern, &pattern.first_positions(), &mut results);
+        results
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.root.node_count()
+    }
+
+    // Node holding exactly this key, if it was ever stored
+    fn find(&self, key: &str) -> Option<&Node<char>> {
+        let path: Vec<char> = normalize(key).chars().collect();
+        self.root.find(path.iter())
+    }
+
+    pub fn term_frequency(&self, key: &str) -> u64 {
+        self.find(key).map_or(0, |node| node.stats().term_frequency)
+    }
+
+    pub fn line_frequency(&self, key: &str) -> u64 {
+        self.find(key).map_or(0, |node| node.stats().line_frequency)
+    }
+
+    // Number of distinct (normalized) keys stored
+    pub fn vocabulary_size(&self) -> usize {
+        self.root.vocabulary_size()
+    }
+
+    // Precomputes, for every node, the `k` most frequent keys strictly below
+    // it, so `top_completions` is only a walk down the query. `k = 0` drops
+    // them again.
+    pub fn build_top_k(&mut self, k: usize) {
+        self.root.rank_top(k);
+        self.top_k = (k > 0).then_some(k);
+    }
+
+    // Whether `build_top_k` ran with some `k`
+    pub fn has_top_k(&self) -> bool {
+        self.top_k.is_some()
+    }
+
+    // Most frequent completions of `prefix`, best first, or `None` when
+    // `build_top_k` never ran
+    pub fn top_completions(&self, prefix: &str) -> Result<Option<Vec<TermId>>, SearchError> {
+        let normalized = normalize_query(prefix)?;
+        if !self.has_top_k() {
+            return Ok(None);
         }
+        Ok(Some(self.descend(&normalized).map(|node| node.top().to_vec()).unwrap_or_default()))
     }
 
-    pub fn search(&self, prefix: String) -> Result<Vec<String>, String> {
-        let mut node = self;
-        
-        // Traverse to the end of the prefix
-        for char in prefix.chars() {
-            let index = if char == ' ' { 26 } else { (char as u8 - b'a') as usize };
-            
-            if node.children[index].is_none() {
-                return Err(format!("No words with prefix '{}'", prefix));
+    // Visits the precomputed completions of every node that has some, with
+    // the path leading to it
+    pub(crate) fn for_each_top(&self, visit: &mut dyn FnMut(&str, &[TermId])) {
+        self.root.walk(&mut Vec::new(), &mut |path, node| {
+            if !node.top().is_empty() {
+                visit(&path.iter().copied().collect::<String>(), node.top());
             }
-            node = node.children[index].as_ref().unwrap();
+        });
+    }
+}
+
+fn walk_fuzzy(node: &Node<char>, automaton: &Levenshtein, row: &[u32], results: &mut Vec<(u32, TermId)>) {
+    if node.is_terminal {
+        if let Some(distance) = automaton.row_distance(row) {
+            results.extend(node.ids().iter().map(|id| (distance, *id)));
         }
-        
-        // Collect all words from this node down
-        let mut results = Vec::new();
-        self.collect_words(node, &prefix, &mut results);
-        
-        if results.is_empty() {
-            Err("No words found".to_string())
-        } else {
-            Ok(results)
+    }
+    for (char, child) in node.children.iter() {
+        let next = automaton.next_row(row, *char);
+        if automaton.row_can_match(&next) {
+            walk_fuzzy(child, automaton, &next, results);
         }
     }
-    
-    fn collect_words(&self, node: &Trie, prefix: &String, results: &mut Vec<String>) {
-        if node.is_terminal {
-            if let Some(value) = &node.value {
-                for word in value {
-                    // Only add words that start with prefix but aren't equal to it
-                    if word.starts_with(prefix) && word != prefix {
-                        results.push(word.to_string());
-                    }
+}
+
+fn walk_wildcard(node: &Node<char>, pattern: &Pattern, positions: &[usize], results: &mut Vec<TermId>) {
+    if node.is_terminal && pattern.positions_match(positions) {
+        results.extend_from_slice(node.ids());
+    }
+    let mut step = |char: char, child: &Node<char>| {
+        let next = pattern.next_positions(positions, char);
+        if !next.is_empty() {
+            walk_wildcard(child, pattern, &next, results);
+        }
+    };
+    match pattern.next_chars(positions) {
+        Some(chars) => {
+            for char in chars {
+                if let Some(child) = node.children.get(&char) {
+                    step(char, child);
                 }
             }
         }
-        
-        for child in node.children.iter().flatten() {
-            self.collect_words(child, prefix, results);
+        None => {
+            for (char, child) in node.children.iter() {
+                step(*char, child);
+            }
+        }
+    }
+}
+
+impl SearchStructure for Trie {
+    fn search(&self, _pool: &dyn TermLookup, query: &str) -> Result<Vec<TermId>, SearchError> {
+        Trie::search(self, query.to_string())
+    }
+
+    fn search_each(
+        &self,
+        _pool: &dyn TermLookup,
+        query: &str,
+        visit: &mut dyn FnMut(TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        Trie::search_each(self, query, visit)
+    }
+
+    fn search_by_length(
+        &self,
+        _pool: &dyn TermLookup,
+        query: &str,
+        visit: &mut dyn FnMut(usize, TermId) -> ControlFlow<()>,
+    ) -> Result<(), SearchError> {
+        Trie::search_by_length(self, query, visit)
+    }
+
+    fn top_completions(&self, query: &str) -> Result<Option<Vec<TermId>>, SearchError> {
+        Trie::top_completions(self, query)
+    }
+
+    fn search_fuzzy(&self, query: &str, max_distance: u32) -> Result<Option<Vec<(u32, TermId)>>, SearchError> {
+        Trie::search_fuzzy(self, query, max_distance).map(Some)
+    }
+
+    fn search_wildcard(
+        &self,
+        _pool: &dyn TermLookup,
+        pattern: &Pattern,
+    ) -> Result<Option<Vec<TermId>>, SearchError> {
+        Ok(Some(Trie::search_wildcard(self, pattern)))
+    }
+
+    fn len(&self) -> usize {
+        self.vocabulary_size()
+    }
+
+    // Keys come in order
+    fn for_each_key(&self, visit: &mut dyn FnMut(&str, &[TermId], KeyStats)) {
+        self.root.walk(&mut Vec::new(), &mut |path, node| {
+            if node.is_terminal {
+                visit(&path.iter().copied().collect::<String>(), node.ids(), node.stats());
+            }
+        });
+    }
+
+    fn stats(&self, key: &str) -> KeyStats {
+        self.find(key).map(Node::stats).unwrap_or_default()
+    }
+}
+
+impl BuildStructure for Trie {
+    fn store(&mut self, key: &str, id: TermId, line: u64) {
+        Trie::store(self, key, id, line);
+    }
+
+    fn remove(&mut self, key: &str, id: TermId, line: u64) -> bool {
+        Trie::remove(self, key, id, line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::StringPool;
+
+    fn trie(words: &[&str]) -> (StringPool, Trie) {
+        let mut pool = StringPool::new();
+        let mut trie = Trie::new();
+        for (line, word) in words.iter().enumerate() {
+            trie.store(word, pool.intern(word).unwrap(), line as u64);
         }
+        (pool, trie)
+    }
+
+    fn search(pool: &StringPool, trie: &Trie, prefix: &str) -> Vec<String> {
+        pool.resolve(&trie.search(prefix.to_string()).unwrap())
+    }
+
+    #[test]
+    fn finds_keys_beyond_ascii() {
+        let (pool, trie) = trie(&["東京", "東京都", "東大", "straße", "strasse", "🦀", "🦀rust", "ωmega"]);
+        assert_eq!(search(&pool, &trie, "東"), ["東京", "東京都", "東大"]);
+        assert_eq!(search(&pool, &trie, "東京"), ["東京都"]);
+        assert_eq!(search(&pool, &trie, "straß"), ["straße"]);
+        assert_eq!(search(&pool, &trie, "🦀"), ["🦀rust"]);
+        assert_eq!(search(&pool, &trie, "Ω"), ["ωmega"]);
+        assert!(search(&pool, &trie, "京").is_empty());
+    }
+
+    #[test]
+    fn folds_case_and_composition() {
+        // Precomposed, decomposed and upper case spellings are one key
+        let (pool, trie) = trie(&["naïve", "nai\u{308}ve", "NAÏVETÉ", "Москва"]);
+        assert_eq!(search(&pool, &trie, "NAÏ"), ["naïve", "nai\u{308}ve", "NAÏVETÉ"]);
+        assert_eq!(search(&pool, &trie, "nai\u{308}v"), ["naïve", "nai\u{308}ve", "NAÏVETÉ"]);
+        assert_eq!(search(&pool, &trie, "моск"), ["Москва"]);
+        assert_eq!(trie.term_frequency("Naïve"), 2);
+        assert_eq!(trie.vocabulary_size(), 3);
+    }
+
+    #[test]
+    fn walks_shorter_keys_first() {
+        let (pool, trie) = trie(&["carton", "car", "cart", "cards", "cat", "東京都"]);
+        let mut found = Vec::new();
+        trie.search_by_length("CA", &mut |extra, id| {
+            found.push((extra, pool.get(id).unwrap_or_default()));
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(found, [(1, "car"), (1, "cat"), (2, "cart"), (3, "cards"), (4, "carton")]);
+
+        // Breaking stops the walk there
+        let mut visited = 0;
+        trie.search_by_length("ca", &mut |extra, _| {
+            visited += 1;
+            if extra > 1 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .unwrap();
+        assert_eq!(visited, 3);
+    }
+
+    #[test]
+    fn knows_whether_completions_were_built() {
+        let (pool, mut trie) = trie(&["tea", "team", "tea", "ten"]);
+        assert_eq!(trie.top_completions("te").unwrap(), None);
+        trie.build_top_k(2);
+        assert_eq!(pool.resolve(&trie.top_completions("te").unwrap().unwrap()), ["tea", "team"]);
+        assert_eq!(trie.top_completions("x").unwrap(), Some(Vec::new()));
+        trie.build_top_k(0);
+        assert_eq!(trie.top_completions("te").unwrap(), None);
+
+        // An empty tree has nothing to complete either way
+        let mut empty = Trie::new();
+        assert_eq!(empty.top_completions("te").unwrap(), None);
+        empty.build_top_k(2);
+        assert_eq!(empty.top_completions("te").unwrap(), Some(Vec::new()));
+    }
+
+    #[test]
+    fn walks_wildcards_down_the_tree() {
+        let (pool, trie) = trie(&["cat", "cut", "cart", "coat", "Cat", "act", "東京"]);
+        let found = |pattern: &str| pool.resolve(&trie.search_wildcard(&Pattern::parse(pattern).unwrap()));
+        assert_eq!(found("c?t"), ["cat", "Cat", "cut"]);
+        assert_eq!(found("c*t"), ["cart", "cat", "Cat", "coat", "cut"]);
+        assert_eq!(found("?a*"), ["cart", "cat", "Cat"]);
+        assert_eq!(found("*t"), ["act", "cart", "cat", "Cat", "coat", "cut"]);
+        assert_eq!(found("東?"), ["東京"]);
+        assert!(found("c?").is_empty());
     }
 }
@@ -0,0 +1,782 @@
+use fst::Automaton;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::error::SearchError;
+use crate::text::normalize_query;
+use crate::trees::suffix_array::SEPARATOR;
+
+// Matches any run of characters, the empty one included
+pub const ANY_RUN: char = '*';
+// Matches exactly one character
+pub const ANY_ONE: char = '?';
+// Line queries: a `*` on its own matches one word, `**` any number of them
+pub const ANY_WORD: &str = "*";
+pub const ANY_WORDS: &str = "**";
+// A class expanding to more characters than this is checked against every
+// child edge instead of looked up edge by edge
+const MAX_BRANCHES: usize = 64;
+
+// One character out of a set, e.g. `[ae]`, `[a-z]` or `[^aeiou]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+}
+
+impl Class {
+    fn contains(&self, char: char) -> bool {
+        self.ranges.iter().any(|(low, high)| (*low..=*high).contains(&char)) != self.negated
+    }
+
+    // Every character the class matches, when there are few enough
+    fn chars(&self) -> Option<Vec<char>> {
+        if self.negated {
+            return None;
+        }
+        let mut chars = Vec::new();
+        for (low, high) in self.ranges.iter() {
+            chars.extend(*low..=*high);
+            if chars.len() > MAX_BRANCHES {
+                return None;
+            }
+        }
+        Some(chars)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Char(char),
+    Class(Class),
+    AnyOne,
+    AnyRun,
+    Group(Vec<Vec<Piece>>), // alternatives, an empty one making the group optional
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Char(char),
+    Class(Class),
+    Any,
+}
+
+impl Step {
+    fn matches(&self, char: char) -> bool {
+        match self {
+            Step::Char(expected) => *expected == char,
+            Step::Class(class) => class.contains(char),
+            Step::Any => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Step(Step, usize), // reads one character, then goes on to the node given
+    Split(Vec<usize>), // goes on to every node given without reading
+    Match,
+}
+
+// A term pattern over the characters of a word; it has to match the whole
+// key. The syntax:
+//
+// - `*` matches any run of characters, none included, and `?` exactly one
+// - `[ae]` matches one character out of the set, `[a-z]` one out of a range
+//   and `[^aeiou]` one outside the set
+// - `(u)` matches "u" or nothing, so "colo(u)r" finds "color" and "colour";
+//   `(a|e)` matches exactly one of the alternatives and `(a|e|)` one or none
+// - `\` makes the character after it literal, e.g. `\*`
+//
+// Groups cannot nest, and everything is normalized like any other query
+// first, so "[A-Z]" is "[a-z]".
+//
+// The pattern is compiled into a small NFA over the key's characters: a state
+// is the set of nodes reached so far, so trees can step it down their edges
+// and drop every subtree where the set runs empty. Where the next character
+// can only be one of a few, `next_chars` lists them, so a tree can look those
+// child edges up instead of trying all of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    pieces: Vec<Piece>,
+    nodes: Vec<Node>,
+}
+
+fn parse_error(message: &'static str) -> SearchError {
+    SearchError::InvalidPattern(message)
+}
+
+// The character after a `\`, or `char` itself
+fn literal(char: char, chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<char, SearchError> {
+    if char == '\\' {
+        chars.next().ok_or_else(|| parse_error("nothing to escape after `\\`"))
+    } else {
+        Ok(char)
+    }
+}
+
+fn parse_class(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Class, SearchError> {
+    let negated = chars.next_if_eq(&'^').is_some();
+    let mut ranges = Vec::new();
+    loop {
+        let low = match chars.next() {
+            None => return Err(parse_error("unclosed `[`")),
+            Some(']') if ranges.is_empty() => return Err(parse_error("empty `[]`")),
+            Some(']') => break,
+            Some(char) => literal(char, chars)?,
+        };
+        // A `-` right before the `]` is literal
+        let mut lookahead = chars.clone();
+        let high = match (lookahead.next(), lookahead.next()) {
+            (Some('-'), Some(next)) if next != ']' => {
+                chars.next();
+                let next = chars.next().unwrap_or(low);
+                literal(next, chars)?
+            }
+            _ => low,
+        };
+        if high < low {
+            return Err(parse_error("range in `[]` runs backwards"));
+        }
+        ranges.push((low, high));
+    }
+    Ok(Class { ranges, negated })
+}
+
+fn parse_group(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Piece, SearchError> {
+    let mut alternatives = vec![Vec::new()];
+    loop {
+        let piece = match chars.next() {
+            None => return Err(parse_error("unclosed `(`")),
+            Some(')') => break,
+            Some('|') => {
+                alternatives.push(Vec::new());
+                continue;
+            }
+            Some('(') => return Err(parse_error("groups cannot nest")),
+            Some(char) => parse_piece(char, chars)?,
+        };
+        if let Some(alternative) = alternatives.last_mut() {
+            alternative.push(piece);
+        }
+    }
+    // A lone alternative is optional
+    if alternatives.len() == 1 {
+        alternatives.push(Vec::new());
+    }
+    Ok(Piece::Group(alternatives))
+}
+
+// Anything but a group
+fn parse_piece(char: char, chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Piece, SearchError> {
+    Ok(match char {
+        ANY_RUN => Piece::AnyRun,
+        ANY_ONE => Piece::AnyOne,
+        '[' => Piece::Class(parse_class(chars)?),
+        ']' | ')' | '|' => return Err(parse_error("unmatched `]`, `)` or `|`")),
+        char => Piece::Char(literal(char, chars)?),
+    })
+}
+
+fn reverse(pieces: &[Piece]) -> Vec<Piece> {
+    pieces
+        .iter()
+        .rev()
+        .map(|piece| match piece {
+            Piece::Group(alternatives) => Piece::Group(alternatives.iter().map(|alternative| reverse(alternative)).collect()),
+            piece => piece.clone(),
+        })
+        .collect()
+}
+
+// Appends the nodes of `pieces`; each piece goes on to the node right after
+// its own, so the last one leads to whatever is appended next
+fn compile(pieces: &[Piece], nodes: &mut Vec<Node>) {
+    for piece in pieces.iter() {
+        let start = nodes.len();
+        match piece {
+            Piece::Char(char) => nodes.push(Node::Step(Step::Char(*char), start + 1)),
+            Piece::Class(class) => nodes.push(Node::Step(Step::Class(class.clone()), start + 1)),
+            Piece::AnyOne => nodes.push(Node::Step(Step::Any, start + 1)),
+            Piece::AnyRun => {
+                nodes.push(Node::Split(vec![start + 1, start + 2]));
+                nodes.push(Node::Step(Step::Any, start));
+            }
+            Piece::Group(alternatives) => {
+                nodes.push(Node::Split(Vec::new()));
+                let mut starts = Vec::new();
+                let mut exits = Vec::new();
+                for alternative in alternatives.iter() {
+                    starts.push(nodes.len());
+                    compile(alternative, nodes);
+                    exits.push(nodes.len());
+                    nodes.push(Node::Split(Vec::new()));
+                }
+                let end = nodes.len();
+                nodes[start] = Node::Split(starts);
+                for exit in exits {
+                    nodes[exit] = Node::Split(vec![end]);
+                }
+            }
+        }
+    }
+}
+
+impl Pattern {
+    // Normalizes `query` like any other search, then parses it
+    pub fn parse(query: &str) -> Result<Self, SearchError> {
+        let normalized = normalize_query(query)?;
+        let mut chars = normalized.chars().peekable();
+        let mut pieces = Vec::new();
+        while let Some(char) = chars.next() {
+            let piece = match char {
+                '(' => parse_group(&mut chars)?,
+                char => parse_piece(char, &mut chars)?,
+            };
+            // Runs of `*` are one `*`
+            if !(piece == Piece::AnyRun && pieces.last() == Some(&Piece::AnyRun)) {
+                pieces.push(piece);
+            }
+        }
+        Ok(Self::from_pieces(pieces))
+    }
+
+    fn from_pieces(pieces: Vec<Piece>) -> Self {
+        let mut nodes = Vec::new();
+        compile(&pieces, &mut nodes);
+        nodes.push(Node::Match);
+        Self { pieces, nodes }
+    }
+
+    // Whether `query` uses any pattern syntax at all
+    pub fn is_wildcard(query: &str) -> bool {
+        query.contains([ANY_RUN, ANY_ONE, '[', '('])
+    }
+
+    // The same pattern read back to front, for structures storing keys
+    // reversed
+    pub fn reversed(&self) -> Self {
+        Self::from_pieces(reverse(&self.pieces))
+    }
+
+    // Literal characters before the first wildcard or group
+    pub fn literal_prefix(&self) -> String {
+        self.literals_from(self.pieces.iter())
+    }
+
+    // Literal characters after the last wildcard or group
+    pub fn literal_suffix(&self) -> String {
+        let mut suffix: Vec<char> = self.literals_from(self.pieces.iter().rev()).chars().collect();
+        suffix.reverse();
+        suffix.into_iter().collect()
+    }
+
+    fn literals_from<'a>(&self, pieces: impl Iterator<Item = &'a Piece>) -> String {
+        pieces
+            .map_while(|piece| match piece {
+                Piece::Char(char) => Some(*char),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Every run of literal characters every match contains, in order
+    pub fn literals(&self) -> Vec<String> {
+        let mut literals = Vec::new();
+        let mut current = String::new();
+        for piece in self.pieces.iter() {
+            match piece {
+                Piece::Char(char) => current.push(*char),
+                _ if !current.is_empty() => literals.push(std::mem::take(&mut current)),
+                _ => {}
+            }
+        }
+        if !current.is_empty() {
+            literals.push(current);
+        }
+        literals
+    }
+
+    pub fn is_match(&self, key: &str) -> bool {
+        let mut positions = self.first_positions();
+        for char in key.chars() {
+            positions = self.next_positions(&positions, char);
+            if positions.is_empty() {
+                return false;
+            }
+        }
+        self.positions_match(&positions)
+    }
+
+    // Follows every split, keeping only the nodes that read or match
+    fn close(&self, mut positions: Vec<usize>) -> Vec<usize> {
+        let mut index = 0;
+        while index < positions.len() {
+            if let Some(Node::Split(targets)) = self.nodes.get(positions[index]) {
+                for target in targets.iter() {
+                    if !positions.contains(target) {
+                        positions.push(*target);
+                    }
+                }
+            }
+            index += 1;
+        }
+        positions.retain(|position| !matches!(self.nodes.get(*position), Some(Node::Split(_))));
+        positions.sort_unstable();
+        positions
+    }
+
+    // Positions before anything is read
+    pub fn first_positions(&self) -> Vec<usize> {
+        self.close(vec![0])
+    }
+
+    // Positions after the key grows by `char`
+    pub fn next_positions(&self, positions: &[usize], char: char) -> Vec<usize> {
+        let mut next = Vec::with_capacity(positions.len());
+        for position in positions.iter() {
+            if let Some(Node::Step(step, target)) = self.nodes.get(*position) {
+                if step.matches(char) && !next.contains(target) {
+                    next.push(*target);
+                }
+            }
+        }
+        self.close(next)
+    }
+
+    // Whether the key read so far matches the whole pattern
+    pub fn positions_match(&self, positions: &[usize]) -> bool {
+        positions.contains(&(self.nodes.len() - 1))
+    }
+
+    // The only characters that can come next, in order, or `None` when
+    // almost any can
+    pub fn next_chars(&self, positions: &[usize]) -> Option<Vec<char>> {
+        let mut chars = Vec::new();
+        for position in positions.iter() {
+            match self.nodes.get(*position) {
+                Some(Node::Step(Step::Char(char), _)) => chars.push(*char),
+                Some(Node::Step(Step::Class(class), _)) => chars.extend(class.chars()?),
+                Some(Node::Step(Step::Any, _)) => return None,
+                _ => {}
+            }
+        }
+        chars.sort_unstable();
+        chars.dedup();
+        Some(chars)
+    }
+
+    // Reads keys as an fst stores them: plain UTF-8, or graphemes each
+    // followed by a separator when `separated`
+    pub fn automaton(&self, separated: bool) -> PatternAutomaton<'_> {
+        PatternAutomaton {
+            pattern: self,
+            separated,
+        }
+    }
+}
+
+// `Pattern` as an `fst::Automaton`. Separated keys are reversed ones, stored
+// last grapheme first, so each grapheme is stepped through back to front once
+// its separator arrives; the pattern has to be reversed already.
+#[derive(Debug, Clone)]
+pub struct PatternAutomaton<'a> {
+    pattern: &'a Pattern,
+    separated: bool,
+}
+
+// Pattern positions plus the bytes not stepped through yet
+#[derive(Debug, Clone)]
+pub struct PatternState {
+    positions: Vec<usize>,
+    pending: Vec<u8>,
+}
+
+impl PatternAutomaton<'_> {
+    fn step(&self, positions: &[usize], text: &str) -> Vec<usize> {
+        let mut positions = positions.to_vec();
+        if self.separated {
+            for char in text.chars().rev() {
+                positions = self.pattern.next_positions(&positions, char);
+            }
+        } else {
+            for char in text.chars() {
+                positions = self.pattern.next_positions(&positions, char);
+            }
+        }
+        positions
+    }
+}
+
+impl Automaton for PatternAutomaton<'_> {
+    // `None` once the bytes stop being valid UTF-8
+    type State = Option<PatternState>;
+
+    fn start(&self) -> Self::State {
+        Some(PatternState {
+            positions: self.pattern.first_positions(),
+            pending: Vec::new(),
+        })
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        state
+            .as_ref()
+            .is_some_and(|state| state.pending.is_empty() && self.pattern.positions_match(&state.positions))
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        state.as_ref().is_some_and(|state| !state.positions.is_empty())
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        let state = state.as_ref()?;
+        let mut pending = state.pending.clone();
+        if self.separated && byte == SEPARATOR as u8 {
+            let text = std::str::from_utf8(&pending).ok()?;
+            return Some(PatternState {
+                positions: self.step(&state.positions, text),
+                pending: Vec::new(),
+            });
+        }
+        pending.push(byte);
+        if self.separated {
+            return Some(PatternState {
+                positions: state.positions.clone(),
+                pending,
+            });
+        }
+        match std::str::from_utf8(&pending) {
+            Ok(text) => Some(PatternState {
+                positions: self.step(&state.positions, text),
+                pending: Vec::new(),
+            }),
+            // The character goes on in the next byte
+            Err(error) if error.error_len().is_none() => Some(PatternState {
+                positions: state.positions.clone(),
+                pending,
+            }),
+            Err(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum WordToken {
+    Word(String),
+    Like(Pattern),
+    AnyWord,
+    AnyWords,
+}
+
+// A wildcard query over the words of a line, such as "mad * dog" or
+// "mad ** dog", where `**` may also stand for no words at all. The sequence
+// can sit anywhere in the line, and a word with `*` or `?` inside it matches
+// like a word `Pattern`. Lines are split into words like the line n-grams
+// split them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WordPattern {
+    tokens: Vec<WordToken>, // wrapped in `AnyWords`, so the match is unanchored
+}
+
+impl WordPattern {
+    pub fn parse(query: &str) -> Result<Self, SearchError> {
+        let mut tokens = vec![WordToken::AnyWords];
+        for part in normalize_query(query)?.split_whitespace() {
+            if part.chars().all(|char| char == ANY_RUN) {
+                tokens.push(if part == ANY_WORD {
+                    WordToken::AnyWord
+                } else {
+                    WordToken::AnyWords
+                });
+            } else if Pattern::is_wildcard(part) {
+                tokens.push(WordToken::Like(Pattern::parse(part)?));
+            } else {
+                tokens.extend(part.unicode_words().map(|word| WordToken::Word(word.to_string())));
+            }
+        }
+        tokens.push(WordToken::AnyWords);
+        tokens.dedup_by(|next, previous| *next == WordToken::AnyWords && *previous == WordToken::AnyWords);
+
+        if tokens.len() == 1 {
+            return Err(SearchError::EmptyQuery);
+        }
+        Ok(Self { tokens })
+    }
+
+    // Every run of literal words with no gap inside it, in order
+    pub fn literal_runs(&self) -> Vec<Vec<String>> {
+        let mut runs = Vec::new();
+        let mut current = Vec::new();
+        for token in self.tokens.iter() {
+            match token {
+                WordToken::Word(word) => current.push(word.clone()),
+                _ if !current.is_empty() => runs.push(std::mem::take(&mut current)),
+                _ => {}
+            }
+        }
+        if !current.is_empty() {
+            runs.push(current);
+        }
+        runs
+    }
+
+    // Fewest words a matching line can have
+    pub fn min_words(&self) -> usize {
+        self.tokens
+            .iter()
+            .filter(|token| **token != WordToken::AnyWords)
+            .count()
+    }
+
+    // Whether the sequence occurs somewhere in `words`
+    pub fn is_match(&self, words: &[impl AsRef<str>]) -> bool {
+        let mut positions = self.close(vec![0]);
+        for word in words.iter() {
+            positions = self.next_positions(&positions, word.as_ref());
+            if positions.is_empty() {
+                return false;
+            }
+        }
+        positions.contains(&self.tokens.len())
+    }
+
+    // A `**` can always be skipped, so reaching one reaches what follows it
+    fn close(&self, mut positions: Vec<usize>) -> Vec<usize> {
+        let mut index = 0;
+        while index < positions.len() {
+            let position = positions[index];
+            if self.tokens.get(position) == Some(&WordToken::AnyWords) && !positions.contains(&(position + 1)) {
+                positions.push(position + 1);
+            }
+            index += 1;
+        }
+        positions
+    }
+
+    fn next_positions(&self, positions: &[usize], word: &str) -> Vec<usize> {
+        let mut next = Vec::with_capacity(positions.len());
+        for position in positions.iter() {
+            let reached = match self.tokens.get(*position) {
+                Some(WordToken::Word(expected)) if expected == word => position + 1,
+                Some(WordToken::Like(pattern)) if pattern.is_match(word) => position + 1,
+                Some(WordToken::AnyWord) => position + 1,
+                Some(WordToken::AnyWords) => *position,
+                _ => continue,
+            };
+            if !next.contains(&reached) {
+                next.push(reached);
+            }
+        }
+        self.close(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, key: &str) -> bool {
+        Pattern::parse(pattern).unwrap().is_match(key)
+    }
+
+    fn invalid(pattern: &str) -> bool {
+        matches!(Pattern::parse(pattern), Err(SearchError::InvalidPattern(_)))
+    }
+
+    #[test]
+    fn runs_and_single_characters() {
+        assert!(matches("c?t", "cat"));
+        assert!(!matches("c?t", "ct"));
+        assert!(matches("inter*tion", "internation"));
+        assert!(matches("inter*tion", "intertion"));
+        assert!(!matches("inter*tion", "internations"));
+        assert!(matches("*", ""));
+        assert!(matches("***a", "banana"));
+        assert!(matches("?", "é"));
+    }
+
+    #[test]
+    fn question_mark_takes_exactly_one_character() {
+        assert!(matches("?at", "cat"));
+        assert!(matches("ca?", "cat"));
+        assert!(!matches("ca?", "ca"));
+        assert!(!matches("ca?", "cats"));
+        assert!(matches("??", "ab"));
+        assert!(!matches("??", "a"));
+        // One character, however many bytes it takes
+        assert!(matches("stra?e", "straße"));
+        assert!(matches("?", "東"));
+        assert!(!matches("?", "東京"));
+    }
+
+    #[test]
+    fn star_takes_any_run() {
+        assert!(matches("ca*", "ca"));
+        assert!(matches("ca*", "catalog"));
+        assert!(matches("*log", "catalog"));
+        assert!(matches("*tal*", "catalog"));
+        assert!(!matches("*tal*", "catlog"));
+        assert!(matches("c*t*g", "catalog"));
+        assert!(!matches("c*t*g", "catalogs"));
+        // Runs of stars are one star
+        assert!(matches("c**g", "cg"));
+        assert!(matches("*?", "a"));
+        assert!(!matches("*?", ""));
+        assert!(matches("東*", "東京都"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("gr[ae]y", "grey"));
+        assert!(!matches("gr[ae]y", "griy"));
+        assert!(matches("[a-c]at", "bat"));
+        assert!(!matches("[^a-c]at", "bat"));
+        assert!(matches("[^a-c]at", "rat"));
+        // A `-` at either end is literal, as is an escaped `]`
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[-a]", "-"));
+        assert!(matches("[\\]]", "]"));
+    }
+
+    #[test]
+    fn groups() {
+        assert!(matches("colo(u)r", "color"));
+        assert!(matches("colo(u)r", "colour"));
+        assert!(matches("(un|re)do", "redo"));
+        assert!(!matches("(un|re)do", "do"));
+        assert!(matches("(un|re|)do", "do"));
+        assert!(matches("(a*|b)c", "axyzc"));
+    }
+
+    #[test]
+    fn escapes_and_normalization() {
+        assert!(matches("a\\*", "a*"));
+        assert!(!matches("a\\*", "ab"));
+        assert!(matches("A?C", "abc"));
+        assert!(matches("[A-Z]", "q"));
+    }
+
+    #[test]
+    fn rejects_broken_patterns() {
+        assert!(invalid("x\\"));
+        assert!(invalid("[abc"));
+        assert!(invalid("[]"));
+        assert!(invalid("[z-a]"));
+        assert!(invalid("(ab"));
+        assert!(invalid("(a(b))"));
+        assert!(invalid("a)"));
+        assert!(invalid("a|b"));
+        assert!(matches!(Pattern::parse(""), Err(SearchError::EmptyQuery)));
+        assert!(matches!(Pattern::parse("  "), Err(SearchError::EmptyQuery)));
+    }
+
+    #[test]
+    fn literals() {
+        let pattern = Pattern::parse("inter*na?tion").unwrap();
+        assert_eq!(pattern.literal_prefix(), "inter");
+        assert_eq!(pattern.literal_suffix(), "tion");
+        assert_eq!(pattern.literals(), ["inter", "na", "tion"]);
+        assert_eq!(Pattern::parse("*").unwrap().literals(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reversed_matches_reversed_keys() {
+        let pattern = Pattern::parse("c(o|a)*t[sz]").unwrap().reversed();
+        assert!(pattern.is_match("stoc"));
+        assert!(pattern.is_match("ztxxac"));
+        assert!(!pattern.is_match("cats"));
+    }
+
+    #[test]
+    fn next_chars_lists_the_only_way_on() {
+        let pattern = Pattern::parse("b[ae]d").unwrap();
+        let start = pattern.first_positions();
+        assert_eq!(pattern.next_chars(&start), Some(vec!['b']));
+        let after_b = pattern.next_positions(&start, 'b');
+        assert_eq!(pattern.next_chars(&after_b), Some(vec!['a', 'e']));
+        let pattern = Pattern::parse("b*").unwrap();
+        let after_b = pattern.next_positions(&pattern.first_positions(), 'b');
+        assert_eq!(pattern.next_chars(&after_b), None);
+    }
+
+    #[test]
+    fn automaton_reads_utf8_and_separated_keys() {
+        let pattern = Pattern::parse("caf?").unwrap();
+        let automaton = pattern.automaton(false);
+        let state = "café".bytes().fold(automaton.start(), |state, byte| automaton.accept(&state, byte));
+        assert!(automaton.is_match(&state));
+
+        // Reversed keys store each grapheme followed by the separator
+        let reversed = pattern.reversed();
+        let automaton = reversed.automaton(true);
+        let mut key = String::new();
+        for grapheme in ["é", "f", "a", "c"] {
+            key.push_str(grapheme);
+            key.push(SEPARATOR);
+        }
+        let state = key.bytes().fold(automaton.start(), |state, byte| automaton.accept(&state, byte));
+        assert!(automaton.is_match(&state));
+    }
+
+    fn words(line: &str) -> Vec<&str> {
+        line.split_whitespace().collect()
+    }
+
+    #[test]
+    fn word_patterns() {
+        let pattern = WordPattern::parse("mad * dog").unwrap();
+        assert!(pattern.is_match(&words("the mad old dog barked")));
+        assert!(!pattern.is_match(&words("the mad dog")));
+        let pattern = WordPattern::parse("mad ** dog").unwrap();
+        assert!(pattern.is_match(&words("mad dog")));
+        assert!(pattern.is_match(&words("a mad and very old dog")));
+        assert!(!pattern.is_match(&words("dog mad")));
+        let pattern = WordPattern::parse("d?g ** the").unwrap();
+        assert!(pattern.is_match(&words("dig up the bone")));
+    }
+
+    #[test]
+    fn star_takes_exactly_one_word() {
+        let pattern = WordPattern::parse("quick * fox").unwrap();
+        assert!(pattern.is_match(&words("the quick brown fox")));
+        assert!(pattern.is_match(&words("quick red fox jumps")));
+        assert!(!pattern.is_match(&words("quick fox")));
+        assert!(!pattern.is_match(&words("quick brown red fox")));
+        let pattern = WordPattern::parse("* * fox").unwrap();
+        assert!(pattern.is_match(&words("quick brown fox")));
+        assert!(!pattern.is_match(&words("brown fox")));
+        // A star next to other characters is a character wildcard
+        let pattern = WordPattern::parse("qu* fox").unwrap();
+        assert!(pattern.is_match(&words("quick fox")));
+        assert!(!pattern.is_match(&words("slow fox")));
+    }
+
+    #[test]
+    fn double_star_takes_any_run_of_words() {
+        let pattern = WordPattern::parse("quick ** fox").unwrap();
+        assert!(pattern.is_match(&words("quick fox")));
+        assert!(pattern.is_match(&words("quick brown fox")));
+        assert!(pattern.is_match(&words("a quick and very brown fox")));
+        assert!(!pattern.is_match(&words("fox quick")));
+        // `**` after `*` still needs the one word
+        let pattern = WordPattern::parse("quick * ** fox").unwrap();
+        assert!(!pattern.is_match(&words("quick fox")));
+        assert!(pattern.is_match(&words("quick brown fox")));
+        assert!(pattern.is_match(&words("quick brown old fox")));
+        // A pattern of nothing but gaps matches by word count alone
+        let pattern = WordPattern::parse("* **").unwrap();
+        assert!(pattern.is_match(&words("fox")));
+        assert!(!pattern.is_match(&Vec::<&str>::new()));
+    }
+
+    #[test]
+    fn word_pattern_parsing() {
+        let pattern = WordPattern::parse("The mad * old ** dog").unwrap();
+        assert_eq!(pattern.literal_runs(), [vec!["the", "mad"], vec!["old"], vec!["dog"]]);
+        assert_eq!(pattern.min_words(), 5);
+        // Runs of `**` count once, and none of them need a word
+        assert_eq!(WordPattern::parse("** ** sea").unwrap().min_words(), 1);
+        assert!(matches!(WordPattern::parse(""), Err(SearchError::EmptyQuery)));
+        assert!(matches!(WordPattern::parse("a[b c"), Err(SearchError::InvalidPattern(_))));
+    }
+}
@@ -0,0 +1,80 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use data_structs::format;
+use data_structs::mapped::dictionary::MappedDictionary;
+use data_structs::mapped::pool::MappedPool;
+use data_structs::pool::TermLookup;
+use data_structs::segment::{self, SegmentTokens};
+use data_structs::structure::BuildStructure;
+use data_structs::trees::{fst_dict::FstDictionary, radix::RadixTrie, trie::Trie};
+use runtime::{load_structure, map_structure, segment_paths, Scope};
+
+// Stores every token of a segment into an empty structure. Only the mapped
+// layouts are kept on disk, so the trees compared here are rebuilt from the
+// segment's tokens.
+fn fill<T: BuildStructure>(mut structure: T, tokens: &SegmentTokens, pool: &MappedPool) -> T {
+    for (id, line) in tokens.iter() {
+        structure.store(pool.get(*id).unwrap_or_default(), *id, *line);
+    }
+    structure
+}
+
+fn benchmark_prefix_load(c: &mut Criterion) {
+    for (scope, name) in [(Scope::Words, "word_scope"), (Scope::Lines, "line_scope")] {
+        // The oldest segment, which holds the whole dataset after a rebuild
+        let segments = segment_paths(&scope).expect("Failed to read the segment manifest");
+        let segment = segments.first().expect("No segments to load");
+        let scope = name;
+        let mapped_path = format!("{}/trie-mapped.idx", segment);
+
+        let pool: MappedPool = map_structure(&format!("{}/pool-mapped.idx", segment)).expect("Failed to map pool");
+        let tokens: SegmentTokens =
+            load_structure(&format!("{}/{}", segment, segment::TOKENS)).expect("Failed to load tokens");
+        let trie = fill(Trie::new(), &tokens, &pool);
+        let radix = fill(RadixTrie::new(), &tokens, &pool);
+        let trie_bytes = trie.serialize().expect("Failed to encode trie");
+        let radix_bytes = radix.serialize().expect("Failed to encode radix trie");
+        let mut fst = FstDictionary::builder();
+        for (id, _) in tokens.iter() {
+            fst.store(pool.get(*id).unwrap_or_default(), *id);
+        }
+        let fst = fst.build().expect("Failed to build fst dictionary");
+        let mapped: MappedDictionary = map_structure(&mapped_path).expect("Failed to map trie");
+
+        // Print the encoded size once before the benchmark; node counts times
+        // the node size would leave out every heap allocation of the trees
+        println!(
+            "Nodes for {}: Trie {} ({} bytes encoded), Radix {} ({} bytes encoded)",
+            scope,
+            trie.node_count(),
+            trie_bytes.len(),
+            radix.node_count(),
+            radix_bytes.len(),
+        );
+        println!("Fst for {}: {} bytes of transducer", scope, fst.fst_bytes());
+
+        c.bench_function(&format!("load_Trie_{}", scope), |b| {
+            b.iter(|| format::decode::<Trie>(black_box(&trie_bytes)).unwrap())
+        });
+        c.bench_function(&format!("load_Radix_{}", scope), |b| {
+            b.iter(|| format::decode::<RadixTrie>(black_box(&radix_bytes)).unwrap())
+        });
+        c.bench_function(&format!("map_Trie_{}", scope), |b| {
+            b.iter(|| map_structure::<MappedDictionary>(black_box(&mapped_path)).unwrap())
+        });
+        c.bench_function(&format!("search_Trie_{}", scope), |b| {
+            b.iter(|| trie.search(black_box("me".to_string())))
+        });
+        c.bench_function(&format!("search_Radix_{}", scope), |b| {
+            b.iter(|| radix.search(black_box("me".to_string())))
+        });
+        c.bench_function(&format!("search_MappedTrie_{}", scope), |b| {
+            b.iter(|| mapped.search(black_box("me".to_string())))
+        });
+        c.bench_function(&format!("search_Fst_{}", scope), |b| {
+            b.iter(|| fst.search(black_box("me".to_string())))
+        });
+    }
+}
+
+criterion_group!(benches, benchmark_prefix_load);
+criterion_main!(benches);
@@ -25,7 +25,7 @@ fn benchmark_perform_search(c: &mut Criterion) {
                 term,
                 sender.clone(),
             )
-            .len();
+            .map_or(0, |results| results.len());
 
             // Print once before the benchmark
             println!("Result length for {:?}_{:?}: {}", scope, search_type, result_len);
@@ -33,7 +33,7 @@ fn benchmark_perform_search(c: &mut Criterion) {
             c.bench_function(&bench_name, move |b| {
                 b.iter(|| {
                     let (sender_inner, _receiver_inner) = channel();
-                    perform_search(
+                    let _ = perform_search(
                         black_box(&index_clone),
                         black_box(scope_clone.clone()),
                         black_box(search_type_clone.clone()),
@@ -0,0 +1,31 @@
+use std::{io, path::PathBuf};
+
+use data_structs::document::DocumentId;
+use data_structs::error::{FormatError, SearchError};
+use thiserror::Error;
+
+use crate::SearchType;
+
+#[derive(Debug, Error)]
+pub enum Errors {
+    #[error("could not locate the project root above {0:?}")]
+    ProjectRoot(PathBuf),
+    #[error("index {path:?} is missing, run `cargo run -p compile` first: {source}")]
+    IndexMissing { path: PathBuf, source: io::Error },
+    #[error("index {path:?} is unusable: {source}")]
+    IndexFormat { path: PathBuf, source: FormatError },
+    #[error("index {0} was not loaded")]
+    IndexNotLoaded(String),
+    #[error("{0} cannot answer {1:?} searches")]
+    Unsupported(String, SearchType),
+    #[error("document store is unusable: {0}")]
+    StoreFormat(FormatError),
+    #[error("document {document} has no text at byte {offset}")]
+    NoContext { document: DocumentId, offset: u64 },
+    #[error("invalid query: {0}")]
+    InvalidQuery(#[from] SearchError),
+    #[error("search thread panicked")]
+    SearchPanicked,
+    #[error("search results had nowhere to go, the window was closed")]
+    Disconnected,
+}
@@ -1,156 +1,390 @@
-use bincode::config;
-use std::collections::HashMap;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::SystemTime;
 use unicode_segmentation::UnicodeSegmentation;
 
 use levenshtein::levenshtein;
 
-use data_structs::trees;
+use data_structs::document::{self, Context, Document, DocumentId, Location, Window};
+use data_structs::format;
+use data_structs::fuzzy;
+use data_structs::mapped::{self, Mapped};
+use data_structs::pool::{TermId, TermLookup};
+use data_structs::regex_query::RegexQuery;
+use data_structs::segment::{self, Manifest};
+use data_structs::structure::SearchStructure;
+use data_structs::wildcard::{Pattern, WordPattern};
 
-use trees::ngram::NGramIndex;
-use trees::suffix::SuffixTree;
-use trees::trie::Trie;
+use mapped::dictionary::MappedDictionary;
+use mapped::locations::MappedLocations;
+use mapped::ngram::MappedNGram;
+use mapped::pool::MappedPool;
+use mapped::store::MappedStore;
+use mapped::suffix_array::MappedSuffixArray;
 
-#[derive(Debug, Clone)]
+mod error;
+pub use error::Errors;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Scope {
     Words,
     Lines,
 }
 
-#[derive(Clone)]
-pub enum SearchIndex {
-    Trie(Trie),
-    SuffixTree(SuffixTree),
-    NGramIndex(NGramIndex),
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum SearchType {
     Prefix,
     Suffix,
     Contains,
+    Fuzzy,
+    Wildcard,
+    Regex,
+}
+
+// Edits a fuzzy search allows unless told otherwise
+pub const DEFAULT_FUZZY_DISTANCE: u32 = 2;
+
+// One segment of a scope: its pool and every loaded structure, keyed by the
+// kind of search it answers, plus where its lines came from for the line
+// scope. Structures are shared, so cloning is cheap.
+#[derive(Clone)]
+pub struct Segment {
+    name: String,
+    pool: Arc<dyn TermLookup + Send + Sync>,
+    structures: HashMap<SearchType, Arc<dyn SearchStructure>>,
+    locations: Option<Arc<MappedLocations>>,
+}
+
+impl Segment {
+    pub fn new(name: &str, pool: Arc<dyn TermLookup + Send + Sync>) -> Self {
+        Self {
+            name: name.to_string(),
+            pool,
+            structures: HashMap::new(),
+            locations: None,
+        }
+    }
+
+    pub fn set_locations(&mut self, locations: Arc<MappedLocations>) {
+        self.locations = Some(locations);
+    }
+
+    // Every place the pool entry `id` occurs; none for segments without
+    // locations, i.e. the word scope
+    pub fn locations(&self, id: TermId) -> Vec<Location> {
+        self.locations.as_ref().map(|locations| locations.get(id)).unwrap_or_default()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn insert(&mut self, search_type: SearchType, structure: Arc<dyn SearchStructure>) {
+        self.structures.insert(search_type, structure);
+    }
+
+    // Every structure of the segment answers with ids into this pool
+    pub fn pool(&self) -> &(dyn TermLookup + Send + Sync) {
+        self.pool.as_ref()
+    }
+
+    pub fn structure(&self, search_type: &SearchType) -> Result<&dyn SearchStructure, Errors> {
+        self.structures
+            .get(search_type)
+            .map(Arc::as_ref)
+            .ok_or_else(|| Errors::IndexNotLoaded(format!("{} {:?}", self.name, search_type)))
+    }
+}
+
+// The segments of every scope, oldest first; a search runs on each of them
+// and merges what they find. Cloning the index is cheap.
+#[derive(Clone)]
+pub struct SearchIndex {
+    segments: HashMap<Scope, Vec<Segment>>,
+    manifests_modified: HashMap<Scope, Option<SystemTime>>, // when the loaded manifests were written
+    documents: Arc<Vec<Document>>,
+    store: Option<Arc<MappedStore>>,
+    fuzzy_distance: u32,
+}
+
+impl Default for SearchIndex {
+    fn default() -> Self {
+        Self {
+            segments: HashMap::new(),
+            manifests_modified: HashMap::new(),
+            documents: Arc::new(Vec::new()),
+            store: None,
+            fuzzy_distance: DEFAULT_FUZZY_DISTANCE,
+        }
+    }
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Maximum edits of a `SearchType::Fuzzy` search, clamped to 1..=2
+    pub fn set_fuzzy_distance(&mut self, max_distance: u32) {
+        self.fuzzy_distance = max_distance.clamp(fuzzy::MIN_DISTANCE, fuzzy::MAX_DISTANCE);
+    }
+
+    pub fn fuzzy_distance(&self) -> u32 {
+        self.fuzzy_distance
+    }
+
+    pub fn set_documents(&mut self, documents: Vec<Document>) {
+        self.documents = Arc::new(documents);
+    }
+
+    // Every indexed document with its metadata, in id order
+    pub fn documents(&self) -> &[Document] {
+        &self.documents
+    }
+
+    // The file a `Location` points into
+    pub fn document(&self, id: DocumentId) -> Option<&Document> {
+        self.documents.get(id as usize).filter(|document| document.id == id)
+    }
+
+    pub fn set_store(&mut self, store: MappedStore) {
+        self.store = Some(Arc::new(store));
+    }
+
+    // The text around byte `offset` of a document, e.g. a result's
+    // `Location`, read from the document store rather than the original file
+    pub fn context(&self, document: DocumentId, offset: u64, window: Window) -> Result<Context, Errors> {
+        let store = self
+            .store
+            .as_ref()
+            .ok_or_else(|| Errors::IndexNotLoaded("document store".to_string()))?;
+        store
+            .context(document, offset, window)
+            .map_err(Errors::StoreFormat)?
+            .ok_or(Errors::NoContext { document, offset })
+    }
+
+    pub fn push_segment(&mut self, scope: Scope, segment: Segment) {
+        self.segments.entry(scope).or_default().push(segment);
+    }
+
+    pub fn segments(&self, scope: &Scope) -> Result<&[Segment], Errors> {
+        self.segments
+            .get(scope)
+            .map(Vec::as_slice)
+            .filter(|segments| !segments.is_empty())
+            .ok_or_else(|| Errors::IndexNotLoaded(format!("{:?} segments", scope)))
+    }
+
+    // Whether `compile` changed the segments since the index was loaded.
+    // Every change writes a new manifest, so this only looks at when the
+    // manifests were written, not at what they say.
+    pub fn is_stale(&self) -> bool {
+        SCOPES
+            .iter()
+            .any(|scope| self.manifests_modified.get(scope).copied().flatten() != manifest_modified(scope))
+    }
+
+    // Loads the current segments in place of the ones this index holds;
+    // searches still running on a clone finish on the old ones
+    pub fn reload(&mut self) -> Result<(), Errors> {
+        let fuzzy_distance = self.fuzzy_distance;
+        *self = load_index()?;
+        self.fuzzy_distance = fuzzy_distance;
+        Ok(())
+    }
+}
+
+// How many ranked results a search returns
+pub const MAX_RESULTS: usize = 100;
+
+// One ranked match: its priority (lower is better), the matched entry and,
+// for line searches, every place that line occurs, so it can be opened at
+// the source. Word results have no locations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub priority: u8,
+    pub text: String,
+    pub locations: Vec<Location>,
 }
 
 pub enum AppMessage {
-    SearchComplete(Vec<(u8, String)>, std::time::Duration),
+    SearchComplete(Vec<SearchResult>, std::time::Duration),
+    SearchFailed(Errors),
     Debug(String),
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TermStats {
+    pub term_frequency: u64,
+    pub line_frequency: u64,
+    pub vocabulary_size: usize,
+}
+
+// Corpus statistics for an exact term, for ranking and analytics. They come
+// from the prefix structure of each segment, which sees every key exactly
+// once; segments hold different lines, so their counts add up, but a key in
+// several segments counts once per segment in the vocabulary size until they
+// are merged.
+pub fn term_stats(index: &SearchIndex, scope: &Scope, term: &str) -> Result<TermStats, Errors> {
+    let mut total = TermStats {
+        term_frequency: 0,
+        line_frequency: 0,
+        vocabulary_size: 0,
+    };
+    for segment in index.segments(scope)? {
+        let prefix = segment.structure(&SearchType::Prefix)?;
+        let stats = prefix.stats(term);
+        total.term_frequency += stats.term_frequency;
+        total.line_frequency += stats.line_frequency;
+        total.vocabulary_size += prefix.len();
+    }
+    Ok(total)
+}
+
+// The best MAX_RESULTS matches across segments; a candidate is only copied
+// out of its pool, and its locations looked up, once it makes that cut. An
+// entry found in several segments is kept once with the locations of every
+// copy: its priority only depends on its text, so every copy ranks the same.
+#[derive(Default)]
+struct Best {
+    heap: BinaryHeap<(u8, String)>,
+    locations: HashMap<String, Vec<Location>>, // of every entry in the heap
+    ranked: usize, // entries offered, up to where each walk stopped
+}
+
+impl Best {
+    fn offer(&mut self, priority: u8, item: &str, locations: impl FnOnce() -> Vec<Location>) {
+        self.ranked += 1;
+        if let Some(kept) = self.locations.get_mut(item) {
+            kept.extend(locations());
+            return;
+        }
+        if self.heap.len() == MAX_RESULTS {
+            match self.heap.peek() {
+                Some((worst, worst_item)) if (priority, item) < (*worst, worst_item.as_str()) => {}
+                _ => return,
+            }
+            if let Some((_, dropped)) = self.heap.pop() {
+                self.locations.remove(&dropped);
+            }
+        }
+        self.heap.push((priority, item.to_string()));
+        self.locations.insert(item.to_string(), locations());
+    }
+
+    // Whether an entry of this priority can still make the cut; ties are
+    // broken by text, so they are let through
+    fn takes(&self, priority: u8) -> bool {
+        self.heap.len() < MAX_RESULTS || self.heap.peek().is_some_and(|(worst, _)| priority <= *worst)
+    }
+
+    // Best first, locations in document and line order
+    fn into_results(mut self) -> Vec<SearchResult> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|(priority, text)| {
+                let mut locations = self.locations.remove(&text).unwrap_or_default();
+                locations.sort_unstable();
+                SearchResult {
+                    priority,
+                    text,
+                    locations,
+                }
+            })
+            .collect()
+    }
+}
+
 pub fn perform_search(
-    index: &HashMap<String, SearchIndex>,
+    index: &SearchIndex,
     scope: Scope,
     search_type: SearchType,
     term: &str,
     debug_sender: Sender<AppMessage>,
-) -> Vec<(u8, String)> {
-    let mut sorted_result: Vec<(u8, String)> = Vec::new();
-    let scope_path = match scope {
-        Scope::Words => "word_scope",
-        Scope::Lines => "line_scope",
-    };
-
-    let type_path = match search_type {
-        SearchType::Prefix => "trie-serial.bin",
-        SearchType::Suffix => "suffix-serial.bin",
-        SearchType::Contains => "ngram-serial.bin",
-    };
+) -> Result<Vec<SearchResult>, Errors> {
+    let stats = term_stats(index, &scope, term)?;
+    let message_stats = format!(
+        "'{}' occurs {} times on {} lines",
+        term, stats.term_frequency, stats.line_frequency
+    );
+    if let Err(e) = debug_sender.send(AppMessage::Debug(message_stats)) {
+        eprintln!("Failed to send debug message: {}", e);
+    }
 
-    let path = format!("./serialized_outputs/{}/{}", scope_path, type_path);
-    //let file_path = Path::new(&path);
+    let mut best = Best::default();
+    for segment in index.segments(&scope)? {
+        search_segment(index, segment, &scope, &search_type, term, &debug_sender, &mut best)?;
+    }
 
-    if let Err(e) = debug_sender.send(AppMessage::Debug(format!("Searching in file: {}", path))) {
-        eprintln!("Failed to send debug message: {}", e);
+    if best.ranked > 0 {
+        let message = format!("{} entries ranked", best.ranked);
+        if let Err(e) = debug_sender.send(AppMessage::Debug(message)) {
+            eprintln!("Failed to send debug message: {}", e);
+        }
     }
 
-    let message = match search_type {
-        SearchType::Prefix => "TRIE decoded successfully".to_string(),
-        SearchType::Suffix => "SUFFIX decoded successfully".to_string(),
-        SearchType::Contains => "NGRAM decoded successfully".to_string(),
-    };
+    Ok(best.into_results())
+}
 
-    let results = match search_type {
-        SearchType::Contains => match scope {
-            Scope::Words => index.get("NGramIndex_Word").and_then(|idx| {
-                if let SearchIndex::NGramIndex(ngram_index) = idx {
-                    ngram_index
-                        .search(term.to_string())
-                        .ok()
-                        .map(Some)
-                        .unwrap_or(None)
-                } else {
-                    None
-                }
-            }),
-            Scope::Lines => index.get("NGramIndex_Line").and_then(|idx| {
-                if let SearchIndex::NGramIndex(ngram_index) = idx {
-                    ngram_index
-                        .search(term.to_string())
-                        .ok()
-                        .map(Some)
-                        .unwrap_or(None)
-                } else {
-                    None
-                }
-            }),
-        },
-        SearchType::Suffix => match scope {
-            Scope::Words => index.get("SuffixTree_Word").and_then(|idx| {
-                if let SearchIndex::SuffixTree(suffix_tree) = idx {
-                    suffix_tree
-                        .search(term.to_string())
-                        .ok()
-                        .map(Some)
-                        .unwrap_or(None)
-                } else {
-                    None
-                }
-            }),
-            Scope::Lines => index.get("SuffixTree_Line").and_then(|idx| {
-                if let SearchIndex::SuffixTree(suffix_tree) = idx {
-                    suffix_tree
-                        .search(term.to_string())
-                        .ok()
-                        .map(Some)
-                        .unwrap_or(None)
-                } else {
-                    None
-                }
-            }),
-        },
-        SearchType::Prefix => match scope {
-            Scope::Words => index.get("Trie_Word").and_then(|idx| {
-                if let SearchIndex::Trie(trie) = idx {
-                    trie.search(term.to_string()).ok().map(Some).unwrap_or(None)
-                } else {
-                    None
-                }
-            }),
-            Scope::Lines => index.get("Trie_Line").and_then(|idx| {
-                if let SearchIndex::Trie(trie) = idx {
-                    trie.search(term.to_string()).ok().map(Some).unwrap_or(None)
-                } else {
-                    None
-                }
-            }),
-        },
+// Ranks the matches of one segment into `best`
+fn search_segment(
+    index: &SearchIndex,
+    segment: &Segment,
+    scope: &Scope,
+    search_type: &SearchType,
+    term: &str,
+    debug_sender: &Sender<AppMessage>,
+    best: &mut Best,
+) -> Result<(), Errors> {
+    // Every structure answers with ids into the segment's string pool
+    let pool = segment.pool();
+    let (structure, planned_matches) = match planned_search(segment, scope, search_type, term)? {
+        Some((structure, ids)) => (structure, Some(ids)),
+        None => (segment.structure(search_type)?, None),
     };
+    let message = format!("Searching {} with {}", segment.name(), structure.describe());
+    if let Err(e) = debug_sender.send(AppMessage::Debug(message)) {
+        eprintln!("Failed to send debug message: {}", e);
+    }
 
-    if let Some(results) = results {
-        if let Err(e) = debug_sender.send(AppMessage::Debug("File read successfully".to_string())) {
-            eprintln!("Failed to send debug message: {}", e);
+    if let Some(ids) = planned_matches {
+        for id in ids {
+            if let Some(item) = pool.get(id) {
+                best.offer(levenshtein(term, item) as u8, item, || segment.locations(id));
+            }
         }
-        if let Err(e) = debug_sender.send(AppMessage::Debug(message)) {
-            eprintln!("Failed to send debug message: {}", e);
+    } else if *search_type == SearchType::Fuzzy {
+        // Fuzzy matches come ranked by the distance the automaton measured
+        let matches = structure
+            .search_fuzzy(term, index.fuzzy_distance())?
+            .ok_or_else(|| Errors::Unsupported(structure.describe(), search_type.clone()))?;
+        for (distance, id) in matches {
+            if let Some(item) = pool.get(id) {
+                best.offer(distance as u8, item, || segment.locations(id));
+            }
         }
+    } else {
+        // Every match holds the term, so its edit distance is the number of
+        // characters it has beyond it. They come shortest first, so once
+        // `best` is full the first one that cannot make the cut ends the walk.
+        structure.search_by_length(pool, term, &mut |extra, id| {
+            let priority = u8::try_from(extra).unwrap_or(u8::MAX);
+            if !best.takes(priority) {
+                return ControlFlow::Break(());
+            }
+            let Some(item) = pool.get(id) else {
+                return ControlFlow::Continue(());
+            };
 
-        for item in results.iter() {
             if matches!(scope, Scope::Lines) {
                 let lines_scope = item.unicode_words().collect::<Vec<&str>>();
-                if let (Some(first_word), Some(last_word)) =
-                    (lines_scope.first(), lines_scope.last())
-                {
+                if let (Some(first_word), Some(last_word)) = (lines_scope.first(), lines_scope.last()) {
                     let condition = match search_type {
                         SearchType::Contains => {
                             *first_word.to_lowercase() != term.to_lowercase()
@@ -158,86 +392,459 @@ pub fn perform_search(
                         }
                         SearchType::Suffix => *last_word.to_lowercase() == term.to_lowercase(),
                         SearchType::Prefix => *first_word.to_lowercase() == term.to_lowercase(),
+                        SearchType::Fuzzy | SearchType::Wildcard | SearchType::Regex => true,
                     };
                     if condition {
-                        let priority = levenshtein(term, item);
-                        sorted_result.push((priority as u8, item.to_string()));
+                        best.offer(priority, item, || segment.locations(id));
                     }
                 }
             } else {
-                let priority = levenshtein(term, item);
-                sorted_result.push((priority as u8, item.to_string()));
+                best.offer(priority, item, || segment.locations(id));
+            }
+            ControlFlow::Continue(())
+        })?;
+    }
+    Ok(())
+}
+
+// Queries that pick the structure answering them when they are run
+enum Planned {
+    Chars(Pattern),
+    Words(WordPattern),
+    Regex(RegexQuery),
+}
+
+// The structures to ask, best first, for search types planned per query.
+//
+// A word wildcard is a character `Pattern` (`*`, `?`, `[ae]`, `(u)`): the
+// prefix trie takes patterns starting with their longer literal end, the
+// suffix tree ones ending with it, and the gram index the rest, if a literal
+// is at least one gram long. In lines `*` and `**` stand for whole words, and
+// the line grams narrow down the candidates. A regex goes to the gram index,
+// which prefilters with the literals every match contains. The prefix
+// structure can answer anything, if slowly.
+fn plan(scope: &Scope, search_type: &SearchType, term: &str) -> Result<Option<(Vec<SearchType>, Planned)>, Errors> {
+    Ok(Some(match (search_type, scope) {
+        (SearchType::Wildcard, Scope::Words) => {
+            let pattern = Pattern::parse(term)?;
+            let prefix = pattern.literal_prefix().chars().count();
+            let suffix = pattern.literal_suffix().chars().count();
+            let plan = if prefix > 0 && prefix >= suffix {
+                vec![SearchType::Prefix]
+            } else if suffix > 0 {
+                vec![SearchType::Suffix, SearchType::Prefix]
+            } else {
+                vec![SearchType::Wildcard, SearchType::Prefix]
+            };
+            (plan, Planned::Chars(pattern))
+        }
+        (SearchType::Wildcard, Scope::Lines) => (
+            vec![SearchType::Wildcard, SearchType::Prefix],
+            Planned::Words(WordPattern::parse(term)?),
+        ),
+        (SearchType::Regex, _) => (
+            vec![SearchType::Regex, SearchType::Prefix],
+            Planned::Regex(RegexQuery::parse(term)?),
+        ),
+        _ => return Ok(None),
+    }))
+}
+
+// The structure that answered a planned query, with the ids it found
+type Answer<'a> = (&'a dyn SearchStructure, Vec<TermId>);
+
+// Runs a planned query on the first structure of its plan that can narrow
+// it down, or `None` when the search type is not planned
+fn planned_search<'a>(
+    segment: &'a Segment,
+    scope: &Scope,
+    search_type: &SearchType,
+    term: &str,
+) -> Result<Option<Answer<'a>>, Errors> {
+    let Some((plan, query)) = plan(scope, search_type, term)? else {
+        return Ok(None);
+    };
+    let pool = segment.pool();
+    for candidate in plan {
+        let Ok(structure) = segment.structure(&candidate) else {
+            continue;
+        };
+        let ids = match &query {
+            Planned::Chars(pattern) => structure.search_wildcard(pool, pattern)?,
+            Planned::Words(pattern) => structure.search_word_wildcard(pool, pattern)?,
+            Planned::Regex(regex) => structure.search_regex(pool, regex)?,
+        };
+        if let Some(ids) = ids {
+            return Ok(Some((structure, ids)));
+        }
+    }
+    Err(Errors::Unsupported(format!("{} index", segment.name()), search_type.clone()))
+}
+
+// Autocomplete: the `limit` most frequent matches of `term`, best first, with
+// how often each occurs. Structures built with `compile --top-k` answer from
+// their precomputed lists; anything else is ranked from a full search. Each
+// segment puts forward its own best `limit`, which are then ranked by how
+// often they occur in all segments together.
+pub fn complete(
+    index: &SearchIndex,
+    scope: &Scope,
+    search_type: &SearchType,
+    term: &str,
+    limit: usize,
+) -> Result<Vec<(u64, String)>, Errors> {
+    let segments = index.segments(scope)?;
+    let mut candidates: Vec<String> = Vec::new();
+    let mut seen = HashSet::new();
+    for segment in segments {
+        for item in segment_completions(segment, search_type, term, limit)? {
+            if seen.insert(item.clone()) {
+                candidates.push(item);
             }
         }
     }
 
-    sorted_result.sort();
-    sorted_result.truncate(100);
-    sorted_result
+    let mut ranked = Vec::with_capacity(candidates.len());
+    for item in candidates {
+        let mut frequency = 0;
+        for segment in segments {
+            frequency += segment.structure(search_type)?.stats(&item).term_frequency;
+        }
+        ranked.push((frequency, item));
+    }
+    // Stable, so ties keep the order the segments found them in
+    ranked.sort_by_key(|(frequency, _)| std::cmp::Reverse(*frequency));
+    ranked.truncate(limit);
+    Ok(ranked)
+}
+
+// The `limit` most frequent matches of `term` within one segment
+fn segment_completions(
+    segment: &Segment,
+    search_type: &SearchType,
+    term: &str,
+    limit: usize,
+) -> Result<Vec<String>, Errors> {
+    let pool = segment.pool();
+    let structure = segment.structure(search_type)?;
+    let frequency = |item: &str| structure.stats(item).term_frequency;
+
+    let ids = match structure.top_completions(term)? {
+        Some(mut ids) => {
+            ids.truncate(limit);
+            ids
+        }
+        None => {
+            let mut ranked = Vec::new();
+            structure.search_each(pool, term, &mut |id| {
+                ranked.push((frequency(pool.get(id).unwrap_or_default()), id));
+                ControlFlow::Continue(())
+            })?;
+            ranked.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+            ranked.into_iter().take(limit).map(|(_, id)| id).collect()
+        }
+    };
+
+    Ok(ids.into_iter().filter_map(|id| pool.get(id)).map(str::to_string).collect())
 }
 
-pub fn load_index() -> Result<HashMap<String, SearchIndex>, String> {
-    let base_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+fn project_root() -> Result<PathBuf, Errors> {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    manifest_dir
         .parent() // go one directory up
-        .ok_or("Failed to determine project root")?
-        .to_path_buf();
+        .map(Path::to_path_buf)
+        .ok_or_else(|| Errors::ProjectRoot(manifest_dir.to_path_buf()))
+}
 
-    let paths = [
-        (   "Trie_Word",
-            "serialized_outputs/word_scope/trie-serial.bin"
-        ),
-        (   "Trie_Line",
-            "serialized_outputs/line_scope/trie-serial.bin"
-        ),
-        (
-            "SuffixTree_Word",
-            "serialized_outputs/word_scope/suffix-serial.bin",
-        ),
-        (
-            "SuffixTree_Line",
-            "serialized_outputs/line_scope/suffix-serial.bin",
-        ),
-        (
-            "NGramIndex_Word",
-            "serialized_outputs/word_scope/ngram-serial.bin",
-        ),
-        (
-            "NGramIndex_Line",
-            "serialized_outputs/line_scope/ngram-serial.bin",
-        ),
-    ];
-
-    let mut result = HashMap::new();
-
-    for (key, relative_path) in paths.iter() {
-        let full_path = base_path.join(relative_path);
-        let contents =
-            fs::read(&full_path).map_err(|_| format!("Failed to read file: {:?}", full_path))?;
-        let decoded: SearchIndex = match *key {
-            "Trie_Word" | "Trie_Line" => {
-                let trie: Trie = bincode::decode_from_slice(&contents, config::standard())
-                    .map_err(|_| format!("Failed to decode trie: {:?}", full_path))?
-                    .0;
-                SearchIndex::Trie(trie)
+// Reads and decodes a single serialized structure, relative to the project root
+pub fn load_structure<T: bincode::Decode<()>>(relative_path: &str) -> Result<T, Errors> {
+    let path = project_root()?.join(relative_path);
+    let contents = fs::read(&path).map_err(|source| Errors::IndexMissing {
+        path: path.clone(),
+        source,
+    })?;
+    format::decode(&contents).map_err(|source| Errors::IndexFormat { path, source })
+}
+
+// Memory-maps a single structure, relative to the project root. Only its
+// header is read here; searches read the pages they need straight from the
+// page cache, which every runtime process shares.
+pub fn map_structure<T: Mapped>(relative_path: &str) -> Result<T, Errors> {
+    let path = project_root()?.join(relative_path);
+    mapped::map(&path)
+        .map_err(|source| Errors::IndexMissing {
+            path: path.clone(),
+            source,
+        })?
+        .map_err(|source| Errors::IndexFormat { path, source })
+}
+
+type Opener = fn(&str) -> Result<Arc<dyn SearchStructure>, Errors>;
+
+fn open<T: Mapped + SearchStructure + 'static>(relative_path: &str) -> Result<Arc<dyn SearchStructure>, Errors> {
+    Ok(Arc::new(map_structure::<T>(relative_path)?))
+}
+
+// Which structure answers each search, by its file in a segment; a new
+// structure only needs a row here
+const STRUCTURES: [(Scope, SearchType, &str, Opener); 12] = [
+    (Scope::Words, SearchType::Prefix, "trie-mapped.idx", open::<MappedDictionary>),
+    (Scope::Lines, SearchType::Prefix, "trie-mapped.idx", open::<MappedDictionary>),
+    (Scope::Words, SearchType::Fuzzy, "trie-mapped.idx", open::<MappedDictionary>),
+    (Scope::Lines, SearchType::Fuzzy, "trie-mapped.idx", open::<MappedDictionary>),
+    (Scope::Words, SearchType::Suffix, "suffix-mapped.idx", open::<MappedDictionary>),
+    (Scope::Lines, SearchType::Suffix, "suffix-mapped.idx", open::<MappedDictionary>),
+    (Scope::Words, SearchType::Contains, "suffix-array-mapped.idx", open::<MappedSuffixArray>),
+    (Scope::Words, SearchType::Wildcard, "ngram-mapped.idx", open::<MappedNGram>),
+    (Scope::Lines, SearchType::Wildcard, "ngram-mapped.idx", open::<MappedNGram>),
+    (Scope::Words, SearchType::Regex, "ngram-mapped.idx", open::<MappedNGram>),
+    (Scope::Lines, SearchType::Regex, "ngram-mapped.idx", open::<MappedNGram>),
+    (Scope::Lines, SearchType::Contains, "ngram-mapped.idx", open::<MappedNGram>),
+];
+
+const POOL: &str = "pool-mapped.idx";
+
+// Only line scope segments know where their entries came from
+const LOCATIONS: &str = "locations-mapped.idx";
+
+const SCOPES: [Scope; 2] = [Scope::Words, Scope::Lines];
+
+// Where a scope's manifest and segments live
+fn scope_path(scope: &Scope) -> &'static str {
+    match scope {
+        Scope::Words => "serialized_outputs/word_scope",
+        Scope::Lines => "serialized_outputs/line_scope",
+    }
+}
+
+// `None` when the manifest is missing or the platform keeps no times
+fn manifest_modified(scope: &Scope) -> Option<SystemTime> {
+    let path = project_root().ok()?.join(scope_path(scope)).join(segment::MANIFEST);
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn load_manifest(scope: &Scope) -> Result<Manifest, Errors> {
+    load_structure(&format!("{}/{}", scope_path(scope), segment::MANIFEST))
+}
+
+// Directories of the live segments of a scope, oldest first, relative to
+// the project root
+pub fn segment_paths(scope: &Scope) -> Result<Vec<String>, Errors> {
+    Ok(load_manifest(scope)?
+        .segments
+        .iter()
+        .map(|segment| format!("{}/{}", scope_path(scope), segment.name))
+        .collect())
+}
+
+// Maps every segment the manifests list. A segment merged away in the
+// meantime is still readable from the maps, even once `compile` deletes it.
+pub fn load_index() -> Result<SearchIndex, Errors> {
+    let mut index = SearchIndex::new();
+    index.set_documents(load_structure(&format!("serialized_outputs/{}", document::DOCUMENTS))?);
+    index.set_store(map_structure(&format!("serialized_outputs/{}", document::STORE))?);
+    for scope in SCOPES {
+        // Taken first, so a manifest written while this loads shows as stale
+        let modified = manifest_modified(&scope);
+        let manifest = load_manifest(&scope)?;
+        for info in manifest.segments.iter() {
+            let segment_path = format!("{}/{}", scope_path(&scope), info.name);
+            let pool = map_structure::<MappedPool>(&format!("{}/{}", segment_path, POOL))?;
+            let mut segment = Segment::new(&info.name, Arc::new(pool));
+            for (_, search_type, file, open) in STRUCTURES.iter().filter(|(other, ..)| *other == scope) {
+                segment.insert(search_type.clone(), open(&format!("{}/{}", segment_path, file))?);
             }
-            "SuffixTree_Word" | "SuffixTree_Line" => {
-                let suffix_tree: SuffixTree =
-                    bincode::decode_from_slice(&contents, config::standard())
-                        .map_err(|_| format!("Failed to decode suffix tree: {:?}", full_path))?
-                        .0;
-                SearchIndex::SuffixTree(suffix_tree)
+            if scope == Scope::Lines {
+                let locations = map_structure::<MappedLocations>(&format!("{}/{}", segment_path, LOCATIONS))?;
+                segment.set_locations(Arc::new(locations));
             }
-            "NGramIndex_Word" | "NGramIndex_Line" => {
-                let ngram_index: NGramIndex =
-                    bincode::decode_from_slice(&contents, config::standard())
-                        .map_err(|_| format!("Failed to decode ngram: {:?}", full_path))?
-                        .0;
-                SearchIndex::NGramIndex(ngram_index)
+            index.push_segment(scope.clone(), segment);
+        }
+        index.manifests_modified.insert(scope, modified);
+    }
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use SearchType::{Prefix, Suffix, Wildcard};
+
+    use data_structs::error::SearchError;
+    use data_structs::pool::StringPool;
+    use data_structs::structure::{BuildStructure, KeyStats};
+    use data_structs::trees::ngram::{NGramIndex, SearchScopeNgram};
+    use data_structs::trees::{suffix::SuffixTree, suffix_array::SuffixArray, trie::Trie};
+
+    const WORDS: [&str; 8] = ["grey", "gray", "greet", "sing", "ring", "rings", "string", "bring"];
+
+    fn fill<T: BuildStructure + 'static>(mut structure: T, pool: &StringPool) -> Arc<dyn SearchStructure> {
+        for (line, word) in WORDS.iter().enumerate() {
+            structure.store(word, line as TermId, line as u64);
+        }
+        structure.build(pool);
+        Arc::new(structure)
+    }
+
+    // A word scope segment with the in-memory counterpart of every structure
+    // `load_index` maps, less the search types in `without`
+    fn segment(without: &[SearchType]) -> Segment {
+        let mut pool = StringPool::new();
+        for word in WORDS {
+            pool.intern(word).unwrap();
+        }
+        let ngram = NGramIndex::builder().scope(SearchScopeNgram::Words).gram_size(2).build();
+        let structures = [
+            (SearchType::Prefix, fill(Trie::new(), &pool)),
+            (SearchType::Fuzzy, fill(Trie::new(), &pool)),
+            (SearchType::Suffix, fill(SuffixTree::new(), &pool)),
+            (SearchType::Contains, fill(SuffixArray::new(), &pool)),
+            (SearchType::Wildcard, fill(ngram.clone(), &pool)),
+            (SearchType::Regex, fill(ngram, &pool)),
+        ];
+        let mut segment = Segment::new("test", Arc::new(pool));
+        for (search_type, structure) in structures {
+            if !without.contains(&search_type) {
+                segment.insert(search_type, structure);
             }
-            _ => return Err(format!("Unknown key: {}", key)),
-        };
-        result.insert(key.to_string(), decoded);
+        }
+        segment
+    }
+
+    fn search(index: &SearchIndex, search_type: SearchType, term: &str) -> Vec<String> {
+        let (sender, _receiver) = mpsc::channel();
+        let mut found: Vec<String> = perform_search(index, Scope::Words, search_type, term, sender)
+            .unwrap()
+            .into_iter()
+            .map(|result| result.text)
+            .collect();
+        found.sort();
+        found
+    }
+
+    fn planned(scope: Scope, search_type: SearchType, term: &str) -> Option<Vec<SearchType>> {
+        plan(&scope, &search_type, term).unwrap().map(|(plan, _)| plan)
+    }
+
+    #[test]
+    fn dispatches_every_search_type_to_its_structure() {
+        let mut index = SearchIndex::new();
+        index.push_segment(Scope::Words, segment(&[]));
+
+        assert_eq!(search(&index, SearchType::Prefix, "gr"), ["gray", "greet", "grey"]);
+        assert_eq!(search(&index, SearchType::Suffix, "ing"), ["bring", "ring", "sing", "string"]);
+        // Strictly inside: "rings" starts with the query
+        assert_eq!(search(&index, SearchType::Contains, "rin"), ["bring", "string"]);
+        assert_eq!(search(&index, SearchType::Fuzzy, "gery"), ["gray", "grey"]);
+        assert_eq!(search(&index, SearchType::Wildcard, "gr?y"), ["gray", "grey"]);
+        assert_eq!(search(&index, SearchType::Regex, "^r.*s$"), ["rings"]);
+
+        let segment = &index.segments(&Scope::Words).unwrap()[0];
+        assert_eq!(segment.structure(&SearchType::Suffix).unwrap().describe(), "SuffixTree");
+        assert!(matches!(index.segments(&Scope::Lines), Err(Errors::IndexNotLoaded(_))));
+    }
+
+    #[test]
+    fn plans_word_wildcards_by_their_longer_literal_end() {
+        assert_eq!(planned(Scope::Words, Wildcard, "gr*"), Some(vec![Prefix]));
+        assert_eq!(planned(Scope::Words, Wildcard, "gr?ing"), Some(vec![Suffix, Prefix]));
+        assert_eq!(planned(Scope::Words, Wildcard, "gre?y"), Some(vec![Prefix]));
+        assert_eq!(planned(Scope::Words, Wildcard, "*ee*"), Some(vec![Wildcard, Prefix]));
+        assert_eq!(planned(Scope::Lines, Wildcard, "* ring"), Some(vec![Wildcard, Prefix]));
+        assert_eq!(planned(Scope::Words, SearchType::Regex, "r.*s"), Some(vec![SearchType::Regex, Prefix]));
+        let lines = plan(&Scope::Lines, &Wildcard, "* ring").unwrap();
+        assert!(matches!(lines, Some((_, Planned::Words(_)))));
+        for search_type in [SearchType::Prefix, SearchType::Suffix, SearchType::Contains, SearchType::Fuzzy] {
+            assert_eq!(planned(Scope::Words, search_type, "gr*"), None);
+        }
+        assert!(matches!(plan(&Scope::Words, &SearchType::Regex, "(r"), Err(Errors::InvalidQuery(_))));
+    }
+
+    #[test]
+    fn planned_searches_fall_back_down_the_plan() {
+        let pattern = "*ing";
+        let full = segment(&[]);
+        let (structure, _) = planned_search(&full, &Scope::Words, &SearchType::Wildcard, pattern).unwrap().unwrap();
+        assert_eq!(structure.describe(), "SuffixTree");
+
+        // Without the suffix tree the prefix trie checks every key
+        let no_suffix = segment(&[SearchType::Suffix]);
+        let (structure, mut ids) = planned_search(&no_suffix, &Scope::Words, &SearchType::Wildcard, pattern)
+            .unwrap()
+            .unwrap();
+        assert_eq!(structure.describe(), "Trie");
+        ids.sort_unstable();
+        assert_eq!(no_suffix.pool().resolve(&ids), ["sing", "ring", "string", "bring"]);
+
+        let nothing = segment(&[SearchType::Suffix, SearchType::Prefix]);
+        assert!(matches!(
+            planned_search(&nothing, &Scope::Words, &SearchType::Wildcard, pattern),
+            Err(Errors::Unsupported(_, SearchType::Wildcard))
+        ));
+        assert!(planned_search(&full, &Scope::Words, &SearchType::Prefix, "gr").unwrap().is_none());
+    }
+
+    // A trie that counts how many matches it hands out
+    struct Counted {
+        trie: Trie,
+        visited: Arc<AtomicUsize>,
+    }
+
+    impl SearchStructure for Counted {
+        fn search(&self, pool: &dyn TermLookup, query: &str) -> Result<Vec<TermId>, SearchError> {
+            SearchStructure::search(&self.trie, pool, query)
+        }
+
+        fn search_by_length(
+            &self,
+            _pool: &dyn TermLookup,
+            query: &str,
+            visit: &mut dyn FnMut(usize, TermId) -> ControlFlow<()>,
+        ) -> Result<(), SearchError> {
+            self.trie.search_by_length(query, &mut |extra, id| {
+                self.visited.fetch_add(1, Ordering::Relaxed);
+                visit(extra, id)
+            })
+        }
+
+        fn len(&self) -> usize {
+            self.trie.vocabulary_size()
+        }
+
+        fn for_each_key(&self, visit: &mut dyn FnMut(&str, &[TermId], KeyStats)) {
+            self.trie.for_each_key(visit);
+        }
+
+        fn stats(&self, key: &str) -> KeyStats {
+            self.trie.stats(key)
+        }
     }
 
-    Ok(result)
+    #[test]
+    fn ranked_search_stops_once_nothing_longer_can_make_the_cut() {
+        // MAX_RESULTS keys one character past the query, then longer ones
+        let words: Vec<String> = (0..MAX_RESULTS)
+            .map(|n| format!("w{}", char::from_u32(0x4E00 + n as u32).unwrap_or_default()))
+            .chain((0..MAX_RESULTS).map(|n| format!("w{:04}", n)))
+            .collect();
+        let mut pool = StringPool::new();
+        let mut trie = Trie::new();
+        for (line, word) in words.iter().enumerate() {
+            trie.store(word, pool.intern(word).unwrap(), line as u64);
+        }
+        let visited = Arc::new(AtomicUsize::new(0));
+        let mut segment = Segment::new("test", Arc::new(pool));
+        segment.insert(Prefix, Arc::new(Counted { trie, visited: visited.clone() }));
+        let mut index = SearchIndex::new();
+        index.push_segment(Scope::Words, segment);
+
+        let (sender, _receiver) = mpsc::channel();
+        let results = perform_search(&index, Scope::Words, Prefix, "w", sender).unwrap();
+        assert_eq!(results.len(), MAX_RESULTS);
+        assert!(results.iter().all(|result| result.priority == 1));
+        // The first longer key ends the walk
+        assert_eq!(visited.load(Ordering::Relaxed), MAX_RESULTS + 1);
+    }
 }
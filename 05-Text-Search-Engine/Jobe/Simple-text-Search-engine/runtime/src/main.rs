@@ -3,10 +3,12 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use std::{collections::HashMap, sync::mpsc::{channel, Receiver, Sender}};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::{
+    env,
     error::Error,
     io::{self, Stdout},
+    thread::{self, JoinHandle},
     time::{Duration, Instant},
 };
 use throbber_widgets_tui::Throbber;
@@ -19,13 +21,17 @@ use tui::{
     Frame, Terminal,
 };
 
-use runtime::{load_index, perform_search, AppMessage, Scope, SearchIndex, SearchType};  // Import from our lib
+use data_structs::document::Window;
+use runtime::{load_index, perform_search, AppMessage, Errors, Scope, SearchIndex, SearchResult, SearchType};  // Import from our lib
+
+// Lines shown before and after a hit when its context is opened
+const CONTEXT_LINES: usize = 2;
 
 struct App {
     input_scope: String,
     input_type: String,
     input_term: String,
-    results: Vec<String>,
+    results: Vec<SearchResult>,
     result_state: ListState,
     debug_state: ListState,
     state: AppState,
@@ -36,7 +42,8 @@ struct App {
     loading_start_time: Option<Instant>,
     sender: Sender<AppMessage>,
     receiver: Receiver<AppMessage>,
-    indexes: HashMap<String, SearchIndex>, // Add indexes to the App struct
+    search: Option<JoinHandle<Result<(), Errors>>>, // The running search, if any
+    indexes: SearchIndex, // Add indexes to the App struct
 }
 
 enum AppState {
@@ -47,7 +54,7 @@ enum AppState {
 }
 
 impl App {
-    fn new(indexes: HashMap<String, SearchIndex>) -> Self { // Accept indexes as a parameter
+    fn new(indexes: SearchIndex) -> Self { // Accept indexes as a parameter
         let (sender, receiver) = channel();
         Self {
             input_scope: String::new(),
@@ -72,6 +79,7 @@ impl App {
             loading_start_time: None,
             sender,
             receiver,
+            search: None,
             indexes, // Initialize indexes
         }
     }
@@ -101,9 +109,65 @@ impl App {
     }
 }
 
+// Where a line result sits, as `path:line` of its first location, which
+// editors open straight at the line
+fn source_label(indexes: &SearchIndex, result: &SearchResult) -> Option<String> {
+    let location = result.locations.first()?;
+    let path = indexes
+        .document(location.document)
+        .map_or_else(|| format!("document {}", location.document), |document| document.path.clone());
+    let more = match result.locations.len() {
+        1 => String::new(),
+        count => format!(" (+{} more)", count - 1),
+    };
+    Some(format!("  {}:{}{}", path, location.line, more))
+}
+
+// Lines around the first location of the selected result, into the debug
+// window, the hit marked with `>`
+fn show_context(app: &mut App) {
+    let Some(result) = app.result_state.selected().and_then(|selected| app.results.get(selected)) else {
+        return;
+    };
+    let Some(location) = result.locations.first().copied() else {
+        app.add_debug_message("Only line results have context".to_string());
+        return;
+    };
+    let window = Window::Lines {
+        before: CONTEXT_LINES,
+        after: CONTEXT_LINES,
+    };
+    match app.indexes.context(location.document, location.offset, window) {
+        Ok(context) => {
+            let first = location.line.saturating_sub(context.hit as u64);
+            for (index, line) in context.lines.iter().enumerate() {
+                let marker = if index == context.hit { '>' } else { ' ' };
+                app.add_debug_message(format!("{} {:>5} | {}", marker, first + index as u64, line));
+            }
+        }
+        Err(error) => app.add_debug_message(format!("No context: {}", error)),
+    }
+}
+
+// Reads a `--fuzzy-distance 1` style flag into the index
+fn apply_options(indexes: &mut SearchIndex) {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fuzzy-distance" => {
+                if let Some(distance) = args.next().and_then(|d| d.trim().parse().ok()) {
+                    indexes.set_fuzzy_distance(distance);
+                }
+            }
+            _ => eprintln!("Ignoring unknown argument - {}", arg),
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let start_time = Instant::now();
-    let indexes = load_index().unwrap(); // Load indexes before starting the TUI
+    let mut indexes = load_index()?; // Load indexes before starting the TUI
+    apply_options(&mut indexes);
     let duration = start_time.elapsed();
     println!("time took to load all indexes {:?}",duration);
     let mut terminal = setup_terminal()?;
@@ -130,7 +194,7 @@ fn restore_terminal(
     Ok(())
 }
 
-fn run_app(terminal: &mut Terminal<CrosstermBackend<Stdout>>, indexes: HashMap<String, SearchIndex>) -> Result<(), Box<dyn Error>> {
+fn run_app(terminal: &mut Terminal<CrosstermBackend<Stdout>>, indexes: SearchIndex) -> Result<(), Box<dyn Error>> {
     let mut app = App::new(indexes); // Pass indexes to the App
     app.add_debug_message("Application started".to_string());
 
@@ -145,7 +209,7 @@ fn run_app(terminal: &mut Terminal<CrosstermBackend<Stdout>>, indexes: HashMap<S
         if let Ok(message) = app.receiver.try_recv() {
             match message {
                 AppMessage::SearchComplete(results, duration) => {
-                    app.results = results.into_iter().map(|(_, s)| s).collect();
+                    app.results = results;
                     app.is_loading = false;
                     app.loading_start_time = None;
                     app.result_state.select(Some(0));
@@ -154,11 +218,26 @@ fn run_app(terminal: &mut Terminal<CrosstermBackend<Stdout>>, indexes: HashMap<S
                         duration
                     ));
                 }
+                AppMessage::SearchFailed(error) => {
+                    app.is_loading = false;
+                    app.loading_start_time = None;
+                    app.status_message = Some(error.to_string());
+                    app.add_debug_message(format!("Search failed: {}", error));
+                }
                 AppMessage::Debug(message) => {
                     app.add_debug_message(message);
                 }
             }
         }
+        // A search that died never sends its results, so reap it here
+        if let Some(search) = app.search.take_if(|search| search.is_finished()) {
+            if let Err(error) = search.join().unwrap_or(Err(Errors::SearchPanicked)) {
+                app.is_loading = false;
+                app.loading_start_time = None;
+                app.status_message = Some(error.to_string());
+                app.add_debug_message(format!("Search failed: {}", error));
+            }
+        }
         if event::poll(Duration::from_millis(100))? {
             if let Event::Key(key) = event::read()? {
                 match key.code {
@@ -199,6 +278,7 @@ fn run_app(terminal: &mut Terminal<CrosstermBackend<Stdout>>, indexes: HashMap<S
                                 app.add_debug_message(format!("Selected result #{}", prev + 1));
                             }
                         }
+                        KeyCode::Enter => show_context(&mut app),
                         _ => {}
                     },
                 }
@@ -269,7 +349,7 @@ fn ui(frame: &mut Frame, app: &mut App) {
     // Type input
     let type_block = Block::default()
         .borders(Borders::ALL)
-        .title("Search Type (1: Prefix, 2: Suffix, 3: Contains)")
+        .title("Search Type (1: Prefix, 2: Suffix, 3: Contains, 4: Fuzzy, 5: Wildcard, 6: Regex)")
         .style(match app.state {
             AppState::TypeInput => Style::default().fg(Color::Green),
             _ => Style::default(),
@@ -321,13 +401,14 @@ fn ui(frame: &mut Frame, app: &mut App) {
                     .results
                     .iter()
                     .enumerate()
-                    .map(|(i, term)| {
+                    .map(|(i, result)| {
+                        let term = result.text.as_str();
                         let prefix = format!("#{} -> ", i + 1);
                         let term_lower = term.to_lowercase();
                         let search_term = app.input_term.trim().to_lowercase();
                         let is_selected = app.result_state.selected() == Some(i);
 
-                        let line = if is_selected {
+                        let mut line = if is_selected {
                             if let Some(start_idx) = term_lower.find(&search_term) {
                                 Line::from(vec![
                                     Span::styled(prefix, Style::default().fg(Color::Green)),
@@ -357,6 +438,9 @@ fn ui(frame: &mut Frame, app: &mut App) {
                             ])
                         };
 
+                        if let Some(source) = source_label(&app.indexes, result) {
+                            line.spans.push(Span::styled(source, Style::default().fg(Color::DarkGray)));
+                        }
                         ListItem::new(line)
                     })
                     .collect();
@@ -365,7 +449,7 @@ fn ui(frame: &mut Frame, app: &mut App) {
                     .block(
                         Block::default()
                             .borders(Borders::ALL)
-                            .title("Results")
+                            .title("Results (Enter: show context)")
                             .border_style(Style::default().fg(Color::Green))
                             .padding(Padding::new(1, 0, 0, 0)),
                     )
@@ -378,7 +462,7 @@ fn ui(frame: &mut Frame, app: &mut App) {
             let help_text = match app.state {
                 AppState::ScopeInput => "Enter 1 for Words or 2 for Lines, then press Enter",
                 AppState::TypeInput => {
-                    "Enter 1 for Prefix, 2 for Suffix, or 3 for Contains, then press Enter"
+                    "Enter 1 for Prefix, 2 for Suffix, 3 for Contains, 4 for Fuzzy, 5 for Wildcard (*, ?, [ae] and (u) in words, * and ** for whole words in lines), or 6 for Regex, then press Enter"
                 }
                 AppState::TermInput => "Enter your search term and press Enter",
                 _ => "",
@@ -443,6 +527,9 @@ fn handle_type_input(app: &mut App, key: KeyEvent) {
             if app.input_type.trim() == "1"
                 || app.input_type.trim() == "2"
                 || app.input_type.trim() == "3"
+                || app.input_type.trim() == "4"
+                || app.input_type.trim() == "5"
+                || app.input_type.trim() == "6"
             {
                 app.add_debug_message(format!(
                     "Search type set to: {}",
@@ -450,8 +537,14 @@ fn handle_type_input(app: &mut App, key: KeyEvent) {
                         "Prefix"
                     } else if app.input_type.trim() == "2" {
                         "Suffix"
-                    } else {
+                    } else if app.input_type.trim() == "3" {
                         "Contains"
+                    } else if app.input_type.trim() == "4" {
+                        "Fuzzy"
+                    } else if app.input_type.trim() == "5" {
+                        "Wildcard"
+                    } else {
+                        "Regex"
                     }
                 ));
                 app.state = AppState::TermInput;
@@ -492,9 +585,20 @@ fn handle_term_input(app: &mut App, key: KeyEvent) {
                     "1" => SearchType::Prefix,
                     "2" => SearchType::Suffix,
                     "3" => SearchType::Contains,
+                    "4" => SearchType::Fuzzy,
+                    "5" => SearchType::Wildcard,
+                    "6" => SearchType::Regex,
                     _ => return,
                 };
 
+                // Pick up segments `compile` added or merged since the last search
+                if app.indexes.is_stale() {
+                    match app.indexes.reload() {
+                        Ok(()) => app.add_debug_message("Reloaded the index segments".to_string()),
+                        Err(error) => app.add_debug_message(format!("Keeping the loaded segments: {}", error)),
+                    }
+                }
+
                 // Clone all necessary data
                 let term = app.input_term.trim().to_string();
                 let scope_clone = scope;
@@ -505,14 +609,16 @@ fn handle_term_input(app: &mut App, key: KeyEvent) {
                 let start_time = Instant::now();
 
                 // Perform search in a separate thread
-                std::thread::spawn(move || {
+                app.search = Some(thread::spawn(move || {
                     let results =
                         perform_search(&indexes, scope_clone, search_type_clone, &term, debug_sender);
                     let duration = start_time.elapsed();
-                    app_sender
-                        .send(AppMessage::SearchComplete(results, duration))
-                        .unwrap();
-                });
+                    let message = match results {
+                        Ok(results) => AppMessage::SearchComplete(results, duration),
+                        Err(error) => AppMessage::SearchFailed(error),
+                    };
+                    app_sender.send(message).map_err(|_| Errors::Disconnected)
+                }));
             }
         }
         KeyCode::Char(c) => {